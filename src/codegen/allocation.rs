@@ -3,11 +3,47 @@
 // Node, Edge
 
 use indexmap::{IndexMap, IndexSet};
-use crate::{intermediate::frame::{Frame}, optimizer::liveness::{InterferenceGraph, Variable}};
+use crate::{codegen::codegen::WINDOWS_REGISTERS, intermediate::{frame::Frame, instruction::Instruction, irgen::Operand}, optimizer::liveness::{Clobbers, InterferenceGraph, Variable}};
 
 
 static REG_COUNT: usize = 12;
 
+// registers a call may overwrite under System V, among the allocatable ones
+const CALLER_SAVED: [&str; 8] = ["%r10", "%r11", "%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+
+// registers an instruction overwrites as a side effect
+pub fn clobbered_registers(inst: &Instruction) -> Clobbers {
+    let (registers, late_uses): (&'static [&'static str], Vec<Operand>) = match inst {
+        Instruction::LCall(_) => (&CALLER_SAVED, Vec::new()),
+        // the dividend is extended into %rdx before the divisor is read,
+        // a constant divisor is first moved to %rcx
+        Instruction::Div { arg2, .. } | Instruction::Mod { arg2, .. } => {
+            match arg2 {
+                Operand::Const(_) => (&["%rdx", "%rcx"], Vec::new()),
+                _ => (&["%rdx"], vec![arg2.clone()]),
+            }
+        },
+        // the first truth value waits in %ecx while the second operand is tested
+        Instruction::LogicalAnd { arg2, .. } | Instruction::LogicalOr { arg2, .. } => {
+            (&["%rcx"], vec![arg2.clone()])
+        },
+        // a variable shift count goes in %cl
+        Instruction::ShiftLeft { arg2, .. } | Instruction::ShiftRight { arg2, .. } => {
+            match arg2 {
+                Operand::Const(_) => (&[], Vec::new()),
+                _ => (&["%rcx"], Vec::new()),
+            }
+        },
+        _ => (&[], Vec::new()),
+    };
+    Clobbers { registers, late_uses }
+}
+
+fn register_index(name: &str) -> usize {
+    WINDOWS_REGISTERS.iter().position(|r| *r == name)
+        .unwrap_or_else(|| panic!("ALLOCATOR: unknown register '{name}'"))
+}
+
 pub struct Allocator {
     register_count: usize,
     pub ifr_graph: InterferenceGraph,
@@ -39,72 +75,76 @@ impl Allocator {
         let mut offset = 0;
         let reg_count = self.register_count;
         
-        // pre-coloring
-        let mut pre_color = 6;
-        for var in &self.frame.params {
-            if self.variables().contains_key(&var.name) { 
-                let v = self.ifr_graph.variables.get_mut(&var.name).unwrap();
-                v.register_id = pre_color;
-                pre_color += 1;
+        // pre-coloring: machine registers keep their color and stay in the graph
+        for var in self.ifr_graph.variables.values_mut() {
+            if var.precolored {
+                var.register_id = register_index(&var.name);
             }
         }
 
+        // params are all live on entry, where nothing defines them,
+        // so the edges between them are added here
+        let params: Vec<String> = self.frame.params.iter()
+            .map(|p| p.name.clone())
+            .filter(|name| self.variables().contains_key(name))
+            .collect();
+        for a in &params {
+            for b in &params {
+                if a != b {
+                    self.ifr_graph.edges.entry(a.clone()).or_default().insert(b.clone());
+                }
+            }
+        }
 
-        while !self.variables().is_empty() {
+        // simplify
+        loop {
             let mut to_remove: Option<String> = None;
+            let mut candidate: Option<(String, f64)> = None;
             for (name, var) in self.ifr_graph.variables.iter() {
-                let edges_len = self.edges()[name].len();
-                
-                if edges_len < reg_count {
+                if var.precolored {
+                    continue;
+                }
+                let degree = self.edges()[name].len();
+                if degree < reg_count {
                     to_remove = Some(name.clone());
-                    stack.push((var.clone(), self.edges()[name].clone(), false));
                     break;
-                }  
+                }
+                let cost = self.ifr_graph.spill_costs.get(name).copied().unwrap_or(0) as f64 / degree as f64;
+                if candidate.as_ref().is_none_or(|(_, c)| cost <= *c) {
+                    candidate = Some((name.clone(), cost));
+                }
             }
 
-            if let Some(name) = to_remove {
-                self.remove_var(name);
-                
-                continue;
-            }
-            
-            let (name, var) = self.variables().iter().last().unwrap();
-            stack.push((var.clone(), self.edges()[name].clone(), true));
-            let name = name.clone();
+            // when every node is significant, the cheapest to spill for the
+            // edges it frees becomes a spill candidate, it is only spilled if
+            // no color is left for it
+            let Some(name) = to_remove.or(candidate.map(|(name, _)| name)) else { break };
+            let var = self.variables()[&name].clone();
+            stack.push((var, self.edges()[&name].clone()));
             self.remove_var(name);
         }
         self.ifr_graph.edges = IndexMap::new();
-        // coloring
-        while !stack.is_empty() {
-            let (mut var, edges, is_spilled) = stack.pop().expect("invalid pop stack on coloring");
-            if is_spilled {
-                var.spilled = true;
-                self.spill.insert(var.name.clone(), offset);
-                offset -= 8;
-            } else {
-                let name = var.name.clone();
-                let mut reg_id = 0;
-                self.add_var(var.clone(), edges.clone());
-                if self.variables().get(&name).unwrap().register_id != 0 {
-                    continue;
-                }
 
-                let used_colors: IndexSet<usize> = self.edges()[&name]
+        // select
+        while !stack.is_empty() {
+            let (mut var, edges) = stack.pop().expect("invalid pop stack on coloring");
+            let used_colors: IndexSet<usize> = edges
                 .iter()
                 .filter_map(|other| self.variables().get(other))
+                .filter(|v| !v.spilled)
                 .map(|v| v.register_id)
                 .collect();
-            
-                while used_colors.contains(&reg_id) {
-                    reg_id += 1;
-                }
-                self.ifr_graph.variables.get_mut(&name).unwrap().register_id = reg_id;
-
+            match (0..reg_count).find(|id| !used_colors.contains(id)) {
+                Some(reg_id) => var.register_id = reg_id,
+                None => {
+                    // slots start below the saved %rbp
+                    offset -= 8;
+                    var.spilled = true;
+                    self.spill.insert(var.name.clone(), offset);
+                },
             }
+            self.add_var(var, edges);
         }
-
-
-        
     }
 
     fn add_var(&mut self, var: Variable, edges: IndexSet<String>) {
@@ -131,4 +171,93 @@ impl Allocator {
     }
 
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{intermediate::frame::new_frame, optimizer::{cfg::function_cfg, liveness::new_liveness_analyzer}, parser::{node::ConstValue, token::Type}};
+
+    use super::*;
+
+    // k0..k5, a, b and c are all live across a variable division, a
+    // remainder by a constant and a variable shift, which leaves only just
+    // enough registers
+    fn allocate(clobbers: fn(&Instruction) -> Clobbers) -> (Vec<Instruction>, Vec<IndexSet<String>>, Allocator) {
+        let var = |name: &str| Operand::Var(name.to_string(), Type::Long);
+        let keep: Vec<String> = (0..4).map(|i| format!("k{i}")).collect();
+        let mut instructions = vec![Instruction::Label("f".to_string()), Instruction::BeginFunc(0)];
+        for (i, name) in keep.iter().map(|s| s.as_str()).chain(["a", "b", "c"]).enumerate() {
+            instructions.push(Instruction::Assign { dest: var(name), arg1: Operand::Const(ConstValue::Int(i as i32 + 2)) });
+        }
+        instructions.extend([
+            Instruction::Div { dest: var("q"), arg1: var("a"), arg2: var("b") },
+            Instruction::Mod { dest: var("r"), arg1: var("a"), arg2: Operand::Const(ConstValue::Int(3)) },
+            Instruction::ShiftLeft { dest: var("s"), arg1: var("a"), arg2: var("c") },
+            Instruction::Assign { dest: var("e"), arg1: Operand::Const(ConstValue::Int(0)) },
+            Instruction::LogicalAnd { dest: var("l"), arg1: var("a"), arg2: var("e") },
+            Instruction::Add { dest: var("t"), arg1: var("q"), arg2: var("r") },
+            Instruction::Add { dest: var("t"), arg1: var("t"), arg2: var("s") },
+            Instruction::Add { dest: var("t"), arg1: var("t"), arg2: var("b") },
+            Instruction::Add { dest: var("t"), arg1: var("t"), arg2: var("c") },
+            Instruction::Add { dest: var("t"), arg1: var("t"), arg2: var("l") },
+        ]);
+        for name in &keep {
+            instructions.push(Instruction::Add { dest: var("t"), arg1: var("t"), arg2: var(name) });
+        }
+        instructions.extend([Instruction::Return { dest: var("t") }, Instruction::EndFunc]);
+
+        let cfg = function_cfg(&instructions);
+        let mut analyzer = new_liveness_analyzer(instructions.clone(), cfg.blocks);
+        analyzer.gen_live_out();
+        analyzer.gen_inst_live_out();
+        analyzer.create_interference_graph(clobbers);
+        let live_out = (0..instructions.len()).map(|i| analyzer.live_out(i).clone()).collect();
+
+        let mut frame = new_frame("f".to_string(), Type::Long);
+        frame.range = (0, instructions.len() - 1);
+        let mut allocator = new_allocator(analyzer.interference_graph, frame);
+        allocator.coloring();
+        (instructions, live_out, allocator)
+    }
+
+    fn register<'a>(allocator: &'a Allocator, name: &str) -> Option<&'a str> {
+        let var = &allocator.ifr_graph.variables[name];
+        (!var.spilled).then(|| WINDOWS_REGISTERS[var.register_id])
+    }
+
+    #[test]
+    fn clobbered_registers_stay_free() {
+        let (instructions, live_out, allocator) = allocate(clobbered_registers);
+        let edges = &allocator.ifr_graph.edges;
+        assert!(edges["%rdx"].contains("b"), "the divisor is read after %rdx is written");
+        assert!(edges["%rcx"].contains("e"), "the second operand is tested after %ecx is written");
+        assert!(edges["%rdx"].contains("k0") && edges["%rcx"].contains("k0"));
+        assert!(allocator.spill.is_empty(), "spilled: {:?}", allocator.spill);
+
+        for (i, inst) in instructions.iter().enumerate() {
+            let def = inst.def().map(|op| op.print());
+            let clobbers = clobbered_registers(inst);
+            let across = live_out[i].iter().filter(|name| Some(*name) != def.as_ref());
+            let late = clobbers.late_uses.iter().map(|op| op.print());
+            for name in across.cloned().chain(late) {
+                let reg = register(&allocator, &name);
+                assert!(reg.is_none_or(|reg| !clobbers.registers.contains(&reg)),
+                    "'{}' in {:?} across '{}'", name, reg, inst.print());
+            }
+        }
+        assert_ne!(register(&allocator, "b"), Some("%rdx"));
+        assert_ne!(register(&allocator, "c"), Some("%rdx"));
+        assert_ne!(register(&allocator, "e"), Some("%rcx"));
+    }
+
+    #[test]
+    fn unclobbered_registers_are_handed_out() {
+        // the same function without the fixed registers uses them for values
+        // live across the division, the case above is not vacuous
+        let (_, _, allocator) = allocate(|_| Clobbers { registers: &[], late_uses: Vec::new() });
+        let used: Vec<&str> = allocator.ifr_graph.variables.keys()
+            .filter_map(|name| register(&allocator, name))
+            .collect();
+        assert!(used.contains(&"%rdx") && used.contains(&"%rcx"), "used: {:?}", used);
+    }
+}
@@ -1,6 +1,6 @@
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
 
-use crate::{intermediate::{frame::{Frame}, instruction::Instruction, irgen::Operand}, optimizer::liveness::Variable};
+use crate::{codegen::condition::{CmpClass, CondCode, Condition, Relation, condition}, intermediate::{analyzer::Signature, frame::{Frame, ParamLocation}, instruction::Instruction, irgen::{Operand, Value, const_value}}, optimizer::liveness::Variable, parser::{node::ConstValue, token::Type}};
 
 
 // para cada instrução:
@@ -13,10 +13,23 @@ use crate::{intermediate::{frame::{Frame}, instruction::Instruction, irgen::Oper
 // addq %r11, %rax
 // movq %rax, (endereço de t0) 
 
-const WINDOWS_REGISTERS: [&str; 12] = [
+pub const WINDOWS_REGISTERS: [&str; 12] = [
     "%r10", "%r11", "%r12", "%r13", "%r14", "%r15", "%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9",
 ];
 
+// registers the callee must preserve under System V
+const CALLEE_SAVED: [&str; 5] = ["%rbx", "%r12", "%r13", "%r14", "%r15"];
+// bytes below %rsp a leaf function may use without moving it
+const RED_ZONE_SIZE: i32 = 128;
+
+// System V argument registers, in order
+const ARG_REGISTERS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+const SSE_ARG_COUNT: usize = 8;
+
+// register save area used by va_start: 6 gp registers + 8 xmm registers
+const REG_SAVE_SIZE: i32 = 6 * 8 + 8 * 16;
+const VA_LIST_SIZE: i32 = 24;
+
 
 pub struct AsmGenerator {
     curr: usize,
@@ -24,31 +37,41 @@ pub struct AsmGenerator {
     frame: Frame,
     variables: IndexMap<String, Variable>,
     spill: IndexMap<String, i32>,
+    // the names live after each instruction, from the allocator's liveness
+    live_out: Vec<IndexSet<String>>,
     reg_names: Vec<String>,    
     pub assembly: Vec<AsmInstruction>,
-    param_counter: usize,
-    saved_regs: Vec<Location>,
+    signatures: IndexMap<String, Signature>,
+    params: Vec<(Location, Type)>,
+    callee_saved: Vec<(String, i32)>,
+    va_count: usize,
 }
 
 
 pub fn new_asm_generator(instructions: Vec<Instruction>, frame: Frame, 
-    variables: IndexMap<String, Variable>, spill: IndexMap<String, i32>) -> AsmGenerator {
+    variables: IndexMap<String, Variable>, spill: IndexMap<String, i32>,
+    live_out: Vec<IndexSet<String>>, signatures: IndexMap<String, Signature>) -> AsmGenerator {
         let reg_names = WINDOWS_REGISTERS
         .iter().map(|&s| s.to_string()).collect();
         AsmGenerator { 
             curr: 0, 
             instructions, frame, 
             variables, spill, 
+            live_out,
             reg_names,
             assembly: Vec::new(),
-            param_counter: 0,
-            saved_regs: Vec::new(),
+            signatures,
+            params: Vec::new(),
+            callee_saved: Vec::new(),
+            va_count: 0,
     }
 }
 #[derive(Clone)]
 pub enum Location {
     Reg(String),
     Stack(i32),
+    Mem(i32, String),
+    Label(String),
     Imm(String),
 }
 impl Location {
@@ -61,47 +84,133 @@ impl Location {
             Location::Stack(offset) => {
                 format!("{offset}(%rbp)")
             }
+            Location::Mem(offset, reg) => {
+                format!("{offset}({reg})")
+            }
+            Location::Label(l) => {
+                format!("{l}(%rip)")
+            }
+        }
+    }
+    pub fn sized(&self, size: Size) -> String {
+        match self {
+            Location::Reg(name) => sub_register(name, size),
+            _ => self.to_string(),
         }
     }
 }
 
-pub enum AsmInstruction {
-    Ret,
-    Label(String),
+// operand size of an instruction, the b/w/l/q suffix
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Size {
+    Byte,
+    Word,
+    Long,
+    Quad,
+}
 
-    Jmp(String),
-    Je(String),
-    Jne(String),
-    Jl(String),
-    Jle(String),
-    Ja(String),
-    Jae(String),
+impl Size {
+    pub fn of(ty: Type) -> Size {
+        match ty.size() {
+            1 => Size::Byte,
+            2 => Size::Word,
+            4 => Size::Long,
+            _ => Size::Quad,
+        }
+    }
 
-    Or(Location, Location),
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Size::Byte => "b",
+            Size::Word => "w",
+            Size::Long => "l",
+            Size::Quad => "q",
+        }
+    }
+}
 
-    SetE(Location),
-    SetNE(Location),
-    SetL(Location),
-    SetLE(Location),
-    SetG(Location),
-    SetGE(Location),
+// the part of a 64-bit register that holds `size` bytes, other names are kept
+fn sub_register(name: &str, size: Size) -> String {
+    let Some(base) = name.strip_prefix("%r") else {
+        return name.to_string();
+    };
+    if base.starts_with(|c: char| c.is_ascii_digit()) {
+        return match size {
+            Size::Byte => format!("{name}b"),
+            Size::Word => format!("{name}w"),
+            Size::Long => format!("{name}d"),
+            Size::Quad => name.to_string(),
+        };
+    }
+    match (base, size) {
+        (_, Size::Quad) => name.to_string(),
+        ("ax" | "bx" | "cx" | "dx", Size::Byte) => format!("%{}l", &base[..1]),
+        (_, Size::Byte) => format!("%{base}l"),
+        (_, Size::Word) => format!("%{base}"),
+        (_, Size::Long) => format!("%e{base}"),
+    }
+}
 
-    MovZbl(Location, Location),
-    Mov(Location, Location),
+pub enum AsmInstruction {
+    Ret,
+    Label(String),
 
-    Cmp(Location, Location),
-    Add(Location, Location),
-    Sub(Location, Location),
-    Mul(Location, Location),
-    Div(Location),
-    Cqo,
+    Jmp(String),
+    Jcc(CondCode, String),
+
+    Or(Size, Location, Location),
+    And(Size, Location, Location),
+    Xor(Size, Location, Location),
+    Neg(Size, Location),
+    Not(Size, Location),
+    // complements the bit at the given index
+    Btc(Size, Location, Location),
+
+    SetCC(CondCode, Location),
+
+    // zero and sign extension, from the first size to the second
+    MovZx(Size, Size, Location, Location),
+    MovSx(Size, Size, Location, Location),
+    MovD(Location, Location),
+    Mov(Size, Location, Location),
+    Lea(Location, Location),
+
+    Cvtsi2sd(Location, Location),
+    Cvtsi2ss(Location, Location),
+    Cvttsd2si(Location, Location),
+    Cvttss2si(Location, Location),
+    Cvtss2sd(Location, Location),
+    Cvtsd2ss(Location, Location),
+
+    Cmp(Size, Location, Location),
+    Ucomisd(Location, Location),
+    Ucomiss(Location, Location),
+    Addsd(Location, Location),
+    Addss(Location, Location),
+    Subsd(Location, Location),
+    Subss(Location, Location),
+    Mulsd(Location, Location),
+    Mulss(Location, Location),
+    Divsd(Location, Location),
+    Divss(Location, Location),
+    Add(Size, Location, Location),
+    Sub(Size, Location, Location),
+    Mul(Size, Location, Location),
+    Div(Size, Location),
+    UDiv(Size, Location),
+    // the whole product of %rax and the operand in %rdx:%rax
+    MulHigh(Size, Location),
+    UMulHigh(Size, Location),
+    Cqo(Size),
+    // the count is an immediate or %cl
+    Shl(Size, Location, Location),
+    Sar(Size, Location, Location),
+    Shr(Size, Location, Location),
 
 
     Push(Location),
     Pop(Location),
     Call(String),
-
-    Comment(String),
 }
 
 
@@ -155,291 +264,820 @@ impl AsmGenerator {
                 Instruction::Goto(l) => {
                     self.emit(AsmInstruction::Jmp(l.clone()));
                 },
-                Instruction::CallStart(ops) => {
-                    for op in ops {
-                        let oper = self.operand_to_reg(op);
-                        self.saved_regs.push(oper.clone());
-                        self.emit(AsmInstruction::Push(oper));
-                    }
-                }
-                Instruction::PushParam(op) => {
+                Instruction::PushParam(op, ty) => {
                     let op = self.operand_to_reg(op);
-                    let dest_str = WINDOWS_REGISTERS[6 + self.param_counter].to_string();
-                    self.emit(AsmInstruction::Mov(op, Location::Reg(dest_str.clone())));
-                    
-                }
-                Instruction::PopParams(_) => {
-                    for i in (0..self.saved_regs.len()).rev() {
-                        self.emit(AsmInstruction::Pop(self.saved_regs[i].clone()));
-                    }
-                    self.saved_regs.clear();
-                }
-                Instruction::LCall(l) => {
-                    self.emit(AsmInstruction::Call(l.clone()));
+                    self.params.push((op, ty));
                 }
+                // the stack arguments are released right after the call
+                Instruction::PopParams(_) => (),
+                Instruction::LCall(l) => match self.tail_call_end(&l) {
+                    Some(end) => {
+                        self.gen_call(l, true);
+                        self.curr = end;
+                    },
+                    None => self.gen_call(l, false),
+                },
                 Instruction::Return { dest } => {
+                    let size = Size::of(self.frame.ret_type);
                     let dest = self.operand_to_reg(dest);
-                    self.emit(AsmInstruction::Mov(dest, rax.clone()));
+                    self.emit(AsmInstruction::Mov(size, dest, rax.clone()));
+                    if self.frame.ret_type.is_floating() {
+                        self.emit(AsmInstruction::Mov(Size::Quad, rax.clone(), Location::Reg("%xmm0".to_string())));
+                    }
                     self.emit(AsmInstruction::Jmp(end_label.clone()));
                 },
                 Instruction::BeginFunc(_) => {
                     self.emit(AsmInstruction::Push(Location::Reg("%rbp".to_string())));
                     
-                    self.emit(AsmInstruction::Mov(Location::Reg("%rsp".to_string()), 
+                    self.emit(AsmInstruction::Mov(Size::Quad, Location::Reg("%rsp".to_string()), 
                     Location::Reg("%rbp".to_string())));
 
-                    let stack_space = self.spill.len() * 8;
-                    if stack_space > 0 {
-                        self.emit(AsmInstruction::Sub(Location::Imm(format!("{stack_space}")),
+                    let stack_space = self.layout_frame();
+                    if stack_space > 0 && !self.uses_red_zone(stack_space) {
+                        self.emit(AsmInstruction::Sub(Size::Quad, Location::Imm(format!("{stack_space}")),
                         Location::Reg("%rsp".to_string())));
                     }
+                    for (reg, offset) in self.callee_saved.clone() {
+                        self.emit(AsmInstruction::Mov(Size::Quad, Location::Reg(reg), Location::Stack(offset)));
+                    }
+                    if self.frame.variadic {
+                        self.save_arg_registers();
+                    }
+                    self.load_params();
                 },
                 Instruction::EndFunc => {
                     self.emit(AsmInstruction::Label(end_label.clone()));
-                    self.emit(AsmInstruction::Mov(Location::Reg("%rbp".to_string()), 
-                    Location::Reg("%rsp".to_string())));
-                    self.emit(AsmInstruction::Pop(Location::Reg("%rbp".to_string())));
+                    self.gen_epilogue();
                     self.emit(AsmInstruction::Ret);
 
                 },
                 Instruction::Assign { dest, arg1 } => {
+                    let size = self.size_of(&dest);
                     let src = self.operand_to_reg(arg1);
                     let dest = self.operand_to_reg(dest);
-                    if let Location::Stack(_) = src && let Location::Stack(_) = dest {
-                        self.emit(AsmInstruction::Mov(src, Location::Reg("%rax".to_string())));
-                        self.emit(AsmInstruction::Mov(Location::Reg("%rax".to_string()), dest));
+                    // memory to memory and 64-bit immediates to memory go through %rax
+                    if !matches!(src, Location::Reg(_)) && let Location::Stack(_) = dest {
+                        self.emit(AsmInstruction::Mov(size, src, Location::Reg("%rax".to_string())));
+                        self.emit(AsmInstruction::Mov(size, Location::Reg("%rax".to_string()), dest));
                     } else {
-                        self.emit(AsmInstruction::Mov(src, dest));
+                        self.emit(AsmInstruction::Mov(size, src, dest));
                     }
                     
 
                 },
-                Instruction::LogicalOr { dest, arg1, arg2 } => {
-                        let a = self.operand_to_reg(arg1);
-                        let b = self.operand_to_reg(arg2);
-                        let dest = self.operand_to_reg(dest);
-                        self.emit(AsmInstruction::Mov(a, rax.clone()));
-                        self.emit(AsmInstruction::Or(b, rax.clone()));
-
-                    if let Instruction::IfZero { label, ..} = self.peek() {
-                        self.emit(AsmInstruction::Je(label.clone()));
-                        self.next_instruction();
+                Instruction::IfZero { cond, label } => {
+                    let cond = self.gen_test(cond);
+                    self.jump_unless(cond, label);
+                },
+                // both operands are evaluated, the truth value of the first
+                // waits in %ecx while the second is tested
+                Instruction::LogicalOr { dest, arg1, arg2 } |
+                Instruction::LogicalAnd { dest, arg1, arg2 } => {
+                    let rcx = Location::Reg("%rcx".to_string());
+                    let is_or = matches!(self.curr_instruction(), Instruction::LogicalOr { .. });
+
+                    let cond = self.gen_test(arg1);
+                    self.set_condition(cond);
+                    self.emit(AsmInstruction::Mov(Size::Long, rax.clone(), rcx.clone()));
+                    let cond = self.gen_test(arg2);
+                    self.set_condition(cond);
+                    if is_or {
+                        self.emit(AsmInstruction::Or(Size::Long, rcx, rax.clone()));
                     } else {
-                        let al = Location::Reg("%al".to_string());
-                        self.emit(AsmInstruction::SetE(al.clone()));
-                        self.emit(AsmInstruction::MovZbl(al, Location::Reg("%eax".to_string())));
-                        self.emit(AsmInstruction::Mov(rax.clone(), dest));
+                        self.emit(AsmInstruction::And(Size::Long, rcx, rax.clone()));
                     }
+                    self.emit(AsmInstruction::Cmp(Size::Long, Location::Imm("0".to_string()), rax.clone()));
+                    self.branch_or_set(condition(Relation::NotEqual, CmpClass::Signed), dest);
                 },
-                Instruction::Equal { dest, arg1, arg2 } => {
+                Instruction::Equal { dest, arg1, arg2 } | Instruction::NotEqual { dest, arg1, arg2 } |
+                Instruction::Greater { dest, arg1, arg2 } | Instruction::GreaterEqual { dest, arg1, arg2 } |
+                Instruction::Less { dest, arg1, arg2 } | Instruction::LessEqual { dest, arg1, arg2 } => {
+                    let rel = relation(self.curr_instruction());
+                    let ty = arg1.binary_type(&arg2);
                     let a = self.operand_to_reg(arg1);
                     let b = self.operand_to_reg(arg2);
-                    let dest = self.operand_to_reg(dest);
-
-                    self.emit(AsmInstruction::Mov(a, rax.clone()));
-                    self.emit(AsmInstruction::Cmp(b, rax.clone())); 
-
-                    if let Instruction::IfZero{ label, ..} = self.peek() {
-                        self.emit(AsmInstruction::Jne(label.clone()));
-                        self.next_instruction();
+                    let cond = self.gen_comparison(rel, a, b, ty);
+                    self.branch_or_set(cond, dest);
+                },
+                Instruction::Not { dest, arg1 } => {
+                    let ty = arg1.ty();
+                    let a = self.operand_to_reg(arg1);
+                    let cond = self.gen_comparison(Relation::Equal, a, Location::Imm("0".to_string()), ty);
+                    self.branch_or_set(cond, dest);
+                },
+                Instruction::Neg { dest, arg1 } => {
+                    let ty = dest.ty();
+                    let a = self.operand_to_reg(arg1);
+                    if ty.is_floating() {
+                        // flip the sign bit
+                        let size = Size::of(ty);
+                        let sign = (8 * ty.size() - 1).to_string();
+                        self.emit(AsmInstruction::Mov(size, a, rax.clone()));
+                        self.emit(AsmInstruction::Btc(size, Location::Imm(sign), rax.clone()));
                     } else {
-                        let al = Location::Reg("%al".to_string());
-                        self.emit(AsmInstruction::SetE(al.clone()));
-                        self.emit(AsmInstruction::MovZbl(al, Location::Reg("%eax".to_string())));
-                        self.emit(AsmInstruction::Mov(rax.clone(), dest));
+                        let size = arith_size(ty);
+                        self.emit(AsmInstruction::Mov(size, a, rax.clone()));
+                        self.emit(AsmInstruction::Neg(size, rax.clone()));
                     }
+                    self.store_rax(dest);
                 },
-                Instruction::NotEqual { dest, arg1, arg2 } => {
+                Instruction::Complement { dest, arg1 } => {
+                    let size = arith_size(dest.ty());
+                    let a = self.operand_to_reg(arg1);
+                    self.emit(AsmInstruction::Mov(size, a, rax.clone()));
+                    self.emit(AsmInstruction::Not(size, rax.clone()));
+                    self.store_rax(dest);
+                },
+                Instruction::BitwiseAnd { dest, arg1, arg2 } |
+                Instruction::BitwiseOr { dest, arg1, arg2 } |
+                Instruction::BitwiseXor { dest, arg1, arg2 } => {
+                    self.integer_only(dest.ty());
+                    let size = arith_size(dest.ty());
                     let a = self.operand_to_reg(arg1);
                     let b = self.operand_to_reg(arg2);
-                    let dest = self.operand_to_reg(dest);
-
-                    self.emit(AsmInstruction::Mov(a, rax.clone()));
-                    self.emit(AsmInstruction::Cmp(b, rax.clone())); 
+                    self.emit(AsmInstruction::Mov(size, a, rax.clone()));
+                    let op = match self.curr_instruction() {
+                        Instruction::BitwiseAnd { .. } => AsmInstruction::And(size, b, rax.clone()),
+                        Instruction::BitwiseOr { .. } => AsmInstruction::Or(size, b, rax.clone()),
+                        _ => AsmInstruction::Xor(size, b, rax.clone()),
+                    };
+                    self.emit(op);
+                    self.store_rax(dest);
+                },
 
-                    if let Instruction::IfZero{ label, ..} = self.peek() {
-                        self.emit(AsmInstruction::Je(label.clone()));
-                        self.next_instruction();
-                    } else {
-                        let al = Location::Reg("%al".to_string());
-                        self.emit(AsmInstruction::SetNE(al.clone()));
-                        self.emit(AsmInstruction::MovZbl(al, Location::Reg("%eax".to_string())));
-                        self.emit(AsmInstruction::Mov(rax.clone(), dest));
-                    }
-                }
-                Instruction::Greater { dest, arg1, arg2 } => {
+                Instruction::Add { dest, arg1, arg2 } |
+                Instruction::Sub { dest, arg1, arg2 } |
+                Instruction::Mul { dest, arg1, arg2 } |
+                Instruction::Div { dest, arg1, arg2 } if dest.ty().is_floating() => {
                     let a = self.operand_to_reg(arg1);
                     let b = self.operand_to_reg(arg2);
-                    let dest = self.operand_to_reg(dest);
-
-                    self.emit(AsmInstruction::Mov(a, rax.clone()));
-                    self.emit(AsmInstruction::Cmp(b, rax.clone())); 
-
-                    if let Instruction::IfZero{ label, ..} = self.peek() {
-                        self.emit(AsmInstruction::Jle(label.clone()));
-                        self.next_instruction();
-                    } else {
-                        let al = Location::Reg("%al".to_string());
-                        self.emit(AsmInstruction::SetG(al.clone()));
-                        self.emit(AsmInstruction::MovZbl(al, Location::Reg("%eax".to_string())));
-                        self.emit(AsmInstruction::Mov(rax.clone(), dest));
-                    }
-
-                }
-                Instruction::GreaterEqual { dest, arg1, arg2 } => {
+                    self.gen_float_arith(a, b, dest.ty());
+                    self.store_rax(dest);
+                },
+                Instruction::Add { dest, arg1, arg2 } => {
+                    let size = arith_size(dest.ty());
                     let a = self.operand_to_reg(arg1);
                     let b = self.operand_to_reg(arg2);
-                    let dest = self.operand_to_reg(dest);
 
-                    self.emit(AsmInstruction::Mov(a, rax.clone()));
-                    self.emit(AsmInstruction::Cmp(b, rax.clone())); 
+                    self.emit(AsmInstruction::Mov(size, a, rax.clone()));
+                    self.emit(AsmInstruction::Add(size, b, rax.clone()));
+                    self.store_rax(dest);
+                },
+                Instruction::Sub { dest, arg1, arg2 } => {
+                    let size = arith_size(dest.ty());
+                    let a = self.operand_to_reg(arg1);
+                    let b = self.operand_to_reg(arg2);
 
-                    if let Instruction::IfZero{ label, ..} = self.peek() {
-                        self.emit(AsmInstruction::Jl(label.clone()));
-                        self.next_instruction();
-                    } else {
-                        let al = Location::Reg("%al".to_string());
-                        self.emit(AsmInstruction::SetGE(al.clone()));
-                        self.emit(AsmInstruction::MovZbl(al, Location::Reg("%eax".to_string())));
-                        self.emit(AsmInstruction::Mov(rax.clone(), dest));
-                    }
-                }
-                Instruction::Less { dest, arg1, arg2 } => {
+                    self.emit(AsmInstruction::Mov(size, a, rax.clone()));
+                    self.emit(AsmInstruction::Sub(size, b, rax.clone()));
+                    self.store_rax(dest);
+                },
+                Instruction::Mul { dest, arg1, arg2 } => {
+                    let size = arith_size(dest.ty());
                     let a = self.operand_to_reg(arg1);
                     let b = self.operand_to_reg(arg2);
-                    let dest = self.operand_to_reg(dest);
 
-                    self.emit(AsmInstruction::Mov(a, rax.clone()));
-                    self.emit(AsmInstruction::Cmp(b, rax.clone())); 
+                    self.emit(AsmInstruction::Mov(size, a, rax.clone()));
+                    self.emit(AsmInstruction::Mul(size, b, rax.clone()));
+                    self.store_rax(dest);
+                },
+                Instruction::Div { dest, arg1, arg2 } => {
+                    self.gen_division(&arg1, &arg2);
+                    self.store_rax(dest);
+                },
+                Instruction::Mod { dest, arg1, arg2 } => {
+                    self.integer_only(dest.ty());
+                    self.gen_division(&arg1, &arg2);
+                    let size = self.size_of(&dest);
+                    let dest = self.operand_to_reg(dest);
+                    self.emit(AsmInstruction::Mov(size, Location::Reg("%rdx".to_string()), dest));
+                },
+                Instruction::ShiftLeft { dest, arg1, arg2 } |
+                Instruction::ShiftRight { dest, arg1, arg2 } => {
+                    let ty = dest.ty();
+                    let size = arith_size(ty);
+                    let count_size = self.size_of(&arg2);
+                    let a = self.operand_to_reg(arg1);
+                    let b = self.operand_to_reg(arg2);
 
-                    if let Instruction::IfZero{ label, ..} = self.peek() {
-                        self.emit(AsmInstruction::Jae(label.clone()));
-                        self.next_instruction();
+                    self.emit(AsmInstruction::Mov(size, a, rax.clone()));
+                    let count = match b {
+                        Location::Imm(_) => b,
+                        _ => {
+                            self.emit(AsmInstruction::Mov(count_size, b, Location::Reg("%rcx".to_string())));
+                            Location::Reg("%cl".to_string())
+                        },
+                    };
+                    let shift = match self.curr_instruction() {
+                        Instruction::ShiftLeft { .. } => AsmInstruction::Shl(size, count, rax.clone()),
+                        _ if ty.is_signed() => AsmInstruction::Sar(size, count, rax.clone()),
+                        _ => AsmInstruction::Shr(size, count, rax.clone()),
+                    };
+                    self.emit(shift);
+                    self.store_rax(dest);
+                },
+                Instruction::SignExtend { dest, arg1, from, .. } |
+                Instruction::ZeroExtend { dest, arg1, from, .. } => {
+                    let a = self.operand_to_reg(arg1);
+                    self.emit(AsmInstruction::Mov(Size::of(from), a, rax.clone()));
+                    self.extend_rax(from);
+                    self.store_rax(dest);
+                },
+                Instruction::Truncate { dest, arg1, to, .. } => {
+                    let a = self.operand_to_reg(arg1);
+                    self.emit(AsmInstruction::Mov(Size::of(to), a, rax.clone()));
+                    self.store_rax(dest);
+                },
+                Instruction::IntToFloat { dest, arg1, from, to } => {
+                    // values above LONG_MAX in an unsigned long are not handled
+                    let a = self.operand_to_reg(arg1);
+                    let xmm0 = Location::Reg("%xmm0".to_string());
+                    self.emit(AsmInstruction::Mov(Size::of(from), a, rax.clone()));
+                    self.extend_rax(from);
+                    if to == Type::Double {
+                        self.emit(AsmInstruction::Cvtsi2sd(rax.clone(), xmm0.clone()));
                     } else {
-                        let al = Location::Reg("%al".to_string());
-                        self.emit(AsmInstruction::SetL(al.clone()));
-                        self.emit(AsmInstruction::MovZbl(al, Location::Reg("%eax".to_string())));
-                        self.emit(AsmInstruction::Mov(rax.clone(), dest));
+                        self.emit(AsmInstruction::Cvtsi2ss(rax.clone(), xmm0.clone()));
                     }
-                }
-                Instruction::LessEqual { dest, arg1, arg2 } => {
+                    self.float_to_rax(xmm0, to);
+                    self.store_rax(dest);
+                },
+                Instruction::FloatToInt { dest, arg1, from, .. } => {
                     let a = self.operand_to_reg(arg1);
-                    let b = self.operand_to_reg(arg2);
+                    let xmm0 = Location::Reg("%xmm0".to_string());
+                    self.emit(AsmInstruction::Mov(Size::of(from), a, rax.clone()));
+                    self.emit(AsmInstruction::Mov(Size::Quad, rax.clone(), xmm0.clone()));
+                    if from == Type::Double {
+                        self.emit(AsmInstruction::Cvttsd2si(xmm0, rax.clone()));
+                    } else {
+                        self.emit(AsmInstruction::Cvttss2si(xmm0, rax.clone()));
+                    }
+                    self.store_rax(dest);
+                },
+                Instruction::LoadAddress { dest, label } => {
                     let dest = self.operand_to_reg(dest);
-
-                    self.emit(AsmInstruction::Mov(a, rax.clone()));
-                    self.emit(AsmInstruction::Cmp(b, rax.clone())); 
-
-                    if let Instruction::IfZero{ label, ..} = self.peek() {
-                        self.emit(AsmInstruction::Ja(label.clone()));
-                        self.next_instruction();
+                    self.emit(AsmInstruction::Lea(Location::Label(label), rax.clone()));
+                    self.emit(AsmInstruction::Mov(Size::Quad, rax.clone(), dest));
+                },
+                Instruction::VaStart { dest } => {
+                    let dest = self.operand_to_reg(dest);
+                    self.gen_va_start(dest);
+                },
+                Instruction::VaArg { dest, list, ty } => {
+                    let list = self.operand_to_reg(list);
+                    let dest = self.operand_to_reg(dest);
+                    self.gen_va_arg(dest, list, ty);
+                },
+                Instruction::FloatConvert { dest, arg1, from, to } => {
+                    let a = self.operand_to_reg(arg1);
+                    let xmm0 = Location::Reg("%xmm0".to_string());
+                    self.emit(AsmInstruction::Mov(Size::of(from), a, rax.clone()));
+                    self.emit(AsmInstruction::Mov(Size::Quad, rax.clone(), xmm0.clone()));
+                    if to == Type::Double {
+                        self.emit(AsmInstruction::Cvtss2sd(xmm0.clone(), xmm0.clone()));
                     } else {
-                        let al = Location::Reg("%al".to_string());
-                        self.emit(AsmInstruction::SetLE(al.clone()));
-                        self.emit(AsmInstruction::MovZbl(al, Location::Reg("%eax".to_string())));
-                        self.emit(AsmInstruction::Mov(rax.clone(), dest));
+                        self.emit(AsmInstruction::Cvtsd2ss(xmm0.clone(), xmm0.clone()));
                     }
-                }
-                
+                    self.float_to_rax(xmm0, to);
+                    self.store_rax(dest);
+                },
 
-                Instruction::Add { dest, arg1, arg2 } => {
-                    let a = self.operand_to_reg(arg1);
-                    let b = self.operand_to_reg(arg2);
-                    let dest = self.operand_to_reg(dest);
+            }
+            if !self.next_instruction() { break; }
+        }
+        
+    }
 
-                    self.emit(AsmInstruction::Mov(a, rax.clone()));
-                    self.emit(AsmInstruction::Add(b, Location::Reg("%rax".to_string())));
 
-                    if let Location::Reg(s) = &dest && s.contains("%rax") {
-                        continue;
-                    }
+    // frame layout below %rbp: spill slots, the register save area and
+    // va_list of variadic functions, then the callee-saved registers in use.
+    // the total is rounded so %rsp stays 16-byte aligned after the prologue
+    fn layout_frame(&mut self) -> i32 {
+        let mut space = (self.spill.len() * 8) as i32;
+        if self.frame.variadic {
+            space += REG_SAVE_SIZE + VA_LIST_SIZE;
+        }
+        let mut used: Vec<String> = self.variables.values()
+            .filter(|v| !v.spilled && !v.precolored && v.name != "_ret")
+            .map(|v| self.reg_names[v.register_id].clone())
+            .filter(|r| CALLEE_SAVED.contains(&r.as_str()))
+            .collect();
+        used.sort();
+        used.dedup();
+        self.callee_saved.clear();
+        for reg in used {
+            space += 8;
+            self.callee_saved.push((reg, -space));
+        }
+        (space + 15) & !15
+    }
+
+    // a leaf function never pushes, so its whole frame can stay below %rsp
+    fn uses_red_zone(&self, stack_space: i32) -> bool {
+        let leaf = !self.instructions.iter().any(|inst| matches!(inst, 
+            Instruction::LCall(_) | Instruction::VaArg { .. }));
+        leaf && !self.frame.variadic && stack_space <= RED_ZONE_SIZE
+    }
 
-                    self.emit(AsmInstruction::Mov(rax.clone(), dest));
+    // rbp offsets of the register save area and of the va_list struct
+    fn va_offsets(&self) -> (i32, i32) {
+        let spill_space = (self.spill.len() * 8) as i32;
+        let reg_save = -(spill_space + REG_SAVE_SIZE);
+        (reg_save, reg_save - VA_LIST_SIZE)
+    }
+
+    fn save_arg_registers(&mut self) {
+        let (reg_save, _) = self.va_offsets();
+        for (i, reg) in ARG_REGISTERS.iter().enumerate() {
+            self.emit(AsmInstruction::Mov(Size::Quad, Location::Reg(reg.to_string()),
+                Location::Stack(reg_save + 8 * i as i32)));
+        }
+        for i in 0..SSE_ARG_COUNT {
+            self.emit(AsmInstruction::Mov(Size::Quad, Location::Reg(format!("%xmm{i}")),
+                Location::Stack(reg_save + 48 + 16 * i as i32)));
+        }
+    }
 
+    // moves every used param from where the caller left it to its allocated
+    // location. the argument registers are also allocatable, so a move waits
+    // until no pending move still reads its destination, and cycles are
+    // broken through %xmm15
+    fn load_params(&mut self) {
+        let mut moves = Vec::new();
+        for (param, location) in self.frame.params.clone().iter().zip(self.frame.param_locations.clone()) {
+            if !self.variables.contains_key(&param.name) {
+                continue;
+            }
+            let src = match location {
+                ParamLocation::Reg(i) => Location::Reg(ARG_REGISTERS[i].to_string()),
+                ParamLocation::Sse(i) => Location::Reg(format!("%xmm{i}")),
+                ParamLocation::Stack(offset) => Location::Stack(offset),
+            };
+            let dest = self.operand_to_reg(Operand::Var(param.name.clone(), param.stype));
+            moves.push((src, dest, param.stype));
+        }
+
+        while !moves.is_empty() {
+            let ready = (0..moves.len()).find(|&i| {
+                let dest = moves[i].1.to_string();
+                !moves.iter().enumerate().any(|(j, m)| j != i && m.0.to_string() == dest)
+            });
+            match ready {
+                Some(i) => {
+                    let (src, dest, ty) = moves.remove(i);
+                    self.load_param(src, dest, ty);
                 },
-                Instruction::Sub { dest, arg1, arg2 } => {
-                    let a = self.operand_to_reg(arg1);
-                    let b = self.operand_to_reg(arg2);
-                    let dest = self.operand_to_reg(dest);
+                None => {
+                    let xmm15 = Location::Reg("%xmm15".to_string());
+                    self.emit(AsmInstruction::Mov(Size::Quad, moves[0].0.clone(), xmm15.clone()));
+                    moves[0].0 = xmm15;
+                },
+            }
+        }
+    }
 
-                    self.emit(AsmInstruction::Mov(a, rax.clone()));
-                    self.emit(AsmInstruction::Sub(b, rax.clone()));
+    fn load_param(&mut self, src: Location, dest: Location, ty: Type) {
+        let rax = Location::Reg("%rax".to_string());
+        if ty.is_floating() {
+            self.float_to_rax(src, ty);
+        } else {
+            self.emit(AsmInstruction::Mov(Size::Quad, src, rax.clone()));
+        }
+        // the upper bits of a narrow argument are unspecified
+        self.extend_rax(ty);
+        self.emit(AsmInstruction::Mov(Size::of(ty), rax, dest));
+    }
 
-                    if let Location::Reg(s) = &dest && s.contains("%rax") {
-                        self.next_instruction();
-                        continue;
-                    }
+    fn gen_epilogue(&mut self) {
+        for (reg, offset) in self.callee_saved.clone() {
+            self.emit(AsmInstruction::Mov(Size::Quad, Location::Stack(offset), Location::Reg(reg)));
+        }
+        self.emit(AsmInstruction::Mov(Size::Quad, Location::Reg("%rbp".to_string()),
+        Location::Reg("%rsp".to_string())));
+        self.emit(AsmInstruction::Pop(Location::Reg("%rbp".to_string())));
+    }
 
-                    self.emit(AsmInstruction::Mov(rax.clone(), dest));
+    // for a call at the current instruction whose result is returned as it
+    // is, the index of the last instruction it stands for
+    //   LCall g, t = _ret, PopParams n, Return t
+    // a void function returns through EndFunc, it still has to be reached.
+    // such a call can jump to g with the frame already torn down, when g
+    // returns the same type and takes no argument on the stack, which would
+    // have to stay below the return address. the va_list of a variadic
+    // function lives in its frame, it keeps its calls
+    fn tail_call_end(&self, name: &str) -> Option<usize> {
+        let signature = self.signatures.get(name)?;
+        if self.frame.variadic || signature.ret_type != self.frame.ret_type {
+            return None;
+        }
+        let gp = self.params.iter().filter(|(_, ty)| !ty.is_floating()).count();
+        let sse = self.params.len() - gp;
+        if gp > ARG_REGISTERS.len() || sse > SSE_ARG_COUNT {
+            return None;
+        }
+        let i = self.curr;
+        match &self.instructions[i + 1..] {
+            [Instruction::Assign { dest, arg1: Operand::Temp(ret, _) }, Instruction::PopParams(_), Instruction::Return { dest: returned }, ..]
+                if ret == "_ret" && dest == returned => Some(i + 3),
+            [Instruction::Assign { arg1: Operand::Temp(ret, _), .. }, Instruction::PopParams(_), Instruction::EndFunc, ..]
+                if ret == "_ret" && self.frame.ret_type == Type::Void => Some(i + 2),
+            _ => None,
+        }
+    }
 
-                },
-                Instruction::Mul { dest, arg1, arg2 } => {
-                    let a = self.operand_to_reg(arg1);
-                    let b = self.operand_to_reg(arg2);
-                    let dest = self.operand_to_reg(dest);
+    // System V call: integer args in rdi..r9, floating args in xmm0..xmm7,
+    // the rest pushed right to left with %rsp 16-byte aligned at the call.
+    // a tail call leaves the frame first and jumps
+    fn gen_call(&mut self, name: String, tail: bool) {
+        let rax = Location::Reg("%rax".to_string());
+        let rsp = Location::Reg("%rsp".to_string());
+        let params = std::mem::take(&mut self.params);
+        let signature = self.signatures.get(&name).cloned();
+
+        let mut gp_args = Vec::new();
+        let mut sse_args = Vec::new();
+        let mut stack_args = Vec::new();
+        for (loc, ty) in params {
+            if ty.is_floating() && sse_args.len() < SSE_ARG_COUNT {
+                sse_args.push(loc);
+            } else if !ty.is_floating() && gp_args.len() < ARG_REGISTERS.len() {
+                gp_args.push(loc);
+            } else {
+                stack_args.push(loc);
+            }
+        }
 
-                    self.emit(AsmInstruction::Mov(a, rax.clone()));
-                    self.emit(AsmInstruction::Mul(b, rax.clone()));
+        let padding = if stack_args.len() % 2 == 1 { 8 } else { 0 };
+        if padding > 0 {
+            self.emit(AsmInstruction::Sub(Size::Quad, Location::Imm(padding.to_string()), rsp.clone()));
+        }
+        let stack_size = padding + 8 * stack_args.len();
+        for loc in stack_args.into_iter().rev() {
+            self.emit(AsmInstruction::Push(loc));
+        }
 
-                    if let Location::Reg(s) = &dest && s.contains("%rax") {
-                        self.next_instruction();
-                        continue;
-                    }
+        // the sources may live in argument registers themselves,
+        // so every value goes through the stack before being placed
+        for loc in gp_args.iter().chain(sse_args.iter()) {
+            self.emit(AsmInstruction::Push(loc.clone()));
+        }
+        for i in (0..sse_args.len()).rev() {
+            self.emit(AsmInstruction::Pop(rax.clone()));
+            self.emit(AsmInstruction::Mov(Size::Quad, rax.clone(), Location::Reg(format!("%xmm{i}"))));
+        }
+        for i in (0..gp_args.len()).rev() {
+            self.emit(AsmInstruction::Pop(Location::Reg(ARG_REGISTERS[i].to_string())));
+        }
 
-                    self.emit(AsmInstruction::Mov(rax.clone(), dest));
+        // %al holds an upper bound on the vector registers used
+        if signature.as_ref().is_none_or(|s| s.variadic) {
+            self.emit(AsmInstruction::Mov(Size::Long, Location::Imm(sse_args.len().to_string()),
+                Location::Reg("%eax".to_string())));
+        }
+        if tail {
+            self.gen_epilogue();
+            self.emit(AsmInstruction::Jmp(name));
+            return;
+        }
+        self.emit(AsmInstruction::Call(name));
+        if stack_size > 0 {
+            self.emit(AsmInstruction::Add(Size::Quad, Location::Imm(stack_size.to_string()), rsp));
+        }
 
-                },
-                Instruction::Div { dest, arg1, arg2 } => {
-                    // mudar depois
-                    let a = self.operand_to_reg(arg1);
-                    let b = self.operand_to_reg(arg2);
-                    let dest = self.operand_to_reg(dest);
+        match signature.map(|s| s.ret_type) {
+            Some(Type::Double) => self.emit(AsmInstruction::Mov(Size::Quad, Location::Reg("%xmm0".to_string()), rax)),
+            Some(Type::Float) => self.emit(AsmInstruction::MovD(Location::Reg("%xmm0".to_string()),
+                Location::Reg("%eax".to_string()))),
+            _ => (),
+        }
+    }
 
-                    self.emit(AsmInstruction::Mov(a, rax.clone()));
-                    self.emit(AsmInstruction::Cqo);
-                    let rbx = Location::Reg("%rbx".to_string());
-                    self.emit(AsmInstruction::Mov(b, rbx.clone()));
-                    self.emit(AsmInstruction::Div(rbx));
+    fn gen_va_start(&mut self, dest: Location) {
+        let rax = Location::Reg("%rax".to_string());
+        let (reg_save, va_list) = self.va_offsets();
+        let locations = &self.frame.param_locations;
+        let gp_count = locations.iter().filter(|l| matches!(l, ParamLocation::Reg(_))).count();
+        let sse_count = locations.iter().filter(|l| matches!(l, ParamLocation::Sse(_))).count();
+        let overflow = 16 + self.frame.stack_params_size() as i32;
+
+        // gp_offset, fp_offset, overflow_arg_area, reg_save_area
+        self.emit(AsmInstruction::Mov(Size::Long, Location::Imm((8 * gp_count).to_string()),
+            Location::Stack(va_list)));
+        self.emit(AsmInstruction::Mov(Size::Long, Location::Imm((48 + 16 * sse_count).to_string()),
+            Location::Stack(va_list + 4)));
+        self.emit(AsmInstruction::Lea(Location::Stack(overflow), rax.clone()));
+        self.emit(AsmInstruction::Mov(Size::Quad, rax.clone(), Location::Stack(va_list + 8)));
+        self.emit(AsmInstruction::Lea(Location::Stack(reg_save), rax.clone()));
+        self.emit(AsmInstruction::Mov(Size::Quad, rax.clone(), Location::Stack(va_list + 16)));
+        self.emit(AsmInstruction::Lea(Location::Stack(va_list), rax.clone()));
+        self.emit(AsmInstruction::Mov(Size::Quad, rax, dest));
+    }
 
-                    if let Location::Reg(s) = &dest && s.contains("%rax") {
-                        self.next_instruction();
-                        continue;
-                    }
+    fn gen_va_arg(&mut self, dest: Location, list: Location, ty: Type) {
+        let rax = Location::Reg("%rax".to_string());
+        let rcx = Location::Reg("%rcx".to_string());
+        let ecx = Location::Reg("%ecx".to_string());
+        let overflow_label = format!("{}_va{}", self.frame.name, self.va_count);
+        let done_label = format!("{}_va{}_end", self.frame.name, self.va_count);
+        self.va_count += 1;
+
+        // (offset field, register area end, slot size)
+        let (field, limit, step) = if ty.is_floating() { (4, 176, 16) } else { (0, 48, 8) };
+
+        self.emit(AsmInstruction::Mov(Size::Quad, list, rax.clone()));
+        self.emit(AsmInstruction::Push(rcx.clone()));
+        self.emit(AsmInstruction::Mov(Size::Long, Location::Mem(field, "%rax".to_string()), ecx.clone()));
+        self.emit(AsmInstruction::Cmp(Size::Long, Location::Imm(limit.to_string()), ecx));
+        self.emit(AsmInstruction::Jcc(CondCode::AE, overflow_label.clone()));
+        self.emit(AsmInstruction::Add(Size::Long, Location::Imm(step.to_string()), Location::Mem(field, "%rax".to_string())));
+        self.emit(AsmInstruction::Add(Size::Quad, Location::Mem(16, "%rax".to_string()), rcx.clone()));
+        self.emit(AsmInstruction::Mov(Size::Quad, Location::Mem(0, "%rcx".to_string()), rax.clone()));
+        self.emit(AsmInstruction::Jmp(done_label.clone()));
+        self.emit(AsmInstruction::Label(overflow_label));
+        self.emit(AsmInstruction::Mov(Size::Quad, Location::Mem(8, "%rax".to_string()), rcx.clone()));
+        self.emit(AsmInstruction::Add(Size::Quad, Location::Imm("8".to_string()), Location::Mem(8, "%rax".to_string())));
+        self.emit(AsmInstruction::Mov(Size::Quad, Location::Mem(0, "%rcx".to_string()), rax.clone()));
+        self.emit(AsmInstruction::Label(done_label));
+        self.emit(AsmInstruction::Pop(rcx));
+
+        if ty == Type::Float {
+            // variadic floats arrive promoted to double
+            let xmm0 = Location::Reg("%xmm0".to_string());
+            self.emit(AsmInstruction::Mov(Size::Quad, rax.clone(), xmm0.clone()));
+            self.emit(AsmInstruction::Cvtsd2ss(xmm0.clone(), xmm0.clone()));
+            self.float_to_rax(xmm0, ty);
+        } else {
+            self.extend_rax(ty);
+        }
+        self.emit(AsmInstruction::Mov(Size::of(ty), rax, dest));
+    }
 
-                    self.emit(AsmInstruction::Mov(rax.clone(), dest));
-                },
-                Instruction::Mod { dest, arg1, arg2 } => {
-                    // mudar depois
-                    let a = self.operand_to_reg(arg1);
-                    let b = self.operand_to_reg(arg2);
-                    let dest = self.operand_to_reg(dest);
+    fn size_of(&self, op: &Operand) -> Size {
+        Size::of(op.ty())
+    }
 
-                    self.emit(AsmInstruction::Mov(a, rax.clone()));
-                    self.emit(AsmInstruction::Cqo);
-                    let rbx = Location::Reg("%rbx".to_string());
-                    self.emit(AsmInstruction::Mov(b, rbx.clone()));
-                    self.emit(AsmInstruction::Div(rbx));
+    // the front end never builds these on floating operands
+    fn integer_only(&self, ty: Type) {
+        if ty.is_floating() {
+            panic!("CODEGEN: '{}' on a floating operand", self.curr_instruction().print());
+        }
+    }
 
-                    if let Location::Reg(s) = &dest && s.contains("%rdx") {
-                        self.next_instruction();
-                        continue;
-                    }
-                    let rdx = Location::Reg("%rdx".to_string());
-                    self.emit(AsmInstruction::Mov(rdx, dest));
+    // both operands go through %rax into %xmm0 and %xmm1, the result is left
+    // in %rax like the integer operations
+    fn gen_float_arith(&mut self, a: Location, b: Location, ty: Type) {
+        let rax = Location::Reg("%rax".to_string());
+        let xmm0 = Location::Reg("%xmm0".to_string());
+        let xmm1 = Location::Reg("%xmm1".to_string());
+        self.emit(AsmInstruction::Mov(Size::Quad, a, rax.clone()));
+        self.emit(AsmInstruction::Mov(Size::Quad, rax.clone(), xmm0.clone()));
+        self.emit(AsmInstruction::Mov(Size::Quad, b, rax.clone()));
+        self.emit(AsmInstruction::Mov(Size::Quad, rax, xmm1.clone()));
+        let double = ty == Type::Double;
+        let op = match self.curr_instruction() {
+            Instruction::Add { .. } if double => AsmInstruction::Addsd(xmm1, xmm0.clone()),
+            Instruction::Add { .. } => AsmInstruction::Addss(xmm1, xmm0.clone()),
+            Instruction::Sub { .. } if double => AsmInstruction::Subsd(xmm1, xmm0.clone()),
+            Instruction::Sub { .. } => AsmInstruction::Subss(xmm1, xmm0.clone()),
+            Instruction::Mul { .. } if double => AsmInstruction::Mulsd(xmm1, xmm0.clone()),
+            Instruction::Mul { .. } => AsmInstruction::Mulss(xmm1, xmm0.clone()),
+            Instruction::Div { .. } if double => AsmInstruction::Divsd(xmm1, xmm0.clone()),
+            Instruction::Div { .. } => AsmInstruction::Divss(xmm1, xmm0.clone()),
+            inst => panic!("CODEGEN: '{}' is not floating point arithmetic", inst.print()),
+        };
+        self.emit(op);
+        self.float_to_rax(xmm0, ty);
+    }
 
-                },
+    // the low bytes of %rax written to `dest`, sized by its type
+    fn store_rax(&mut self, dest: Operand) {
+        let size = self.size_of(&dest);
+        let dest = self.operand_to_reg(dest);
+        self.emit(AsmInstruction::Mov(size, Location::Reg("%rax".to_string()), dest));
+    }
+
+    // quotient in %rax and remainder in %rdx. the allocator keeps the
+    // divisor out of %rdx, and out of the way of %rcx when it is a constant
+    fn gen_division(&mut self, arg1: &Operand, arg2: &Operand) {
+        let rax = Location::Reg("%rax".to_string());
+        let rdx = Location::Reg("%rdx".to_string());
+        let ty = arg1.binary_type(arg2);
+        let size = arith_size(ty);
+        let a = self.operand_to_reg(arg1.clone());
+        if let Operand::Const(c) = arg2
+            && !matches!(a, Location::Imm(_))
+            && let Value::Int(d) = const_value(c, ty)
+            && self.gen_constant_division(ty, a.clone(), d) {
+            return;
+        }
+        let mut b = self.operand_to_reg(arg2.clone());
+
+        if let Location::Imm(_) = b {
+            let rcx = Location::Reg("%rcx".to_string());
+            self.emit(AsmInstruction::Mov(size, b, rcx.clone()));
+            b = rcx;
+        }
+        self.emit(AsmInstruction::Mov(size, a, rax));
+        if ty.is_signed() {
+            self.emit(AsmInstruction::Cqo(size));
+            self.emit(AsmInstruction::Div(size, b));
+        } else {
+            self.emit(AsmInstruction::Mov(size, Location::Imm("0".to_string()), rdx));
+            self.emit(AsmInstruction::UDiv(size, b));
+        }
+    }
+
+    // division by a constant that is not a power of two: a multiplication by
+    // m = 2^k / d rounded up, keeping the high half of the product, is the
+    // quotient rounded down. k is chosen so that the rounding error of m can
+    // never reach the next multiple of d, and a negative quotient gets 1
+    // added to round toward zero instead. the dividend is kept in %rcx,
+    // false when `d` is left to idiv
+    fn gen_constant_division(&mut self, ty: Type, a: Location, d: i64) -> bool {
+        let rax = Location::Reg("%rax".to_string());
+        let rcx = Location::Reg("%rcx".to_string());
+        let rdx = Location::Reg("%rdx".to_string());
+        let imm = |n: i128| Location::Imm(n.to_string());
+        let size = arith_size(ty);
+        let signed = ty.is_signed();
+        let magnitude = if signed { d.unsigned_abs() } else { d as u64 };
+        // the remainder multiplies the quotient back by d as an immediate
+        if magnitude < 3 || magnitude.is_power_of_two() || magnitude > i32::MAX as u64 {
+            return false;
+        }
+        let d_bits = 64 - (magnitude - 1).leading_zeros();
+        let magic = |k: u32| (1u128 << k).div_ceil(magnitude as u128);
+
+        match (signed, size) {
+            // 32-bit dividends are extended, their products fit in 128 bits
+            // with k = 64 and the quotient is the high half as it is
+            (true, Size::Long) => {
+                self.emit(AsmInstruction::MovSx(Size::Long, Size::Quad, a, rcx.clone()));
+                self.emit(AsmInstruction::Mov(Size::Quad, imm(magic(64) as i128), rax.clone()));
+                self.emit(AsmInstruction::MulHigh(Size::Quad, rcx.clone()));
+            },
+            (false, Size::Long) => {
+                self.emit(AsmInstruction::Mov(Size::Long, a, rcx.clone()));
+                self.emit(AsmInstruction::Mov(Size::Quad, imm(magic(64) as i128), rax.clone()));
+                self.emit(AsmInstruction::UMulHigh(Size::Quad, rcx.clone()));
+            },
+            // m takes 64 bits and is read as m - 2^64, adding the dividend
+            // back makes up for it
+            (true, _) => {
+                self.emit(AsmInstruction::Mov(Size::Quad, a, rcx.clone()));
+                self.emit(AsmInstruction::Mov(Size::Quad, imm(magic(63 + d_bits) as u64 as i64 as i128), rax.clone()));
+                self.emit(AsmInstruction::MulHigh(Size::Quad, rcx.clone()));
+                self.emit(AsmInstruction::Add(Size::Quad, rcx.clone(), rdx.clone()));
+                if d_bits > 1 {
+                    self.emit(AsmInstruction::Sar(Size::Quad, imm(d_bits as i128 - 1), rdx.clone()));
+                }
+            },
+            // m takes 65 bits, (x + high) / 2 is taken without the carry
+            (false, _) => {
+                self.emit(AsmInstruction::Mov(Size::Quad, a, rcx.clone()));
+                self.emit(AsmInstruction::Mov(Size::Quad, imm(magic(64 + d_bits) as u64 as i64 as i128), rax.clone()));
+                self.emit(AsmInstruction::UMulHigh(Size::Quad, rcx.clone()));
+                self.emit(AsmInstruction::Mov(Size::Quad, rcx.clone(), rax.clone()));
+                self.emit(AsmInstruction::Sub(Size::Quad, rdx.clone(), rax.clone()));
+                self.emit(AsmInstruction::Shr(Size::Quad, imm(1), rax.clone()));
+                self.emit(AsmInstruction::Add(Size::Quad, rax.clone(), rdx.clone()));
+                if d_bits > 1 {
+                    self.emit(AsmInstruction::Shr(Size::Quad, imm(d_bits as i128 - 1), rdx.clone()));
+                }
+            },
+        }
+        if signed {
+            // the sign bit of the dividend
+            self.emit(AsmInstruction::Mov(Size::Quad, rcx.clone(), rax.clone()));
+            self.emit(AsmInstruction::Shr(Size::Quad, imm(63), rax.clone()));
+            self.emit(AsmInstruction::Add(Size::Quad, rax.clone(), rdx.clone()));
+            if d < 0 {
+                self.emit(AsmInstruction::Neg(Size::Quad, rdx.clone()));
+            }
+        }
+        self.emit(AsmInstruction::Mov(Size::Quad, rdx.clone(), rax.clone()));
+        if let Instruction::Mod { .. } = self.curr_instruction() {
+            self.emit(AsmInstruction::Mul(Size::Quad, imm(d as i128), rax.clone()));
+            self.emit(AsmInstruction::Mov(Size::Quad, rcx, rdx.clone()));
+            self.emit(AsmInstruction::Sub(Size::Quad, rax, rdx));
+        }
+        true
+    }
+
+    // the label of an IfZero right after a comparison that tests its result,
+    // the comparison then jumps on the flags instead of producing 0 or 1.
+    // `dest` is never written then, so it must be dead after the IfZero
+    fn fused_branch(&self, dest: &Operand) -> Option<String> {
+        let label = match self.peek() {
+            Instruction::IfZero { cond, label } if cond == dest => label.clone(),
+            _ => return None,
+        };
+        let live = self.live_out.get(self.curr + 1)
+            .is_none_or(|names| names.contains(&dest.print()));
+        (!live).then_some(label)
+    }
+
+    // the result of a condition whose flags are set: a jump when the next
+    // instruction branches on it, otherwise 0 or 1 written to `dest`
+    fn branch_or_set(&mut self, cond: Condition, dest: Operand) {
+        if let Some(label) = self.fused_branch(&dest) {
+            self.jump_unless(cond, label);
+            self.next_instruction();
+        } else {
+            self.set_condition(cond);
+            self.store_rax(dest);
+        }
+    }
+
+    // IfZero jumps when the condition does not hold
+    fn jump_unless(&mut self, cond: Condition, label: String) {
+        if cond.parity.is_none() {
+            self.emit(AsmInstruction::Jcc(cond.code.invert(), label));
+        } else {
+            self.set_condition(cond);
+            self.emit(AsmInstruction::Cmp(Size::Long, Location::Imm("0".to_string()),
+                Location::Reg("%rax".to_string())));
+            self.emit(AsmInstruction::Jcc(CondCode::E, label));
+        }
+    }
 
-                _ => self.emit(AsmInstruction::Comment(format!("unknown instruction {} ", self.curr_instruction().print()))),
+    // sets the flags for `op != 0`
+    fn gen_test(&mut self, op: Operand) -> Condition {
+        let ty = op.ty();
+        let a = self.operand_to_reg(op);
+        self.gen_comparison(Relation::NotEqual, a, Location::Imm("0".to_string()), ty)
+    }
+
+    // sets the flags for `a ? b` and returns how to read them
+    fn gen_comparison(&mut self, rel: Relation, a: Location, b: Location, ty: Type) -> Condition {
+        let rax = Location::Reg("%rax".to_string());
+        let cond = condition(rel, CmpClass::of(ty));
+        if ty.is_floating() {
+            let (lhs, rhs) = if cond.swap { (b, a) } else { (a, b) };
+            let xmm0 = Location::Reg("%xmm0".to_string());
+            let xmm1 = Location::Reg("%xmm1".to_string());
+            self.emit(AsmInstruction::Mov(Size::Quad, lhs, rax.clone()));
+            self.emit(AsmInstruction::Mov(Size::Quad, rax.clone(), xmm0.clone()));
+            self.emit(AsmInstruction::Mov(Size::Quad, rhs, rax.clone()));
+            self.emit(AsmInstruction::Mov(Size::Quad, rax, xmm1.clone()));
+            if ty == Type::Double {
+                self.emit(AsmInstruction::Ucomisd(xmm1, xmm0));
+            } else {
+                self.emit(AsmInstruction::Ucomiss(xmm1, xmm0));
             }
-            if !self.next_instruction() { break; }
+        } else {
+            let size = Size::of(ty);
+            self.emit(AsmInstruction::Mov(size, a, rax.clone()));
+            self.emit(AsmInstruction::Cmp(size, b, rax));
+        }
+        cond
+    }
+
+    // materializes a condition as 0 or 1 in %rax
+    fn set_condition(&mut self, cond: Condition) {
+        let al = Location::Reg("%al".to_string());
+        self.emit(AsmInstruction::SetCC(cond.code, al.clone()));
+        if let Some(parity) = cond.parity {
+            let ah = Location::Reg("%ah".to_string());
+            self.emit(AsmInstruction::SetCC(parity, ah.clone()));
+            if parity == CondCode::NP {
+                self.emit(AsmInstruction::And(Size::Byte, ah, al.clone()));
+            } else {
+                self.emit(AsmInstruction::Or(Size::Byte, ah, al.clone()));
+            }
+        }
+        self.emit(AsmInstruction::MovZx(Size::Byte, Size::Long, al, Location::Reg("%eax".to_string())));
+    }
+
+    // values are kept in 64-bit registers, sign or zero extended from their type
+    fn extend_rax(&mut self, ty: Type) {
+        let rax = Location::Reg("%rax".to_string());
+        match (ty.size(), ty.is_signed()) {
+            (1, true) => self.emit(AsmInstruction::MovSx(Size::Byte, Size::Quad, Location::Reg("%al".to_string()), rax)),
+            (1, false) => self.emit(AsmInstruction::MovZx(Size::Byte, Size::Quad, Location::Reg("%al".to_string()), rax)),
+            (2, true) => self.emit(AsmInstruction::MovSx(Size::Word, Size::Quad, Location::Reg("%ax".to_string()), rax)),
+            (2, false) => self.emit(AsmInstruction::MovZx(Size::Word, Size::Quad, Location::Reg("%ax".to_string()), rax)),
+            (4, true) => self.emit(AsmInstruction::MovSx(Size::Long, Size::Quad, Location::Reg("%eax".to_string()), rax)),
+            (4, false) => {
+                let eax = Location::Reg("%eax".to_string());
+                self.emit(AsmInstruction::Mov(Size::Long, eax.clone(), eax));
+            },
+            _ => (),
         }
-        
     }
 
+    // floats travel as their bit pattern in general purpose registers
+    fn float_to_rax(&mut self, xmm: Location, ty: Type) {
+        if ty == Type::Double {
+            self.emit(AsmInstruction::Mov(Size::Quad, xmm, Location::Reg("%rax".to_string())));
+        } else {
+            self.emit(AsmInstruction::MovD(xmm, Location::Reg("%eax".to_string())));
+        }
+    }
 
     fn operand_to_reg(&self, op: Operand) -> Location {
         if let Operand::Const(c) = op {
-            return Location::Imm(c.to_string()); // mudar depois
+            return Location::Imm(immediate(&c));
         }
         let var = match self.variables.get(&op.print()) {
             Some(v) => v,
@@ -461,6 +1099,41 @@ impl AsmGenerator {
 }
 
 
+// byte and word arithmetic is done in 32 bits, only the low bytes are kept.
+// floating values have no integer size, they go through the xmm registers
+fn arith_size(ty: Type) -> Size {
+    if ty.is_floating() {
+        panic!("CODEGEN: integer arithmetic on '{:?}'", ty);
+    }
+    match Size::of(ty) {
+        Size::Byte | Size::Word => Size::Long,
+        size => size,
+    }
+}
+
+fn relation(inst: &Instruction) -> Relation {
+    match inst {
+        Instruction::Equal { .. } => Relation::Equal,
+        Instruction::NotEqual { .. } => Relation::NotEqual,
+        Instruction::Greater { .. } => Relation::Greater,
+        Instruction::GreaterEqual { .. } => Relation::GreaterEqual,
+        Instruction::Less { .. } => Relation::Less,
+        Instruction::LessEqual { .. } => Relation::LessEqual,
+        _ => panic!("CODEGEN: '{}' is not a comparison", inst.print()),
+    }
+}
+
+fn immediate(c: &ConstValue) -> String {
+    match c {
+        ConstValue::Int(n) => n.to_string(),
+        ConstValue::Char(c) => (*c as u32).to_string(),
+        ConstValue::Float(f) => f.to_bits().to_string(),
+        ConstValue::Double(d) => (d.to_bits() as i64).to_string(),
+        ConstValue::Void => String::from("0"),
+    }
+}
+
+
 impl AsmInstruction {
     pub fn to_string(&self) -> String {
         match self {
@@ -470,74 +1143,137 @@ impl AsmInstruction {
             AsmInstruction::Jmp(l) => {
                 format!("\tjmp {l}")
             },
-            AsmInstruction::Je(l) => {
-                format!("\tje {l}")
+            AsmInstruction::Jcc(cc, l) => {
+                format!("\tj{} {l}", cc.suffix())
+            },
+            AsmInstruction::Or(size, a, b) => {
+                format!("\tor{} {}, {}", size.suffix(), a.sized(*size), b.sized(*size))
+            }
+            AsmInstruction::And(size, a, b) => {
+                format!("\tand{} {}, {}", size.suffix(), a.sized(*size), b.sized(*size))
+            }
+            AsmInstruction::Xor(size, a, b) => {
+                format!("\txor{} {}, {}", size.suffix(), a.sized(*size), b.sized(*size))
+            }
+            AsmInstruction::Neg(size, a) => {
+                format!("\tneg{} {}", size.suffix(), a.sized(*size))
+            }
+            AsmInstruction::Not(size, a) => {
+                format!("\tnot{} {}", size.suffix(), a.sized(*size))
+            }
+            AsmInstruction::Btc(size, a, b) => {
+                format!("\tbtc{} {}, {}", size.suffix(), a.to_string(), b.sized(*size))
+            }
+            AsmInstruction::SetCC(cc, a) => {
+                format!("\tset{} {}", cc.suffix(), a.to_string())
+            }, 
+            AsmInstruction::Call(s) => {
+                format!("\tcall {}", s)
+            }       
+            AsmInstruction::Ret => {
+                format!("\tret")
+            },
+            AsmInstruction::Cmp(size, a, b) => {
+                format!("\tcmp{} {}, {}", size.suffix(), a.sized(*size), b.sized(*size))
             },
-            AsmInstruction::Jne(l) => {
-                format!("\tjne {l}")
+            AsmInstruction::Ucomisd(a, b) => {
+                format!("\tucomisd {}, {}", a.to_string(), b.to_string())
             },
-            AsmInstruction::Jl(l) => {
-                format!("\tjl {l}")
+            AsmInstruction::Ucomiss(a, b) => {
+                format!("\tucomiss {}, {}", a.to_string(), b.to_string())
             },
-            AsmInstruction::Jle(l) => {
-                format!("\tjle {l}")
+            AsmInstruction::Lea(a, b) => {
+                format!("\tleaq {}, {}", a.to_string(), b.to_string())
             },
-            AsmInstruction::Ja(l) => {
-                format!("\tja {l}")
+            AsmInstruction::Mov(size, a, b) => {
+                format!("\tmov{} {}, {}", size.suffix(), a.sized(*size), b.sized(*size))
             },
-            AsmInstruction::Jae(l) => {
-                format!("\tjae {l}")
+            AsmInstruction::MovZx(from, to, a, b) => {
+                format!("\tmovz{}{} {}, {}", from.suffix(), to.suffix(), a.sized(*from), b.sized(*to))
             },
-            AsmInstruction::Or(a, b) => {
-                format!("\tor {}, {}", a.to_string(), b.to_string())
-            }
-            AsmInstruction::SetE(a) => {
-                format!("\tsete {}", a.to_string())
-            }, 
-            AsmInstruction::SetNE(a) => {
-                format!("\tsetne {}", a.to_string())
+            AsmInstruction::MovSx(from, to, a, b) => {
+                format!("\tmovs{}{} {}, {}", from.suffix(), to.suffix(), a.sized(*from), b.sized(*to))
             },
-            AsmInstruction::SetL(a) => {
-                format!("\tsetl {}", a.to_string())
+            AsmInstruction::MovD(a, b) => {
+                format!("\tmovd {}, {}", a.to_string(), b.to_string())
             },
-            AsmInstruction::SetLE(a) => {
-                format!("\tsetle {}", a.to_string())
+            AsmInstruction::Cvtsi2sd(a, b) => {
+                format!("\tcvtsi2sdq {}, {}", a.to_string(), b.to_string())
             },
-            AsmInstruction::SetG(a) => {
-                format!("\tsetg {}", a.to_string())
+            AsmInstruction::Cvtsi2ss(a, b) => {
+                format!("\tcvtsi2ssq {}, {}", a.to_string(), b.to_string())
             },
-            AsmInstruction::SetGE(a) => {
-                format!("\tsetge {}", a.to_string())
-            },   
-            AsmInstruction::Call(s) => {
-                format!("\tcall {}", s)
-            }       
-            AsmInstruction::Ret => {
-                format!("\tret")
+            AsmInstruction::Cvttsd2si(a, b) => {
+                format!("\tcvttsd2siq {}, {}", a.to_string(), b.to_string())
+            },
+            AsmInstruction::Cvttss2si(a, b) => {
+                format!("\tcvttss2siq {}, {}", a.to_string(), b.to_string())
+            },
+            AsmInstruction::Cvtss2sd(a, b) => {
+                format!("\tcvtss2sd {}, {}", a.to_string(), b.to_string())
+            },
+            AsmInstruction::Cvtsd2ss(a, b) => {
+                format!("\tcvtsd2ss {}, {}", a.to_string(), b.to_string())
+            },
+            AsmInstruction::Addsd(a, b) => {
+                format!("\taddsd {}, {}", a.to_string(), b.to_string())
+            },
+            AsmInstruction::Addss(a, b) => {
+                format!("\taddss {}, {}", a.to_string(), b.to_string())
+            },
+            AsmInstruction::Subsd(a, b) => {
+                format!("\tsubsd {}, {}", a.to_string(), b.to_string())
+            },
+            AsmInstruction::Subss(a, b) => {
+                format!("\tsubss {}, {}", a.to_string(), b.to_string())
+            },
+            AsmInstruction::Mulsd(a, b) => {
+                format!("\tmulsd {}, {}", a.to_string(), b.to_string())
+            },
+            AsmInstruction::Mulss(a, b) => {
+                format!("\tmulss {}, {}", a.to_string(), b.to_string())
+            },
+            AsmInstruction::Divsd(a, b) => {
+                format!("\tdivsd {}, {}", a.to_string(), b.to_string())
             },
-            AsmInstruction::Cmp(a, b) => {
-                format!("\tcmpq {}, {}", a.to_string(), b.to_string())
+            AsmInstruction::Divss(a, b) => {
+                format!("\tdivss {}, {}", a.to_string(), b.to_string())
             },
-            AsmInstruction::Mov(a, b) => {
-                format!("\tmovq {}, {}", a.to_string(), b.to_string())
+            AsmInstruction::Add(size, a, b) => {
+                format!("\tadd{} {}, {}", size.suffix(), a.sized(*size), b.sized(*size))
             },
-            AsmInstruction::MovZbl(a, b) => {
-                format!("\tmovzbl {}, {}", a.to_string(), b.to_string())
+            AsmInstruction::Sub(size, a, b) => {
+                format!("\tsub{} {}, {}", size.suffix(), a.sized(*size), b.sized(*size))
             },
-            AsmInstruction::Add(a, b) => {
-                format!("\taddq {}, {}", a.to_string(), b.to_string())
+            AsmInstruction::Mul(size, a, b) => {
+                format!("\timul{} {}, {}", size.suffix(), a.sized(*size), b.sized(*size))
             },
-            AsmInstruction::Sub(a, b) => {
-                format!("\tsubq {}, {}", a.to_string(), b.to_string())
+            AsmInstruction::Div(size, a) => {
+                format!("\tidiv{} {}", size.suffix(), a.sized(*size))
             },
-            AsmInstruction::Mul(a, b) => {
-                format!("\timulq {}, {}", a.to_string(), b.to_string())
+            AsmInstruction::UDiv(size, a) => {
+                format!("\tdiv{} {}", size.suffix(), a.sized(*size))
             },
-            AsmInstruction::Div(a) => {
-                format!("\tidivq {}", a.to_string())
+            AsmInstruction::MulHigh(size, a) => {
+                format!("\timul{} {}", size.suffix(), a.sized(*size))
             },
-            AsmInstruction::Cqo => {
-                format!("\tcqo")
+            AsmInstruction::UMulHigh(size, a) => {
+                format!("\tmul{} {}", size.suffix(), a.sized(*size))
+            },
+            AsmInstruction::Shl(size, a, b) => {
+                format!("\tshl{} {}, {}", size.suffix(), a.to_string(), b.sized(*size))
+            },
+            AsmInstruction::Sar(size, a, b) => {
+                format!("\tsar{} {}, {}", size.suffix(), a.to_string(), b.sized(*size))
+            },
+            AsmInstruction::Shr(size, a, b) => {
+                format!("\tshr{} {}, {}", size.suffix(), a.to_string(), b.sized(*size))
+            },
+            AsmInstruction::Cqo(size) => {
+                match size {
+                    Size::Quad => String::from("\tcqto"),
+                    _ => String::from("\tcltd"),
+                }
             },
             AsmInstruction::Push(a,) => {
                 format!("\tpushq {}", a.to_string())
@@ -545,9 +1281,357 @@ impl AsmInstruction {
             AsmInstruction::Pop(a) => {
                 format!("\tpopq {}", a.to_string())
             },
-            AsmInstruction::Comment(s) => {
-                format!("# {}", s)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{intermediate::frame::new_frame, optimizer::{cfg::function_cfg, liveness::new_liveness_analyzer}};
+
+    use super::*;
+
+    // one index per Instruction variant, with no wildcard arm, so a new
+    // variant does not compile until it is lowered and sampled below
+    fn variant_index(inst: &Instruction) -> usize {
+        match inst {
+            Instruction::Label(_) => 0,
+            Instruction::Goto(_) => 1,
+            Instruction::BeginFunc(_) => 2,
+            Instruction::EndFunc => 3,
+            Instruction::PushParam(..) => 4,
+            Instruction::PopParams(_) => 5,
+            Instruction::LCall(_) => 6,
+            Instruction::IfZero { .. } => 7,
+            Instruction::Assign { .. } => 8,
+            Instruction::Return { .. } => 9,
+            Instruction::LogicalOr { .. } => 10,
+            Instruction::LogicalAnd { .. } => 11,
+            Instruction::BitwiseOr { .. } => 12,
+            Instruction::BitwiseXor { .. } => 13,
+            Instruction::BitwiseAnd { .. } => 14,
+            Instruction::Equal { .. } => 15,
+            Instruction::NotEqual { .. } => 16,
+            Instruction::Greater { .. } => 17,
+            Instruction::GreaterEqual { .. } => 18,
+            Instruction::Less { .. } => 19,
+            Instruction::LessEqual { .. } => 20,
+            Instruction::ShiftLeft { .. } => 21,
+            Instruction::ShiftRight { .. } => 22,
+            Instruction::Add { .. } => 23,
+            Instruction::Sub { .. } => 24,
+            Instruction::Mul { .. } => 25,
+            Instruction::Div { .. } => 26,
+            Instruction::Mod { .. } => 27,
+            Instruction::Neg { .. } => 28,
+            Instruction::Complement { .. } => 29,
+            Instruction::Not { .. } => 30,
+            Instruction::SignExtend { .. } => 31,
+            Instruction::ZeroExtend { .. } => 32,
+            Instruction::Truncate { .. } => 33,
+            Instruction::IntToFloat { .. } => 34,
+            Instruction::FloatToInt { .. } => 35,
+            Instruction::FloatConvert { .. } => 36,
+            Instruction::LoadAddress { .. } => 37,
+            Instruction::VaStart { .. } => 38,
+            Instruction::VaArg { .. } => 39,
+        }
+    }
+    const VARIANT_COUNT: usize = 40;
+
+    // the assembly of `instructions` as the function f. each variable named in
+    // `registers` sits in the given register, or in its own stack slot for None
+    fn lower(instructions: Vec<Instruction>, params: &[(&str, Type)], locals: &[(&str, Type)],
+        registers: &[(&str, Option<&str>)], signatures: IndexMap<String, Signature>) -> String {
+        let mut frame = new_frame("f".to_string(), Type::Int);
+        for (name, ty) in params {
+            frame.allocate_param(name.to_string(), 1, *ty);
+        }
+        for (name, ty) in locals {
+            frame.allocate_local(name.to_string(), 1, *ty);
+        }
+        frame.range = (0, instructions.len() - 1);
+        let mut variables = IndexMap::new();
+        let mut spill = IndexMap::new();
+        for (name, register) in registers.iter().chain([&("_ret", None)]) {
+            let register_id = register.map_or(0, |r| WINDOWS_REGISTERS.iter().position(|w| *w == r).unwrap());
+            let spilled = register.is_none() && *name != "_ret";
+            if spilled {
+                spill.insert(name.to_string(), -8 * (spill.len() as i32 + 1));
+            }
+            variables.insert(name.to_string(), Variable { name: name.to_string(),
+                register_id, spilled, precolored: false });
+        }
+        let mut liveness = new_liveness_analyzer(instructions.clone(), function_cfg(&instructions).blocks);
+        liveness.gen_live_out();
+        liveness.gen_inst_live_out();
+        let live_out = (0..instructions.len()).map(|i| liveness.live_out(i).clone()).collect();
+        let mut asm_gen = new_asm_generator(instructions, frame, variables, spill, live_out, signatures);
+        asm_gen.generate_assembly();
+        asm_gen.print_asm()
+    }
+
+    #[test]
+    fn codegen_covers_every_instruction() {
+        let a = || Operand::Var("a".to_string(), Type::Int);
+        let b = || Operand::Var("b".to_string(), Type::Int);
+        let t = || Operand::Temp("t".to_string(), Type::Int);
+        let list = || Operand::Temp("l".to_string(), Type::Pointer);
+        let x = || Operand::Var("x".to_string(), Type::Double);
+        let d = || Operand::Temp("d".to_string(), Type::Double);
+        let binary = [
+            |dest, arg1, arg2| Instruction::LogicalOr { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::LogicalAnd { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::BitwiseOr { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::BitwiseXor { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::BitwiseAnd { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::Equal { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::NotEqual { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::Greater { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::GreaterEqual { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::Less { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::LessEqual { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::ShiftLeft { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::ShiftRight { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::Add { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::Sub { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::Mul { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::Div { dest, arg1, arg2 },
+            |dest, arg1, arg2| Instruction::Mod { dest, arg1, arg2 },
+        ];
+
+        let mut instructions = vec![
+            Instruction::BeginFunc(0),
+            Instruction::Label("L0".to_string()),
+            Instruction::Assign { dest: a(), arg1: Operand::Const(ConstValue::Int(1)) },
+            Instruction::IfZero { cond: a(), label: "L0".to_string() },
+            Instruction::Goto("L0".to_string()),
+            Instruction::PushParam(a(), Type::Int),
+            Instruction::LCall("g".to_string()),
+            Instruction::PopParams(0),
+            Instruction::Assign { dest: t(), arg1: Operand::Temp("_ret".to_string(), Type::Int) },
+        ];
+        for make in binary {
+            instructions.push(make(t(), a(), b()));
+        }
+        instructions.push(Instruction::Div { dest: t(), arg1: a(), arg2: Operand::Const(ConstValue::Int(7)) });
+        instructions.extend([
+            Instruction::Add { dest: d(), arg1: x(), arg2: d() },
+            Instruction::Sub { dest: d(), arg1: x(), arg2: d() },
+            Instruction::Mul { dest: d(), arg1: d(), arg2: Operand::Const(ConstValue::Double(2.0)) },
+            Instruction::Div { dest: d(), arg1: x(), arg2: Operand::Const(ConstValue::Double(2.0)) },
+        ]);
+        instructions.extend([
+            Instruction::Neg { dest: t(), arg1: a() },
+            Instruction::Complement { dest: t(), arg1: a() },
+            Instruction::Not { dest: t(), arg1: a() },
+            Instruction::SignExtend { dest: t(), arg1: a(), from: Type::Int, to: Type::Long },
+            Instruction::ZeroExtend { dest: t(), arg1: a(), from: Type::UnsignedInt, to: Type::Long },
+            Instruction::Truncate { dest: t(), arg1: a(), from: Type::Int, to: Type::Char },
+            Instruction::IntToFloat { dest: t(), arg1: a(), from: Type::Int, to: Type::Double },
+            Instruction::FloatToInt { dest: t(), arg1: t(), from: Type::Double, to: Type::Int },
+            Instruction::FloatConvert { dest: t(), arg1: t(), from: Type::Double, to: Type::Float },
+            Instruction::LoadAddress { dest: list(), label: ".LC0".to_string() },
+            Instruction::VaStart { dest: list() },
+            Instruction::VaArg { dest: t(), list: list(), ty: Type::Int },
+            Instruction::Return { dest: a() },
+            Instruction::EndFunc,
+        ]);
+
+        let mut covered = [false; VARIANT_COUNT];
+        for inst in &instructions {
+            covered[variant_index(inst)] = true;
+        }
+        assert!(covered.iter().all(|c| *c), "variants not sampled: {:?}",
+            (0..VARIANT_COUNT).filter(|i| !covered[*i]).collect::<Vec<_>>());
+
+        let registers = [("a", Some("%r10")), ("b", Some("%r11")), ("t", Some("%r12")),
+            ("l", Some("%r13")), ("x", Some("%r14")), ("d", Some("%r15"))];
+        let asm = lower(instructions, &[], &[("a", Type::Int), ("b", Type::Int), ("x", Type::Double)],
+            &registers, IndexMap::new());
+        for expected in ["\tcall g", "\tidivl", "\timulq %rcx", "\tshll", "\tcvtsi2sdq", "\tleaq .LC0(%rip)", "\tret",
+            "\taddsd %xmm1, %xmm0", "\tsubsd %xmm1, %xmm0", "\tmulsd %xmm1, %xmm0", "\tdivsd %xmm1, %xmm0",
+            "\torl %ecx, %eax", "\tandl %ecx, %eax"] {
+            assert!(asm.contains(expected), "missing '{}' in:\n{}", expected, asm);
+        }
+        // the double operations never reach the integer instructions
+        assert_eq!(asm.matches("\tidiv").count(), 2, "in:\n{}", asm);
+        assert_eq!(asm.matches("\timul").count(), 2, "in:\n{}", asm);
+    }
+
+    // a function keeping `a` in %r12 and `b` in %r15 across an optional call
+    // to g, with `spills` stack slots
+    fn frame_asm(call: bool, spills: usize) -> String {
+        let a = || Operand::Var("a".to_string(), Type::Int);
+        let b = || Operand::Var("b".to_string(), Type::Int);
+        let t = || Operand::Temp("t".to_string(), Type::Int);
+        let mut instructions = vec![
+            Instruction::BeginFunc(0),
+            Instruction::Assign { dest: a(), arg1: Operand::Const(ConstValue::Int(1)) },
+            Instruction::Assign { dest: b(), arg1: Operand::Const(ConstValue::Int(2)) },
+        ];
+        if call {
+            instructions.extend([
+                Instruction::LCall("g".to_string()),
+                Instruction::PopParams(0),
+            ]);
+        }
+        instructions.extend([
+            Instruction::Add { dest: t(), arg1: a(), arg2: b() },
+            Instruction::Return { dest: t() },
+            Instruction::EndFunc,
+        ]);
+
+        let names: Vec<String> = (0..spills).map(|i| format!("s{i}")).collect();
+        let mut registers = vec![("a", Some("%r12")), ("b", Some("%r15")), ("t", Some("%r10"))];
+        registers.extend(names.iter().map(|name| (name.as_str(), None)));
+        lower(instructions, &[], &[("a", Type::Int), ("b", Type::Int)], &registers, IndexMap::new())
+    }
+
+    #[test]
+    fn prologue_and_epilogue() {
+        // (calls g, spill slots, frame size, moves %rsp)
+        let cases = [
+            (false, 0, 16, false),
+            (false, 14, 128, false),
+            (false, 15, 144, true),
+            (true, 0, 16, true),
+            (true, 1, 32, true),
+        ];
+        for (call, spills, size, moves) in cases {
+            let asm = frame_asm(call, spills);
+            let lines: Vec<&str> = asm.lines().collect();
+            let saves: Vec<(String, String)> = lines.iter()
+                .filter_map(|l| l.strip_prefix("\tmovq ")?.split_once(", "))
+                .filter(|(reg, slot)| slot.ends_with("(%rbp)") && CALLEE_SAVED.contains(reg))
+                .map(|(reg, slot)| (reg.to_string(), slot.to_string()))
+                .collect();
+            let restores: Vec<(String, String)> = lines.iter()
+                .filter_map(|l| l.strip_prefix("\tmovq ")?.split_once(", "))
+                .filter(|(slot, reg)| slot.ends_with("(%rbp)") && CALLEE_SAVED.contains(reg))
+                .map(|(slot, reg)| (reg.to_string(), slot.to_string()))
+                .collect();
+
+            // exactly the callee-saved registers handed out, restored from
+            // where they were saved
+            let regs: Vec<&str> = saves.iter().map(|(reg, _)| reg.as_str()).collect();
+            assert_eq!(regs, ["%r12", "%r15"], "failed at: {:?} in:\n{}", (call, spills), asm);
+            assert_eq!(saves, restores, "failed at: {:?} in:\n{}", (call, spills), asm);
+
+            let sub = lines.iter().find_map(|l| l.strip_prefix("\tsubq $")?.strip_suffix(", %rsp"));
+            assert_eq!(sub.is_some(), moves, "failed at: {:?} in:\n{}", (call, spills), asm);
+            if let Some(sub) = sub {
+                let sub: i32 = sub.parse().unwrap();
+                assert_eq!(sub, size, "failed at: {:?} in:\n{}", (call, spills), asm);
+                assert_eq!(sub % 16, 0, "failed at: {:?} in:\n{}", (call, spills), asm);
+            }
+            // the saves fit in the frame, below the spill slots
+            for (_, slot) in &saves {
+                let offset: i32 = slot.trim_end_matches("(%rbp)").parse().unwrap();
+                assert!(-offset > 8 * spills as i32 && -offset <= size, "failed at: {:?} in:\n{}", (call, spills), asm);
+            }
+            // the restores come first in the epilogue, then the frame is left
+            let end = lines.iter().position(|l| *l == "f_end:").unwrap();
+            let restored: Vec<String> = restores.iter().map(|(reg, slot)| format!("\tmovq {slot}, {reg}")).collect();
+            assert_eq!(lines[end + 1..end + 3], restored, "failed at: {:?} in:\n{}", (call, spills), asm);
+            assert_eq!(lines[end + 3..], ["\tmovq %rbp, %rsp", "\tpopq %rbp", "\tret"], "failed at: {:?} in:\n{}", (call, spills), asm);
+        }
+    }
+
+    #[test]
+    fn arithmetic_sizes() {
+        let cases = [
+            (Type::Char, Size::Long),
+            (Type::Short, Size::Long),
+            (Type::Int, Size::Long),
+            (Type::UnsignedInt, Size::Long),
+            (Type::Long, Size::Quad),
+            (Type::Pointer, Size::Quad),
+        ];
+        for (ty, expected) in cases {
+            assert_eq!(arith_size(ty), expected, "failed at: {:?}", ty);
+        }
+        for ty in [Type::Float, Type::Double] {
+            assert!(std::panic::catch_unwind(|| arith_size(ty)).is_err(), "failed at: {:?}", ty);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "on a floating operand")]
+    fn floating_remainder_is_rejected() {
+        let d = || Operand::Temp("d".to_string(), Type::Double);
+        let instructions = vec![
+            Instruction::BeginFunc(0),
+            Instruction::Mod { dest: d(), arg1: d(), arg2: d() },
+            Instruction::EndFunc,
+        ];
+        lower(instructions, &[], &[], &[("d", Some("%r10"))], IndexMap::new());
+    }
+
+    #[test]
+    fn fused_branches() {
+        let a = || Operand::Var("a".to_string(), Type::Int);
+        let b = || Operand::Var("b".to_string(), Type::Int);
+        let t = || Operand::Temp("t".to_string(), Type::Int);
+        let sum = || Instruction::Add { dest: t(), arg1: a(), arg2: b() };
+        let read = || Instruction::Add { dest: a(), arg1: t(), arg2: b() };
+        // (instructions before and after `t = a < b; IfZero t L0`, jumps on the flags)
+        let cases = [
+            (vec![], vec![Instruction::Return { dest: a() }], true),
+            (vec![], vec![Instruction::Return { dest: t() }], false),
+            (vec![], vec![read(), Instruction::Return { dest: a() }], false),
+            // an earlier t is read, but the comparison result is dead
+            (vec![sum(), read()], vec![Instruction::Return { dest: a() }], true),
+        ];
+        for (i, (before, after, jumps)) in cases.into_iter().enumerate() {
+            let mut instructions = vec![Instruction::BeginFunc(0)];
+            instructions.extend(before);
+            instructions.extend([
+                Instruction::Less { dest: t(), arg1: a(), arg2: b() },
+                Instruction::IfZero { cond: t(), label: "L0".to_string() },
+                Instruction::Label("L0".to_string()),
+            ]);
+            instructions.extend(after);
+            instructions.push(Instruction::EndFunc);
+            let asm = lower(instructions, &[("a", Type::Int), ("b", Type::Int)], &[],
+                &[("a", Some("%r10")), ("b", Some("%r11")), ("t", Some("%r12"))], IndexMap::new());
+            assert_eq!(asm.contains("\tjge L0"), jumps, "failed at: {} in:\n{}", i, asm);
+            // otherwise t is stored for the reader after the branch, which tests it
+            let stored = "\tsetl %al\n\tmovzbl %al, %eax\n\tmovl %eax, %r12d";
+            assert_eq!(asm.contains(stored), !jumps, "failed at: {} in:\n{}", i, asm);
+        }
+    }
+
+    #[test]
+    fn tail_calls() {
+        let a = || Operand::Var("a".to_string(), Type::Int);
+        let t = || Operand::Temp("t".to_string(), Type::Int);
+        let call = |callee: &str| vec![
+            Instruction::PushParam(a(), Type::Int),
+            Instruction::LCall(callee.to_string()),
+            Instruction::Assign { dest: t(), arg1: Operand::Temp("_ret".to_string(), Type::Int) },
+            Instruction::PopParams(0),
+        ];
+        let mut signatures = IndexMap::new();
+        signatures.insert("g".to_string(), Signature { ret_type: Type::Int, params: vec![Type::Int], variadic: false });
+        signatures.insert("h".to_string(), Signature { ret_type: Type::Long, params: vec![Type::Int], variadic: false });
+
+        // (callee, returned after the call, jumps)
+        let cases = [("g", true, true), ("g", false, false), ("h", true, false)];
+        for (callee, returned, jumps) in cases {
+            let mut instructions = vec![Instruction::Label("f".to_string()), Instruction::BeginFunc(0)];
+            instructions.extend(call(callee));
+            if !returned {
+                instructions.push(Instruction::Add { dest: t(), arg1: t(), arg2: a() });
             }
+            instructions.extend([Instruction::Return { dest: t() }, Instruction::EndFunc]);
+
+            let asm = lower(instructions, &[("a", Type::Int)], &[],
+                &[("a", Some("%r10")), ("t", Some("%r11"))], signatures.clone());
+            let jump = format!("\tjmp {}", callee);
+            assert_eq!(asm.contains(&jump), jumps, "failed at: {} in:\n{}", callee, asm);
+            assert_eq!(asm.contains(&format!("\tcall {}", callee)), !jumps, "failed at: {} in:\n{}", callee, asm);
+            assert!(asm.contains("\tret"), "failed at: {} in:\n{}", callee, asm);
         }
     }
-}
\ No newline at end of file
+}
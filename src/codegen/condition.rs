@@ -0,0 +1,293 @@
+use crate::parser::token::Type;
+
+// x86 condition codes, the suffix of jcc and setcc
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CondCode {
+    E, NE,
+    // signed
+    L, LE, G, GE,
+    // unsigned, also used after ucomiss/ucomisd
+    B, BE, A, AE,
+    // parity, set when a floating comparison is unordered
+    P, NP,
+}
+
+impl CondCode {
+    pub fn invert(self) -> CondCode {
+        match self {
+            CondCode::E => CondCode::NE,
+            CondCode::NE => CondCode::E,
+            CondCode::L => CondCode::GE,
+            CondCode::GE => CondCode::L,
+            CondCode::LE => CondCode::G,
+            CondCode::G => CondCode::LE,
+            CondCode::B => CondCode::AE,
+            CondCode::AE => CondCode::B,
+            CondCode::BE => CondCode::A,
+            CondCode::A => CondCode::BE,
+            CondCode::P => CondCode::NP,
+            CondCode::NP => CondCode::P,
+        }
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            CondCode::E => "e",
+            CondCode::NE => "ne",
+            CondCode::L => "l",
+            CondCode::LE => "le",
+            CondCode::G => "g",
+            CondCode::GE => "ge",
+            CondCode::B => "b",
+            CondCode::BE => "be",
+            CondCode::A => "a",
+            CondCode::AE => "ae",
+            CondCode::P => "p",
+            CondCode::NP => "np",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Relation {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CmpClass {
+    Signed,
+    Unsigned,
+    Floating,
+}
+
+impl CmpClass {
+    pub fn of(ty: Type) -> CmpClass {
+        if ty.is_floating() {
+            CmpClass::Floating
+        } else if ty == Type::Pointer || !ty.is_signed() {
+            CmpClass::Unsigned
+        } else {
+            CmpClass::Signed
+        }
+    }
+}
+
+// how a relation is read from the flags of `cmp b, a` (a ? b).
+// swap compares b ? a instead, and parity is a second condition that must
+// also hold (NP) or may hold instead (P), for unordered floating operands
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Condition {
+    pub code: CondCode,
+    pub swap: bool,
+    pub parity: Option<CondCode>,
+}
+
+pub fn condition(rel: Relation, class: CmpClass) -> Condition {
+    let cond = |code, swap, parity| Condition { code, swap, parity };
+    match (class, rel) {
+        (CmpClass::Signed | CmpClass::Unsigned, Relation::Equal) => cond(CondCode::E, false, None),
+        (CmpClass::Signed | CmpClass::Unsigned, Relation::NotEqual) => cond(CondCode::NE, false, None),
+
+        (CmpClass::Signed, Relation::Less) => cond(CondCode::L, false, None),
+        (CmpClass::Signed, Relation::LessEqual) => cond(CondCode::LE, false, None),
+        (CmpClass::Signed, Relation::Greater) => cond(CondCode::G, false, None),
+        (CmpClass::Signed, Relation::GreaterEqual) => cond(CondCode::GE, false, None),
+
+        (CmpClass::Unsigned, Relation::Less) => cond(CondCode::B, false, None),
+        (CmpClass::Unsigned, Relation::LessEqual) => cond(CondCode::BE, false, None),
+        (CmpClass::Unsigned, Relation::Greater) => cond(CondCode::A, false, None),
+        (CmpClass::Unsigned, Relation::GreaterEqual) => cond(CondCode::AE, false, None),
+
+        // A and AE are false when unordered, so less-than swaps the operands
+        (CmpClass::Floating, Relation::Equal) => cond(CondCode::E, false, Some(CondCode::NP)),
+        (CmpClass::Floating, Relation::NotEqual) => cond(CondCode::NE, false, Some(CondCode::P)),
+        (CmpClass::Floating, Relation::Less) => cond(CondCode::A, true, None),
+        (CmpClass::Floating, Relation::LessEqual) => cond(CondCode::AE, true, None),
+        (CmpClass::Floating, Relation::Greater) => cond(CondCode::A, false, None),
+        (CmpClass::Floating, Relation::GreaterEqual) => cond(CondCode::AE, false, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the flags `cmp b, a` or `ucomisd b, a` leave for a ? b
+    struct Flags {
+        zf: bool,
+        cf: bool,
+        pf: bool,
+        // SF != OF
+        lt: bool,
+    }
+
+    fn int_flags(a: i64, b: i64) -> Flags {
+        Flags { zf: a == b, cf: (a as u64) < (b as u64), pf: false, lt: a < b }
+    }
+
+    fn float_flags(a: f64, b: f64) -> Flags {
+        if a.is_nan() || b.is_nan() {
+            return Flags { zf: true, cf: true, pf: true, lt: false };
+        }
+        Flags { zf: a == b, cf: a < b, pf: false, lt: false }
+    }
+
+    fn holds(code: CondCode, f: &Flags) -> bool {
+        match code {
+            CondCode::E => f.zf,
+            CondCode::NE => !f.zf,
+            CondCode::L => f.lt,
+            CondCode::GE => !f.lt,
+            CondCode::LE => f.lt || f.zf,
+            CondCode::G => !f.lt && !f.zf,
+            CondCode::B => f.cf,
+            CondCode::AE => !f.cf,
+            CondCode::BE => f.cf || f.zf,
+            CondCode::A => !f.cf && !f.zf,
+            CondCode::P => f.pf,
+            CondCode::NP => !f.pf,
+        }
+    }
+
+    // what the generated code computes for a ? b, with `flags` standing for
+    // the comparison instruction
+    fn evaluate<T: Copy>(cond: Condition, a: T, b: T, flags: fn(T, T) -> Flags) -> bool {
+        let f = if cond.swap { flags(b, a) } else { flags(a, b) };
+        match cond.parity {
+            Some(CondCode::NP) => holds(cond.code, &f) && holds(CondCode::NP, &f),
+            Some(parity) => holds(cond.code, &f) || holds(parity, &f),
+            None => holds(cond.code, &f),
+        }
+    }
+
+    const RELATIONS: [Relation; 6] = [
+        Relation::Equal, Relation::NotEqual, Relation::Less,
+        Relation::LessEqual, Relation::Greater, Relation::GreaterEqual,
+    ];
+
+    fn expected<T: PartialOrd>(rel: Relation, a: T, b: T) -> bool {
+        match rel {
+            Relation::Equal => a == b,
+            Relation::NotEqual => a != b,
+            Relation::Less => a < b,
+            Relation::LessEqual => a <= b,
+            Relation::Greater => a > b,
+            Relation::GreaterEqual => a >= b,
+        }
+    }
+
+    #[test]
+    fn condition_table() {
+        let cases = [
+            (Relation::Equal, CmpClass::Signed, "e", false, None),
+            (Relation::NotEqual, CmpClass::Signed, "ne", false, None),
+            (Relation::Less, CmpClass::Signed, "l", false, None),
+            (Relation::LessEqual, CmpClass::Signed, "le", false, None),
+            (Relation::Greater, CmpClass::Signed, "g", false, None),
+            (Relation::GreaterEqual, CmpClass::Signed, "ge", false, None),
+            (Relation::Equal, CmpClass::Unsigned, "e", false, None),
+            (Relation::NotEqual, CmpClass::Unsigned, "ne", false, None),
+            (Relation::Less, CmpClass::Unsigned, "b", false, None),
+            (Relation::LessEqual, CmpClass::Unsigned, "be", false, None),
+            (Relation::Greater, CmpClass::Unsigned, "a", false, None),
+            (Relation::GreaterEqual, CmpClass::Unsigned, "ae", false, None),
+            (Relation::Equal, CmpClass::Floating, "e", false, Some(CondCode::NP)),
+            (Relation::NotEqual, CmpClass::Floating, "ne", false, Some(CondCode::P)),
+            (Relation::Less, CmpClass::Floating, "a", true, None),
+            (Relation::LessEqual, CmpClass::Floating, "ae", true, None),
+            (Relation::Greater, CmpClass::Floating, "a", false, None),
+            (Relation::GreaterEqual, CmpClass::Floating, "ae", false, None),
+        ];
+        for (rel, class, suffix, swap, parity) in cases {
+            let cond = condition(rel, class);
+            assert_eq!((cond.code.suffix(), cond.swap, cond.parity), (suffix, swap, parity),
+                "failed at: {:?} {:?}", rel, class);
+        }
+    }
+
+    #[test]
+    fn condition_classes() {
+        let types = [
+            (Type::Int, CmpClass::Signed),
+            (Type::Char, CmpClass::Signed),
+            (Type::Long, CmpClass::Signed),
+            (Type::UnsignedInt, CmpClass::Unsigned),
+            (Type::Pointer, CmpClass::Unsigned),
+            (Type::Float, CmpClass::Floating),
+            (Type::Double, CmpClass::Floating),
+        ];
+        for (ty, class) in types {
+            assert_eq!(CmpClass::of(ty), class, "failed at: {:?}", ty);
+        }
+    }
+
+    #[test]
+    fn condition_semantics() {
+        let ints = [0, 1, -1, 5, i64::MIN, i64::MAX];
+        let floats = [0.0, -0.0, 1.5, -2.0, f64::INFINITY, f64::NAN];
+        for rel in RELATIONS {
+            let signed = condition(rel, CmpClass::Signed);
+            let unsigned = condition(rel, CmpClass::Unsigned);
+            let floating = condition(rel, CmpClass::Floating);
+            for a in ints {
+                for b in ints {
+                    assert_eq!(evaluate(signed, a, b, int_flags), expected(rel, a, b),
+                        "failed at: {} {:?} {}", a, rel, b);
+                    assert_eq!(evaluate(unsigned, a, b, int_flags), expected(rel, a as u64, b as u64),
+                        "failed at: {} {:?} {} unsigned", a, rel, b);
+                }
+            }
+            // every relation but != is false when an operand is NaN
+            for a in floats {
+                for b in floats {
+                    assert_eq!(evaluate(floating, a, b, float_flags), expected(rel, a, b),
+                        "failed at: {} {:?} {}", a, rel, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inverted_jumps() {
+        let ints = [0, -1, 7, i64::MIN];
+        for rel in RELATIONS {
+            for class in [CmpClass::Signed, CmpClass::Unsigned] {
+                let cond = condition(rel, class);
+                let inverted = Condition { code: cond.code.invert(), ..cond };
+                assert_eq!(inverted.code.invert(), cond.code);
+                for a in ints {
+                    for b in ints {
+                        assert_eq!(evaluate(inverted, a, b, int_flags), !evaluate(cond, a, b, int_flags),
+                            "failed at: {} {:?} {} {:?}", a, rel, b, class);
+                    }
+                }
+            }
+        }
+        // a floating condition with a parity part is materialized instead,
+        // the others jump on the inverted code, past the body when unordered
+        let floats = [1.0, -2.5, f64::NAN];
+        for rel in RELATIONS {
+            let cond = condition(rel, CmpClass::Floating);
+            if cond.parity.is_some() {
+                continue;
+            }
+            let inverted = Condition { code: cond.code.invert(), ..cond };
+            for a in floats {
+                for b in floats {
+                    assert_eq!(evaluate(inverted, a, b, float_flags), !evaluate(cond, a, b, float_flags),
+                        "failed at: {} {:?} {}", a, rel, b);
+                }
+            }
+        }
+        // `if (n < 0)` jumps past the body when n >= 0, a signed test
+        let jump = condition(Relation::Less, CmpClass::of(Type::Int)).code.invert();
+        assert_eq!(jump.suffix(), "ge");
+        assert!(!holds(jump, &int_flags(-3, 0)));
+        assert!(holds(jump, &int_flags(3, 0)));
+    }
+}
@@ -1,2 +1,3 @@
 pub mod allocation;
-pub mod codegen;
\ No newline at end of file
+pub mod codegen;
+pub mod condition;
\ No newline at end of file
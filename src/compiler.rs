@@ -1,7 +1,7 @@
 
 use std::{  fs, io::{self, Write}, process::Command, thread::sleep, time::{Duration, Instant}};
-use crate::{codegen::{allocation::new_allocator, codegen::new_asm_generator}, intermediate::{analyzer::new_analyzer, frame::Frame, instruction::Instruction, irgen::new_codegen}, optimizer::{cfg::{ControlFlowGraph, create_cfgs}, liveness::{InterferenceGraph, Variable, new_liveness_analyzer}}, parser::{node::{NODE_COUNT, ParserNode}, parser::{Parser, new_parser}}};
-use indexmap::IndexMap;
+use crate::{codegen::{allocation::{clobbered_registers, new_allocator}, codegen::new_asm_generator}, intermediate::{analyzer::{Signature, new_analyzer}, frame::Frame, instruction::Instruction, interpreter::new_interpreter, irgen::{Value, new_codegen}, tac::parse_program, verify::verify}, optimizer::{cfg::{ControlFlowGraph, create_cfgs}, passes::{PassManager, new_pass_manager, optimization_level}, liveness::{InterferenceGraph, Variable, new_liveness_analyzer}}, parser::{node::{NODE_COUNT, ParserNode}, parser::{Parser, new_parser}, token::Type}};
+use indexmap::{IndexMap, IndexSet};
 
 static CALCULATE_TIME: bool = true;
 
@@ -9,6 +9,7 @@ static PARSE_INFO: bool = false;
 
 static TAC_PATH: &str = "tac.txt";
 static MAKE_TAC_FILE: bool = true;
+static VERIFY_IR: bool = true;
 
 static PRINT_BLOCKS: bool = false;
 static CFG_INFO: bool = false;
@@ -20,21 +21,41 @@ static ASM_CODE_OBJECT: &str = "code.o";
 static HELPER_FILE: &str = "main.c";
 static EXECUTABLE: &str = "exec.exe";
 
+// how far the pipeline runs, `Run` interprets the TAC instead of linking
+#[derive(Clone, PartialEq)]
+pub enum Emit {
+    Tac,
+    Run { entry: String, args: Vec<Value> },
+    Executable,
+}
+
 pub struct Compiler {
+    pub emit: Emit,
+    pub pass_manager: PassManager,
     program_node: ParserNode,
     instructions: Vec<Instruction>,
     frames: IndexMap<String, Frame>,
+    signatures: IndexMap<String, Signature>,
+    strings: IndexMap<String, String>,
     cfgs: Vec<ControlFlowGraph>,
     interference_graphs: Vec<InterferenceGraph>,
+    live_outs: Vec<Vec<IndexSet<String>>>,
 }
 
 pub fn new_compiler() -> Compiler {
+    let mut pass_manager = new_pass_manager(optimization_level("1").unwrap());
+    pass_manager.verify = VERIFY_IR;
     Compiler {
+        emit: Emit::Executable,
+        pass_manager,
         program_node: ParserNode::Block(Vec::new()),
         instructions: Vec::new(),
         frames: IndexMap::new(),
+        signatures: IndexMap::new(),
+        strings: IndexMap::new(),
         cfgs: Vec::new(),
         interference_graphs: Vec::new(),
+        live_outs: Vec::new(),
       }
 }
 
@@ -48,9 +69,22 @@ impl Compiler {
 
         self.generate_ir();
 
+        self.verify_ir();
+
+        if self.emit == Emit::Tac {
+            return;
+        }
+
+        self.optimize();
+
+        if let Emit::Run { .. } = self.emit {
+            return self.interpret();
+        }
+
         self.generate_cfgs();
 
-        self.generate_assembly();
+        let output = self.generate_assembly();
+        self.write_assembly(output);
         
         if CALCULATE_TIME {
             let end_time = now.elapsed();
@@ -60,6 +94,49 @@ impl Compiler {
         self.linker_run();
     }
 
+    // starts the pipeline from a textual TAC file instead of C
+    pub fn compile_tac(&mut self, file_path: &str) {
+        let now = Instant::now();
+
+        self.read_tac(file_path);
+
+        self.verify_ir();
+
+        self.optimize();
+
+        if let Emit::Run { .. } = self.emit {
+            return self.interpret();
+        }
+
+        self.generate_cfgs();
+
+        let output = self.generate_assembly();
+        self.write_assembly(output);
+
+        if CALCULATE_TIME {
+            let end_time = now.elapsed();
+            println!("\ncompile duration: {} ms", end_time.as_millis());
+        }
+
+        self.linker_run();
+    }
+
+    fn read_tac(&mut self, file_path: &str) {
+        println!("\n------------------------ IR ------------------------");
+        println!("Reading TAC file '{file_path}'");
+        let input = fs::read_to_string(file_path).expect("file not found");
+        let program = match parse_program(&input) {
+            Ok(v) => v,
+            Err(e) => panic!("{}", e)
+        };
+        self.instructions = program.instructions;
+        self.frames = program.frames;
+        self.signatures = program.signatures;
+        self.strings = program.strings;
+        println!("- Instruction List created\ninstruction count: {}", self.instructions.len());
+        println!("- Function Frames created\nframe count: {}", self.frames.len());
+    }
+
     fn parse(&mut self, file_path: &str) {
         println!("\n-------------------- SOURCE CODE -------------------");
         println!("Reading file '{file_path}'");
@@ -97,12 +174,13 @@ impl Compiler {
             
         }
         self.frames = analyzer.function_frames;
+        self.signatures = analyzer.function_signatures;
     }
 
     fn generate_ir(&mut self) {
         println!("\n------------------------ IR ------------------------");
         println!("Starting Intermediate Representation Generation:");
-        let mut code_gen = new_codegen(self.frames.clone());
+        let mut code_gen = new_codegen(self.frames.clone(), self.signatures.clone());
         println!("- Three Adress Code Gen created");
         code_gen.cgen(&self.program_node);
 
@@ -112,11 +190,57 @@ impl Compiler {
         }
         
         self.instructions = code_gen.instructions;
+        self.strings = code_gen.strings;
         println!("- Instruction List created\ninstruction count: {}", self.instructions.len());
         let frames = code_gen.frames;
         println!("- Function Frames created\nframe count: {}", frames.len());
     }
 
+    fn verify_ir(&self) {
+        if !VERIFY_IR {
+            return;
+        }
+        match verify(&self.instructions, &self.signatures) {
+            Ok(_) => println!("- IR verified"),
+            Err(e) => panic!("{}", e)
+        }
+    }
+
+    fn optimize(&mut self) {
+        println!("\n--------------------- OPTIMIZER --------------------");
+        if self.pass_manager.passes.is_empty() {
+            println!("- No passes to run");
+            return;
+        }
+        let names: Vec<&str> = self.pass_manager.passes.iter().map(|p| p.name()).collect();
+        println!("Running passes: {}", names.join(", "));
+        self.instructions = match self.pass_manager.run(&self.instructions, &mut self.frames, &self.signatures) {
+            Ok(v) => v,
+            Err(e) => panic!("{}", e)
+        };
+        for (name, time) in &self.pass_manager.timings {
+            println!("- {}: {:.3} ms", name, time.as_secs_f64() * 1000.0);
+        }
+        println!("instruction count: {}", self.instructions.len());
+        self.verify_ir();
+    }
+
+    fn interpret(&self) {
+        let Emit::Run { entry, args } = &self.emit else { return };
+        println!("\n-------------------- INTERPRETER -------------------");
+        println!("- Running '{}' on the TAC", entry);
+        let mut interpreter = new_interpreter(&self.instructions, &self.frames, &self.strings);
+        let result = interpreter.run(entry, args);
+        print!("{}", interpreter.output);
+        match result {
+            Ok(value) => {
+                let ret_type = self.frames.get(entry).map_or(Type::Int, |f| f.ret_type);
+                println!("result '{}': {}", entry, value.to_string(ret_type));
+            },
+            Err(e) => println!("{}", e),
+        }
+    }
+
     fn generate_cfgs(&mut self) {
         println!("\n---------------- CONTROL FLOW GRAPH ----------------");
         println!("Starting Block Building");
@@ -146,7 +270,9 @@ impl Compiler {
                 println!("block count: {}", block_count);
             }
             lv_analyzer.gen_inst_live_out();    
-            lv_analyzer.create_interference_graph();
+            lv_analyzer.create_interference_graph(clobbered_registers);
+            lv_analyzer.weigh_spill_costs(&cfg.loop_depths());
+            self.live_outs.push((0..=b - a).map(|i| lv_analyzer.live_out(i).clone()).collect());
             
             for l in lv_analyzer.inst_liveness {
                 println!("{:?}", l);
@@ -159,21 +285,15 @@ impl Compiler {
             }
             self.interference_graphs.push(lv_analyzer.interference_graph); 
 
-            // handle callstart
-            let mut i = 0;
-            for inst in &mut self.instructions[a..=b] {
+            for inst in &self.instructions[a..=b] {
                 println!("{}", inst.print());
-                if let Instruction::CallStart(ops) = inst {
-                    *ops = lv_analyzer.callstarts[i].clone();
-                    i += 1;
-                }
             }
             
         }
         
     }
 
-    fn generate_assembly(&mut self) {
+    fn generate_assembly(&mut self) -> String {
         println!("\n---------------------- CODEGEN ---------------------");
         println!("Starting Register Allocation:");
         
@@ -185,7 +305,14 @@ impl Compiler {
         }
 
         let mut output = String::new();
-        for ig in self.interference_graphs.iter() {
+        if !self.strings.is_empty() {
+            output.push_str(".section .rodata\n");
+            for (s, label) in self.strings.iter() {
+                output.push_str(format!("{}:\n\t.string {}\n", label, escape_string(s)).as_str());
+            }
+            output.push_str(".text\n");
+        }
+        for (ig, live_out) in self.interference_graphs.iter().zip(&self.live_outs) {
             // setup frame
             let curr_frame = *frames.first().unwrap();
             frames.remove(0);
@@ -206,13 +333,16 @@ impl Compiler {
             let mut asm_gen = new_asm_generator(self.instructions[start..=end].to_vec(),
                 curr_frame.clone(), 
                 allocator.ifr_graph.variables, 
-                allocator.spill);
+                allocator.spill,
+                live_out.clone(),
+                self.signatures.clone());
             asm_gen.generate_assembly();
             output.push_str(asm_gen.print_asm().as_str());
         }
+        output
+    }
 
-        
-
+    fn write_assembly(&self, output: String) {
         if MAKE_ASM_FILE {
             fs::write(ASM_PATH, output).expect("write file failed");
             println!("- TAC file created at '{ASM_PATH}'");
@@ -261,4 +391,71 @@ impl Compiler {
 
     }
 
-}
\ No newline at end of file
+}
+
+// string literals are written with the escapes understood by the assembler
+fn escape_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\000"),
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{env, sync::atomic::{AtomicUsize, Ordering}};
+
+    use crate::optimizer::{passes::Pass, tests::optimize_flat};
+
+    use super::*;
+
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    // what `input` prints once optimized with `passes`, assembled and linked
+    // with the main of HELPER_FILE in its own temp directory
+    pub fn run_native(input: &str, passes: Vec<Pass>) -> String {
+        let (code_gen, _) = optimize_flat(input, |code_gen| code_gen.instructions.clone());
+        let mut compiler = new_compiler();
+        compiler.pass_manager.passes = passes;
+        compiler.instructions = code_gen.instructions;
+        compiler.frames = code_gen.frames;
+        compiler.signatures = code_gen.signatures;
+        compiler.strings = code_gen.strings;
+        compiler.optimize();
+        compiler.generate_cfgs();
+        let output = compiler.generate_assembly();
+
+        let dir = env::temp_dir().join(format!("compilador-{}-{}", std::process::id(), RUNS.fetch_add(1, Ordering::Relaxed)));
+        fs::create_dir_all(&dir).expect("temp dir failed");
+        fs::write(dir.join(ASM_PATH), output).expect("write file failed");
+        let gcc = Command::new("gcc").arg(HELPER_FILE).arg(dir.join(ASM_PATH))
+            .arg("-o").arg(dir.join(EXECUTABLE))
+            .output().expect("gcc error");
+        assert!(gcc.status.success(), "failed at: {}\n{}", input, String::from_utf8_lossy(&gcc.stderr));
+        let run = Command::new(dir.join(EXECUTABLE)).output().expect("run error");
+        fs::remove_dir_all(&dir).expect("temp dir failed");
+        String::from_utf8_lossy(&run.stdout).to_string()
+    }
+
+    #[test]
+    fn comparison_used_after_branch() {
+        let input = "long g(int a, int b) { int c = a < b; int x = 5; if (c) { x = 1; } return c + x; }
+            long h(int x, int t, int a) { int v = x && t; if (v) { a = a * 2; } return v + a; }
+            int func() { int k; int r = 0;
+            for (k = 0; k < 5; k = k + 1) { r = r * 10 + g(k * 7 - 10, 13 - k); }
+            for (k = 0; k < 4; k = k + 1) { r = r * 10 + h(k & 1, k & 2, k); }
+            return r; }";
+        let got = run_native(input, optimization_level("1").unwrap());
+        assert_eq!(got, "result 'func': 222550127\n", "failed at: {}", input);
+    }
+}
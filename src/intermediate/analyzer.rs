@@ -9,6 +9,7 @@ static DEBUG_ANALYZER: bool = false;
 pub struct SemanticAnalyzer {
     symbol_table: Vec<IndexMap<String, Symbol>>,
     pub function_frames: IndexMap<String, Frame>,
+    pub function_signatures: IndexMap<String, Signature>,
     current_frame: Option<Frame>,
     scope_count: usize,
 }
@@ -25,8 +26,16 @@ pub struct Symbol {
 #[derive(Debug, Clone)]
 pub enum SymbolKind {
     Variable { initialized: bool },
-    Function { args_size: usize },
+    Function { signature: Signature, defined: bool },
 }
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub ret_type: Type,
+    pub params: Vec<Type>,
+    pub variadic: bool,
+}
+
 #[derive(Debug)]
 pub enum AnalyzerError {
     UndeclaredVar{var: String, last_func: String},
@@ -59,6 +68,7 @@ pub fn new_analyzer() -> SemanticAnalyzer {
     SemanticAnalyzer {
         symbol_table: vec!(IndexMap::new()),
         function_frames: IndexMap::new(),
+        function_signatures: IndexMap::new(),
         current_frame: None,
         scope_count: 0,
     }
@@ -86,7 +96,7 @@ impl SemanticAnalyzer {
                 self.symbol_table.pop().unwrap();
                 if self.scope_count != 0 { self.scope_count -= 1}
             },
-            ParserNode::FuncDecl { ident, args, block, ntype} => {
+            ParserNode::FuncDecl { ident, args, block, ntype, variadic, inline } => {
                 
                 let name = self.get_ident(ident)?;
                 if self.scope_count != 0 {
@@ -101,17 +111,32 @@ impl SemanticAnalyzer {
 
                 if DEBUG_ANALYZER { println!("DEBUG_ANALYZER: new function frame: {}", name.clone())}
 
-                self.current_frame = Some(new_frame(name.clone()));
-                self.declare_function(&name, args.len(), ntype.clone())?;
+                let mut frame = new_frame(name.clone(), *ntype);
+                frame.variadic = *variadic;
+                frame.inline = *inline;
+                self.current_frame = Some(frame);
+                let signature = Signature { ret_type: *ntype, params: param_types(args), variadic: *variadic };
+                self.declare_function(&name, signature, true)?;
                 self.new_scope();
 
                 for arg in args {
                     if let ParserNode::Var { ident, ntype } = arg {
+                        if ident.is_empty() {
+                            return Err(AnalyzerError::InvalidNode(format!("parameter name omitted in '{}'", name)))
+                        }
                         self.declare_param(ident, true, *ntype)?;
                     }
                 }
                 self.analyze_node(block)?;
             },
+            ParserNode::FuncProto { ident, args, ntype, variadic } => {
+                let name = self.get_ident(ident)?;
+                if self.scope_count != 0 {
+                    return Err(AnalyzerError::InvalidNode(format!("'{}' decl inside block", name)))
+                }
+                let signature = Signature { ret_type: *ntype, params: param_types(args), variadic: *variadic };
+                self.declare_function(&name, signature, false)?;
+            },
             ParserNode::Declare { ident, exp, ntype } => {
                 let name = self.get_ident(ident)?;
                 let decl_type = *ntype;
@@ -123,8 +148,9 @@ impl SemanticAnalyzer {
                 }
                 match exp {
                     Some(n) => {
-                        let mut type2 = self.analyze_node(n)?;
-                        self.expect_type(ntype, &mut type2)?;
+                        let type2 = self.analyze_node(n)?;
+                        self.expect_type(ntype, &type2)?;
+                        convert(n, type2, *ntype);
                         self.declare_variable(&name, true, *ntype)?;
                     },
                     None => self.declare_variable(&name, false, *ntype)?,
@@ -140,8 +166,12 @@ impl SemanticAnalyzer {
                     return Err(AnalyzerError::UndeclaredVar{ var:name, last_func: self.frame_string()});
                 }
                 let type1 = self.initialize_variable(&name)?;
-                let mut type2 = self.analyze_node(right)?;
-                self.expect_type(&type1, &mut type2)?;
+                if let ParserNode::Var { ntype, .. } = left.as_mut() {
+                    *ntype = type1;
+                }
+                let type2 = self.analyze_node(right)?;
+                self.expect_type(&type1, &type2)?;
+                convert(right, type2, type1);
 
                 return Ok(type1);
             },
@@ -175,7 +205,12 @@ impl SemanticAnalyzer {
                 }
             },
             ParserNode::Return { exp } => {
-                self.analyze_node(exp)?;
+                let type1 = self.analyze_node(exp)?;
+                if let Some(frame) = &self.current_frame {
+                    let ret_type = frame.ret_type;
+                    self.expect_type(&ret_type, &type1)?;
+                    convert(exp, type1, ret_type);
+                }
             },
 
             ParserNode::Expression(nodes) => {
@@ -185,45 +220,114 @@ impl SemanticAnalyzer {
             },
 
             ParserNode::Add {left, right} | ParserNode::Sub {left, right} |
-            ParserNode::Mul {left, right} | ParserNode::Div {left, right} |
-            ParserNode::Mod {left, right} | ParserNode::ShiftLeft {left, right} |
-            ParserNode::ShiftRight {left, right} | ParserNode::Greater {left, right} |
-            ParserNode::GreaterEqual {left, right} | ParserNode::Less {left, right} | 
-            ParserNode::LessEqual {left, right} | ParserNode::Equal {left, right} |
-            ParserNode::NotEqual {left, right} | ParserNode::BitwiseAnd {left, right} |
-            ParserNode::BitwiseXor {left, right} | ParserNode::BitwiseOr {left, right} |
+            ParserNode::Mul {left, right} | ParserNode::Div {left, right} => {
+                let type1 = self.analyze_node(left)?;
+                let type2 = self.analyze_node(right)?;
+                self.expect_type(&type1, &type2)?;
+                let ctype = type1.common(&type2);
+                convert(left, type1, ctype);
+                convert(right, type2, ctype);
+                return Ok(ctype);
+            },
+            ParserNode::Mod {left, right} | ParserNode::BitwiseAnd {left, right} |
+            ParserNode::BitwiseXor {left, right} | ParserNode::BitwiseOr {left, right} => {
+                let type1 = self.analyze_node(left)?;
+                let type2 = self.analyze_node(right)?;
+                self.expect_integer(&type1, &type2)?;
+                let ctype = type1.common(&type2);
+                convert(left, type1, ctype);
+                convert(right, type2, ctype);
+                return Ok(ctype);
+            },
+            ParserNode::ShiftLeft {left, right} | ParserNode::ShiftRight {left, right} => {
+                let type1 = self.analyze_node(left)?;
+                let type2 = self.analyze_node(right)?;
+                self.expect_integer(&type1, &type2)?;
+                convert(left, type1, type1.promote());
+                convert(right, type2, type2.promote());
+                return Ok(type1.promote());
+            },
+            ParserNode::Greater {left, right} | ParserNode::GreaterEqual {left, right} | 
+            ParserNode::Less {left, right} | ParserNode::LessEqual {left, right} | 
+            ParserNode::Equal {left, right} | ParserNode::NotEqual {left, right} => {
+                let type1 = self.analyze_node(left)?;
+                let type2 = self.analyze_node(right)?;
+                self.expect_type(&type1, &type2)?;
+                let ctype = type1.common(&type2);
+                convert(left, type1, ctype);
+                convert(right, type2, ctype);
+                return Ok(Type::Int);
+            },
             ParserNode::LogicalAnd {left, right} | ParserNode::LogicalOr {left, right} => {
                 let type1 = self.analyze_node(left)?;
-                let mut type2 = self.analyze_node(right)?;
-                self.expect_type(&type1, &mut type2)?;
-                return Ok(type1);
+                let type2 = self.analyze_node(right)?;
+                self.expect_type(&type1, &type2)?;
+                return Ok(Type::Int);
             },
 
-            ParserNode::Neg { val } | ParserNode::Complement { val } |
-            ParserNode::Not { val } | ParserNode::SubExp { val } => {
+            ParserNode::Neg { val } => {
+                let type1 = self.analyze_node(val)?;
+                convert(val, type1, type1.promote());
+                return Ok(type1.promote());
+            },
+            ParserNode::Complement { val } => {
+                let type1 = self.analyze_node(val)?;
+                self.expect_integer(&type1, &type1)?;
+                convert(val, type1, type1.promote());
+                return Ok(type1.promote());
+            },
+            ParserNode::Not { val } => {
+                self.analyze_node(val)?;
+                return Ok(Type::Int);
+            },
+            ParserNode::SubExp { val } => {
                 return self.analyze_node(val);
             },
 
-            ParserNode::FuncCall { ident, args } => {
-                let mut _ntype = Type::Void;
-                match self.get_symbol(&ident) {
-                    
-                    Some(s) => {
-                        _ntype = s.stype;
-                        match s.kind {
-                            SymbolKind::Function { args_size } => {
-                                if args.len() != args_size {
-                                    return Err(AnalyzerError::InvalidArguments("argument count invalid".into()));
-                                }
-                            }
-                            _ => return Err(AnalyzerError::InvalidNode(ident.clone())),
+            ParserNode::Cast { ntype, from, val } => {
+                let type1 = self.analyze_node(val)?;
+                self.expect_cast(ntype, &type1)?;
+                *from = type1;
+                return Ok(*ntype);
+            },
+            ParserNode::SizeOf { ntype, val } => {
+                if let Some(val) = val {
+                    *ntype = self.analyze_node(val)?;
+                }
+                if *ntype == Type::Void {
+                    return Err(AnalyzerError::InvalidNode("sizeof applied to 'void'".into()));
+                }
+                // typed as int since there are no long constants
+                return Ok(Type::Int);
+            },
 
-                        }
-                    }
+            ParserNode::FuncCall { ident, args } => {
+                let signature = match self.get_symbol(ident) {
+                    Some(s) => match &s.kind {
+                        SymbolKind::Function { signature, .. } => signature.clone(),
+                        _ => return Err(AnalyzerError::InvalidNode(ident.clone())),
+                    },
                     None => return Err(AnalyzerError::UndeclaredVar{ var:ident.clone(), last_func: self.frame_string()}),
+                };
+                let params = &signature.params;
+                if args.len() < params.len() || (!signature.variadic && args.len() != params.len()) {
+                    return Err(AnalyzerError::InvalidArguments(
+                        format!("'{}' expects {} arguments, found {}", ident, params.len(), args.len())));
+                }
+                // arguments are converted to the parameter types of the prototype
+                for (i, arg) in args.iter_mut().enumerate() {
+                    let arg_type = self.analyze_node(arg)?;
+                    let param = match params.get(i) {
+                        Some(param) => *param,
+                        // default argument promotions for the variadic part
+                        None if arg_type == Type::Float => Type::Double,
+                        None => arg_type.promote(),
+                    };
+                    self.expect_type(&param, &arg_type)?;
+                    convert(arg, arg_type, param);
                 }
 
-                return Ok(_ntype);
+                return Ok(signature.ret_type);
             }
 
             ParserNode::Const(val) => {
@@ -235,9 +339,33 @@ impl SemanticAnalyzer {
                     ConstValue::Void => return Ok(Type::Void),
                 }
             },
-            ParserNode::Var {ident, ..} => {
+            ParserNode::StringLiteral(_) => {
+                return Ok(Type::Pointer);
+            },
+            ParserNode::Var {ident, ntype} => {
                 self.is_initialized(&ident)?;
-                return self.initialize_variable(ident);
+                *ntype = self.initialize_variable(ident)?;
+                return Ok(*ntype);
+            },
+
+            ParserNode::VaStart { list, last } => {
+                let variadic = self.current_frame.as_ref().is_some_and(|f| f.variadic);
+                if !variadic {
+                    return Err(AnalyzerError::InvalidNode(format!("va_start used in non-variadic '{}'", self.frame_string())));
+                }
+                let name = self.get_ident(list)?;
+                let list_type = self.initialize_variable(&name)?;
+                self.expect_type(&Type::Pointer, &list_type)?;
+                self.analyze_node(list)?;
+                self.analyze_node(last)?;
+            },
+            ParserNode::VaArg { list, ntype } => {
+                let list_type = self.analyze_node(list)?;
+                self.expect_type(&Type::Pointer, &list_type)?;
+                return Ok(*ntype);
+            },
+            ParserNode::VaEnd { list } => {
+                self.analyze_node(list)?;
             },
             
 
@@ -306,21 +434,32 @@ impl SemanticAnalyzer {
         Err(AnalyzerError::UndeclaredVar{ var:name.clone(), last_func: self.frame_string()})
     }
 
-    fn declare_function(&mut self, name: &String, args_size: usize, ntype: Type) -> Result<(), AnalyzerError> {
-        if self.is_declared(name)? {
-            return Err(AnalyzerError::AlreadyDeclared("function already exists".into()));
+    fn declare_function(&mut self, name: &String, signature: Signature, defined: bool) -> Result<(), AnalyzerError> {
+        let mut defined = defined;
+        if let Some(s) = self.get_symbol(name) {
+            let SymbolKind::Function { signature: prev_signature, defined: prev_defined } = &s.kind else {
+                return Err(AnalyzerError::AlreadyDeclared(format!("'{}' already exists.", name)));
+            };
+            if *prev_signature != signature {
+                return Err(AnalyzerError::InvalidArguments(format!("conflicting types for '{}'", name)));
+            }
+            if defined && *prev_defined {
+                return Err(AnalyzerError::AlreadyDeclared("function already exists".into()));
+            }
+            defined |= *prev_defined;
         }
         let scope = self.scope_count;
         self.current_table()?.insert(
             name.clone(), 
             Symbol {
                 name: name.clone(),
-                kind: SymbolKind::Function { args_size: args_size },
+                kind: SymbolKind::Function { signature: signature.clone(), defined },
                 scope: scope,
                 offset: 0, 
-                stype: ntype, },
+                stype: signature.ret_type, },
                 
             );
+        self.function_signatures.insert(name.clone(), signature);
         self.debug_print();
         Ok(())
     }
@@ -378,16 +517,30 @@ impl SemanticAnalyzer {
         self.symbol_table.push(IndexMap::new());
     }
 
-    fn expect_type(&mut self, type1: &Type, type2: &mut Type) -> Result<(), AnalyzerError> {
-        if type1 == type2 {
+    fn expect_type(&mut self, type1: &Type, type2: &Type) -> Result<(), AnalyzerError> {
+        if type1 == type2 || (type1.is_number() && type2.is_number()) {
             Ok(())
-        } else if type1.is_number() && type2.is_number() {
-            *type2 = *type1;
+        } else {
+            Err(AnalyzerError::TypeMismatch{type1: *type1, type2: *type2, last_func: self.frame_string()})
+        }  
+    }
+
+    // pointers only convert to and from integers
+    fn expect_cast(&mut self, type1: &Type, type2: &Type) -> Result<(), AnalyzerError> {
+        let pointer_like = |t: &Type| t.is_integer() || *t == Type::Pointer;
+        if self.expect_type(type1, type2).is_ok() || (pointer_like(type1) && pointer_like(type2)) {
             Ok(())
         } else {
+            Err(AnalyzerError::TypeMismatch{type1: *type1, type2: *type2, last_func: self.frame_string()})
+        }
+    }
 
+    fn expect_integer(&mut self, type1: &Type, type2: &Type) -> Result<(), AnalyzerError> {
+        if type1.is_integer() && type2.is_integer() {
+            Ok(())
+        } else {
             Err(AnalyzerError::TypeMismatch{type1: *type1, type2: *type2, last_func: self.frame_string()})
-        }  
+        }
     }
 
     fn debug_print(&self) {
@@ -422,6 +575,23 @@ impl SemanticAnalyzer {
 }
 
 
+// wraps a node in an implicit cast when its type differs from the expected one
+fn convert(node: &mut ParserNode, from: Type, to: Type) {
+    if from == to {
+        return;
+    }
+    let val = std::mem::replace(node, ParserNode::Const(ConstValue::Void));
+    *node = ParserNode::Cast { ntype: to, from, val: Box::from(val) };
+}
+
+fn param_types(args: &[ParserNode]) -> Vec<Type> {
+    args.iter().filter_map(|arg| match arg {
+        ParserNode::Var { ntype, .. } => Some(*ntype),
+        _ => None,
+    }).collect()
+}
+
+
 #[cfg(test)]
 mod tests {
     use crate::parser::parser::new_parser;
@@ -450,6 +620,127 @@ mod tests {
         }
     }
 
+    #[test]
+    fn analyzer_cast() {
+        let cases = [
+            ("int f() { double d = 2; return (int)d + sizeof(d); }", "int f() {double d = (double)2;\nreturn ((int)d + sizeof (d));\n}"),
+            ("long f(char c) { return c * 2; }", "long f(char c) {return (long)((int)c * 2);\n}"),
+            ("int f(unsigned int u, long l) { return u < l; }", "int f(unsigned int u, long l) {return ((long)u < l);\n}"),
+            ("float f(float a) { return a + 1.5; }", "float f(float a) {return (float)((double)a + 1.5);\n}"),
+        ];
+
+        for (input, expected) in cases {
+            let mut analyzer = new_analyzer();
+            let mut parser = new_parser(input).unwrap();
+            let mut program_node = parser.parse().unwrap();
+
+            let got = analyzer.analyze(&mut program_node);
+            assert!(matches!(got, Ok(_)), "failed at: {}", input);
+            assert_eq!(expected, program_node.to_string(), "failed at: {}", input);
+        }
+
+        let invalid = [
+            "int f() { return sizeof(void); }",
+            "int f(double d) { return d % 2; }",
+            "int f(double d) { return ~d; }",
+        ];
+        for input in invalid {
+            let mut analyzer = new_analyzer();
+            let mut parser = new_parser(input).unwrap();
+            let mut program_node = parser.parse().unwrap();
+
+            let got = analyzer.analyze(&mut program_node);
+            assert!(matches!(got, Err(_)), "failed at: {}", input);
+        }
+    }
+
+    #[test]
+    fn analyzer_prototype() {
+        let valid = [
+            "int f(int, double); int g() { return f(1, 2); } int f(int a, double b) { return a; }",
+            "extern int abs(int); int g() { return abs(2); }",
+            "void *malloc(unsigned long); void free(void *p); int g() { void *p = malloc(8); free(p); return 0; }",
+            "int f(void); int f(void); int g() { return f(); }",
+        ];
+        for input in valid {
+            let mut analyzer = new_analyzer();
+            let mut parser = new_parser(input).unwrap();
+            let mut program_node = parser.parse().unwrap();
+
+            let got = analyzer.analyze(&mut program_node);
+            assert!(matches!(got, Ok(_)), "failed at: {}", input);
+        }
+
+        let invalid = [
+            "int g() { return printf(1); }",
+            "int f(int); int g() { return f(1, 2); }",
+            "int f(int); double f(int a) { return a; }",
+            "int f(int a) { return a; } int f(int b) { return b; }",
+            "void free(void *p); int g() { free(2); return 0; }",
+            "int f(int) { return 0; }",
+        ];
+        for input in invalid {
+            let mut analyzer = new_analyzer();
+            let mut parser = new_parser(input).unwrap();
+            let mut program_node = parser.parse().unwrap();
+
+            let got = analyzer.analyze(&mut program_node);
+            assert!(matches!(got, Err(_)), "failed at: {}", input);
+        }
+    }
+
+    #[test]
+    fn analyzer_variadic() {
+        let valid = [
+            "int printf(char *fmt, ...); int g() { float f = 1.5; printf(\"%d %f %c\", 1, f, 'a'); return 0; }",
+            "int printf(char *fmt, ...); int g() { return printf(\"hi\"); }",
+            "int s(int n, ...) { va_list ap; va_start(ap, n); int x = va_arg(ap, int); va_end(ap); return x; }",
+            "double s(int n, ...) { va_list ap; va_start(ap, n); double x = va_arg(ap, double); va_end(ap); return x; }",
+        ];
+        for input in valid {
+            let mut analyzer = new_analyzer();
+            let mut parser = new_parser(input).unwrap();
+            let mut program_node = parser.parse().unwrap();
+
+            let got = analyzer.analyze(&mut program_node);
+            assert!(matches!(got, Ok(_)), "failed at: {}", input);
+        }
+
+        let invalid = [
+            "int printf(char *fmt, ...); int g() { return printf(); }",
+            "int printf(char *fmt, ...); int printf(char *fmt); int g() { return 0; }",
+            "int s(int n) { va_list ap; va_start(ap, n); return 0; }",
+            "int s(int n, ...) { int ap = 0; va_start(ap, n); return 0; }",
+        ];
+        for input in invalid {
+            let mut analyzer = new_analyzer();
+            let mut parser = new_parser(input).unwrap();
+            let mut program_node = parser.parse().unwrap();
+
+            let got = analyzer.analyze(&mut program_node);
+            assert!(matches!(got, Err(_)), "failed at: {}", input);
+        }
+    }
+
+    #[test]
+    fn analyzer_param_locations() {
+        use crate::intermediate::frame::ParamLocation;
+        let input = "int f(int a, double b, int c, int d, int e, int x, int g, int h, float i) { return a; }";
+        let mut analyzer = new_analyzer();
+        let mut parser = new_parser(input).unwrap();
+        let mut program_node = parser.parse().unwrap();
+        analyzer.analyze(&mut program_node).unwrap();
+
+        let frame = &analyzer.function_frames["f"];
+        assert_eq!(frame.param_locations, vec![
+            ParamLocation::Reg(0), ParamLocation::Sse(0), ParamLocation::Reg(1), ParamLocation::Reg(2),
+            ParamLocation::Reg(3), ParamLocation::Reg(4), ParamLocation::Reg(5), ParamLocation::Stack(16),
+            ParamLocation::Sse(1),
+        ]);
+        assert_eq!(frame.params[7].offset, 16);
+        assert_eq!(frame.stack_params_size(), 8);
+    }
+
     #[test]
     fn analyzer_expression() { 
         let cases = [
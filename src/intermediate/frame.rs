@@ -1,19 +1,40 @@
 use crate::{intermediate::analyzer::{Symbol, SymbolKind}, parser::token::Type};
 
+// where a parameter arrives under the System V calling convention:
+// the n-th integer or floating argument register, or a positive %rbp offset
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParamLocation {
+    Reg(usize),
+    Sse(usize),
+    Stack(i32),
+}
+
+const ARG_REGISTER_COUNT: usize = 6;
+const SSE_REGISTER_COUNT: usize = 8;
+
 #[derive(Clone, Debug)]
 pub struct Frame {
     pub name: String,
+    pub ret_type: Type,
+    pub variadic: bool,
+    // declared `inline`, a hint for the inliner
+    pub inline: bool,
     pub params: Vec<Symbol>,
+    pub param_locations: Vec<ParamLocation>,
     locals: Vec<Symbol>,
     pub params_size: usize,
     pub locals_size: usize,
     pub range: (usize, usize),
 }
 
-pub fn new_frame(name: String) -> Frame {
+pub fn new_frame(name: String, ret_type: Type) -> Frame {
     Frame { 
         name, 
-         params: Vec::new(), 
+        ret_type,
+        variadic: false,
+        inline: false,
+        params: Vec::new(), 
+        param_locations: Vec::new(),
         locals: Vec::new(),
         params_size: 0,
         locals_size: 0,
@@ -36,15 +57,42 @@ impl Frame {
     }
 
     pub fn allocate_param(&mut self, name: String, scope: usize, stype: Type) -> &Symbol {
+        let location = self.next_param_location(stype);
+        // register params have no home in the caller's frame
+        let offset = match location {
+            ParamLocation::Stack(offset) => offset,
+            _ => 0,
+        };
         let param = Symbol {
             name,
             kind: SymbolKind::Variable { initialized: true },
             scope,
-            offset:  ((self.locals_size + stype.size()) as i32),
+            offset,
             stype,
         };
         self.params.push(param);
+        self.param_locations.push(location);
         self.params_size += stype.size();
         &self.params.last().unwrap()
     }
-}
\ No newline at end of file
+
+    fn next_param_location(&self, stype: Type) -> ParamLocation {
+        let locations = self.param_locations.iter();
+        let regs = locations.clone().filter(|l| matches!(l, ParamLocation::Reg(_))).count();
+        let sse = locations.clone().filter(|l| matches!(l, ParamLocation::Sse(_))).count();
+        let stack = locations.filter(|l| matches!(l, ParamLocation::Stack(_))).count();
+
+        if stype.is_floating() && sse < SSE_REGISTER_COUNT {
+            return ParamLocation::Sse(sse);
+        }
+        if !stype.is_floating() && regs < ARG_REGISTER_COUNT {
+            return ParamLocation::Reg(regs);
+        }
+        // above the saved %rbp and the return address, 8 bytes per slot
+        ParamLocation::Stack(16 + 8 * stack as i32)
+    }
+
+    pub fn stack_params_size(&self) -> usize {
+        self.param_locations.iter().filter(|l| matches!(l, ParamLocation::Stack(_))).count() * 8
+    }
+}
@@ -1,4 +1,6 @@
 use crate::intermediate::irgen::Operand;
+use crate::parser::node::ConstValue;
+use crate::parser::token::Type;
 
 // todo:
 // declare
@@ -17,8 +19,7 @@ pub enum Instruction {
     BeginFunc(usize),
     EndFunc,
 
-    CallStart(Vec<Operand>),
-    PushParam(Operand),
+    PushParam(Operand, Type),
     PopParams(usize),
     
     LCall(String),
@@ -72,6 +73,19 @@ pub enum Instruction {
     Complement {dest: Operand, arg1: Operand},
     Not {dest: Operand, arg1: Operand},
 
+    // conversion
+    SignExtend {dest: Operand, arg1: Operand, from: Type, to: Type},
+    ZeroExtend {dest: Operand, arg1: Operand, from: Type, to: Type},
+    Truncate {dest: Operand, arg1: Operand, from: Type, to: Type},
+    IntToFloat {dest: Operand, arg1: Operand, from: Type, to: Type},
+    FloatToInt {dest: Operand, arg1: Operand, from: Type, to: Type},
+    FloatConvert {dest: Operand, arg1: Operand, from: Type, to: Type},
+
+    // addresses and variadic builtins
+    LoadAddress {dest: Operand, label: String},
+    VaStart {dest: Operand},
+    VaArg {dest: Operand, list: Operand, ty: Type},
+
     // assignment
     // a = 5;
     // a = b;
@@ -98,92 +112,116 @@ impl Instruction {
             Instruction::EndFunc => {
                 format!("   EndFunc")
             },
-            Instruction::CallStart(_) => {
-                format!("   CallStart")     
-            },
             Instruction::LCall(l) => {
                 format!("   LCall {}", l)
             }
-            Instruction::PushParam(param) => {
-                format!("   PushParam {}", param.print())
+            Instruction::PushParam(param, ty) => {
+                format!("   PushParam {} {}", type_name(*ty), param.print_typed())
             }
             Instruction::PopParams(size) => {
                 format!("   PopParams {}", size)
             }
 
             Instruction::IfZero { cond, label } => {
-                format!("   IfZero {} Goto {}", cond.print(), label)
+                format!("   IfZero {} Goto {}", cond.print_typed(), label)
             },
 
             Instruction::Return { dest } => {
-                format!("   Return {}", dest.print())
+                format!("   Return {}", dest.print_typed())
             }
 
             Instruction::Assign { dest, arg1} => {
-                format!("   {} = {}", dest.print(), arg1.print())
+                format!("   {} = {}", dest.print_typed(), arg1.print_typed())
             },
             Instruction::LogicalOr { dest, arg1, arg2 } => {
-                format!("   {} = {} || {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} || {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::LogicalAnd { dest, arg1, arg2 } => {
-                format!("   {} = {} && {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} && {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::BitwiseOr { dest, arg1, arg2 } => {
-                format!("   {} = {} | {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} | {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::BitwiseXor { dest, arg1, arg2 } => {
-                format!("   {} = {} ^ {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} ^ {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::BitwiseAnd { dest, arg1, arg2 } => {
-                format!("   {} = {} & {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} & {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::Equal { dest, arg1, arg2 } => {
-                format!("   {} = {} == {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} == {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::NotEqual { dest, arg1, arg2 } => {
-                format!("   {} = {} != {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} != {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::Greater { dest, arg1, arg2 } => {
-                format!("   {} = {} > {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} > {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::GreaterEqual { dest, arg1, arg2 } => {
-                format!("   {} = {} >= {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} >= {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::Less { dest, arg1, arg2 } => {
-                format!("   {} = {} < {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} < {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },  
             Instruction::LessEqual { dest, arg1, arg2 } => {
-                format!("   {} = {} <= {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} <= {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::ShiftLeft { dest, arg1, arg2 } => {
-                format!("   {} = {} << {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} << {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::ShiftRight { dest, arg1, arg2 } => {
-                format!("   {} = {} >> {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} >> {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::Add { dest, arg1, arg2 } => {
-                format!("   {} = {} + {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} + {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::Sub { dest, arg1, arg2 } => {
-                format!("   {} = {} - {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} - {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::Mul { dest, arg1, arg2 } => {
-                format!("   {} = {} * {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} * {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::Div { dest, arg1, arg2 } => {
-                format!("   {} = {} / {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} / {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::Mod { dest, arg1, arg2 } => {
-                format!("   {} = {} % {}", dest.print(), arg1.print(), arg2.print())
+                format!("   {} = {} % {}", dest.print_typed(), arg1.print_typed(), arg2.print_typed())
             },
             Instruction::Neg { dest, arg1} => {
-                format!("   {} = -{}", dest.print(), arg1.print())
+                format!("   {} = neg {}", dest.print_typed(), arg1.print_typed())
             },
             Instruction::Complement { dest, arg1} => {
-                format!("   {} = ~{}", dest.print(), arg1.print())
+                format!("   {} = compl {}", dest.print_typed(), arg1.print_typed())
             },
             Instruction::Not { dest, arg1} => {
-                format!("   {} = !{}", dest.print(), arg1.print())
+                format!("   {} = not {}", dest.print_typed(), arg1.print_typed())
+            },
+            Instruction::SignExtend { dest, arg1, from, to } => {
+                format!("   {} = sext {} {} to {}", dest.print_typed(), type_name(*from), arg1.print_typed(), type_name(*to))
+            },
+            Instruction::ZeroExtend { dest, arg1, from, to } => {
+                format!("   {} = zext {} {} to {}", dest.print_typed(), type_name(*from), arg1.print_typed(), type_name(*to))
+            },
+            Instruction::Truncate { dest, arg1, from, to } => {
+                format!("   {} = trunc {} {} to {}", dest.print_typed(), type_name(*from), arg1.print_typed(), type_name(*to))
+            },
+            Instruction::IntToFloat { dest, arg1, from, to } => {
+                format!("   {} = itof {} {} to {}", dest.print_typed(), type_name(*from), arg1.print_typed(), type_name(*to))
+            },
+            Instruction::FloatToInt { dest, arg1, from, to } => {
+                format!("   {} = ftoi {} {} to {}", dest.print_typed(), type_name(*from), arg1.print_typed(), type_name(*to))
+            },
+            Instruction::FloatConvert { dest, arg1, from, to } => {
+                format!("   {} = fconv {} {} to {}", dest.print_typed(), type_name(*from), arg1.print_typed(), type_name(*to))
+            },
+            Instruction::LoadAddress { dest, label } => {
+                format!("   {} = &{}", dest.print_typed(), label)
+            },
+            Instruction::VaStart { dest } => {
+                format!("   {} = va_start", dest.print_typed())
+            },
+            Instruction::VaArg { dest, list, ty } => {
+                format!("   {} = va_arg {} {}", dest.print_typed(), list.print_typed(), type_name(*ty))
             },
             
 
@@ -203,7 +241,12 @@ impl Instruction {
             Instruction::Greater { dest, .. } | Instruction::GreaterEqual { dest, .. } |
             Instruction::Less { dest, .. } | Instruction::LessEqual { dest, .. } | 
             Instruction::Assign { dest, .. } | Instruction::Complement { dest, .. } |
-            Instruction::Neg { dest, .. } | Instruction::Not { dest, .. } => {
+            Instruction::Neg { dest, .. } | Instruction::Not { dest, .. } |
+            Instruction::SignExtend { dest, .. } | Instruction::ZeroExtend { dest, .. } |
+            Instruction::Truncate { dest, .. } | Instruction::IntToFloat { dest, .. } |
+            Instruction::FloatToInt { dest, .. } | Instruction::FloatConvert { dest, .. } |
+            Instruction::LoadAddress { dest, .. } | Instruction::VaStart { dest } |
+            Instruction::VaArg { dest, .. } => {
                 Some(dest.clone())
             },
             _ => None,
@@ -224,7 +267,10 @@ impl Instruction {
                 return vec![arg1.clone(), arg2.clone()];
             },
             Instruction::Assign { arg1, .. } | Instruction::Complement { arg1, .. } |
-            Instruction::Neg { arg1, .. } | Instruction::Not { arg1, .. } => {
+            Instruction::Neg { arg1, .. } | Instruction::Not { arg1, .. } |
+            Instruction::SignExtend { arg1, .. } | Instruction::ZeroExtend { arg1, .. } |
+            Instruction::Truncate { arg1, .. } | Instruction::IntToFloat { arg1, .. } |
+            Instruction::FloatToInt { arg1, .. } | Instruction::FloatConvert { arg1, .. } => {
                 return vec![arg1.clone()];  
             },
             Instruction::IfZero { cond, .. } => {
@@ -233,15 +279,99 @@ impl Instruction {
             Instruction::Return { dest } => {
                 return vec![dest.clone()]
             },
-            Instruction::PushParam(p) => {
+            Instruction::PushParam(p, _) => {
                 return vec![p.clone()]
             }
+            Instruction::VaArg { list, .. } => {
+                return vec![list.clone()]
+            }
+            Instruction::LoadAddress { .. } | Instruction::VaStart { .. } |
             Instruction::Label(_) | Instruction::EndFunc | Instruction::BeginFunc(_) | 
-            Instruction::Goto(_) | Instruction::LCall(_) | Instruction::PopParams(_) |
-            Instruction::CallStart(_) => {
+            Instruction::Goto(_) | Instruction::LCall(_) | Instruction::PopParams(_) => {
                 return Vec::new()
             },
         }
     }
 
-}
\ No newline at end of file
+    // the same operands as `def` and `uses`, for passes that rename them
+    pub fn def_mut(&mut self) -> Option<&mut Operand> {
+        match self {
+            Instruction::Add { dest, .. } | Instruction::Sub { dest, .. } |
+            Instruction::Mul { dest, .. } | Instruction::Div { dest, .. } |
+            Instruction::ShiftLeft { dest, .. } | Instruction::ShiftRight { dest, .. } |
+            Instruction::Mod { dest, .. } | Instruction::BitwiseAnd { dest, .. } |
+            Instruction::BitwiseXor { dest, .. } | Instruction::BitwiseOr { dest, .. } |
+            Instruction::LogicalAnd { dest, .. } | Instruction::LogicalOr { dest, .. } |
+            Instruction::Equal { dest, .. } | Instruction::NotEqual { dest, .. } |
+            Instruction::Greater { dest, .. } | Instruction::GreaterEqual { dest, .. } |
+            Instruction::Less { dest, .. } | Instruction::LessEqual { dest, .. } |
+            Instruction::Assign { dest, .. } | Instruction::Complement { dest, .. } |
+            Instruction::Neg { dest, .. } | Instruction::Not { dest, .. } |
+            Instruction::SignExtend { dest, .. } | Instruction::ZeroExtend { dest, .. } |
+            Instruction::Truncate { dest, .. } | Instruction::IntToFloat { dest, .. } |
+            Instruction::FloatToInt { dest, .. } | Instruction::FloatConvert { dest, .. } |
+            Instruction::LoadAddress { dest, .. } | Instruction::VaStart { dest } |
+            Instruction::VaArg { dest, .. } => Some(dest),
+            _ => None,
+        }
+    }
+
+    pub fn uses_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Instruction::Add {arg1, arg2, ..} | Instruction::Sub {arg1, arg2, ..} |
+            Instruction::Mul {arg1, arg2, ..} | Instruction::Div {arg1, arg2, ..} |
+            Instruction::ShiftLeft {arg1, arg2, ..} | Instruction::ShiftRight {arg1, arg2, ..} |
+            Instruction::Mod {arg1, arg2, ..} | Instruction::BitwiseAnd {arg1, arg2, ..} |
+            Instruction::BitwiseXor {arg1, arg2, ..} | Instruction::BitwiseOr {arg1, arg2, ..} |
+            Instruction::LogicalAnd {arg1, arg2, ..} | Instruction::LogicalOr {arg1, arg2, ..} |
+            Instruction::Equal {arg1, arg2, ..} | Instruction::NotEqual {arg1, arg2, ..} |
+            Instruction::Greater {arg1, arg2, ..} | Instruction::GreaterEqual {arg1, arg2, ..} |
+            Instruction::Less {arg1, arg2, ..} | Instruction::LessEqual {arg1, arg2, ..} => vec![arg1, arg2],
+            Instruction::Assign { arg1, .. } | Instruction::Complement { arg1, .. } |
+            Instruction::Neg { arg1, .. } | Instruction::Not { arg1, .. } |
+            Instruction::SignExtend { arg1, .. } | Instruction::ZeroExtend { arg1, .. } |
+            Instruction::Truncate { arg1, .. } | Instruction::IntToFloat { arg1, .. } |
+            Instruction::FloatToInt { arg1, .. } | Instruction::FloatConvert { arg1, .. } => vec![arg1],
+            Instruction::IfZero { cond, .. } => vec![cond],
+            Instruction::Return { dest } => vec![dest],
+            Instruction::PushParam(p, _) => vec![p],
+            Instruction::VaArg { list, .. } => vec![list],
+            Instruction::LoadAddress { .. } | Instruction::VaStart { .. } |
+            Instruction::Label(_) | Instruction::EndFunc | Instruction::BeginFunc(_) |
+            Instruction::Goto(_) | Instruction::LCall(_) | Instruction::PopParams(_) => Vec::new(),
+        }
+    }
+
+}
+
+// a constant as written in the TAC. floating constants always have a
+// fraction or exponent, floats end in 'f', and chars are escaped so that
+// no constant contains whitespace
+pub fn const_literal(val: &ConstValue) -> String {
+    match val {
+        ConstValue::Int(n) => n.to_string(),
+        ConstValue::Double(d) => format!("{:?}", d),
+        ConstValue::Float(f) => format!("{:?}f", f),
+        ConstValue::Char(' ') => String::from("'\\u{20}'"),
+        ConstValue::Char(c) => format!("'{}'", c.escape_default()),
+        ConstValue::Void => String::from("void"),
+    }
+}
+
+// the spelling of a type in the TAC, a single word so it can follow a name
+pub fn type_name(ty: Type) -> &'static str {
+    match ty {
+        Type::Int => "int",
+        Type::Float => "float",
+        Type::Double => "double",
+        Type::Char => "char",
+        Type::Short => "short",
+        Type::Long => "long",
+        Type::UnsignedChar => "uchar",
+        Type::UnsignedShort => "ushort",
+        Type::UnsignedInt => "uint",
+        Type::UnsignedLong => "ulong",
+        Type::Pointer => "ptr",
+        Type::Void => "void",
+    }
+}
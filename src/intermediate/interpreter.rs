@@ -0,0 +1,519 @@
+use std::fmt;
+
+use indexmap::IndexMap;
+
+use crate::{intermediate::{frame::Frame, instruction::Instruction, irgen::{Operand, Value, cast, const_value, float_of, normalize}}, parser::token::Type};
+
+// executes TAC directly, as an oracle for the backend. integers are kept as
+// i64 normalized to their type (sign or zero extended), operations follow
+// the types codegen uses: the dest type for shifts, otherwise the type of
+// the non constant operand. printf is the only external function
+
+const MAX_DEPTH: usize = 100000;
+
+
+#[derive(Debug)]
+pub enum Trap {
+    DivisionByZero(String),
+    DivisionOverflow(String),
+    UnknownFunction(String),
+    UndefinedValue { func: String, name: String },
+    InvalidOperation { func: String, inst: String },
+    ArgumentCount { func: String, expected: usize, found: usize },
+    MissingVaArg(String),
+    StackOverflow(String),
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Trap::DivisionByZero(func) => write!(f, "Trap: division by zero in '{}'", func),
+            Trap::DivisionOverflow(func) => write!(f, "Trap: division overflow in '{}'", func),
+            Trap::UnknownFunction(name) => write!(f, "Trap: call to unknown function '{}'", name),
+            Trap::UndefinedValue { func, name } => write!(f, "Trap: in '{}': '{}' read before it is written", func, name),
+            Trap::InvalidOperation { func, inst } => write!(f, "Trap: in '{}': invalid operation '{}'", func, inst.trim()),
+            Trap::ArgumentCount { func, expected, found } => write!(f, "Trap: '{}' takes {} arguments, {} given", func, expected, found),
+            Trap::MissingVaArg(func) => write!(f, "Trap: va_arg past the last argument in '{}'", func),
+            Trap::StackOverflow(func) => write!(f, "Trap: call depth over {} in '{}'", MAX_DEPTH, func),
+        }
+    }
+}
+
+pub struct Interpreter<'a> {
+    instructions: &'a [Instruction],
+    frames: &'a IndexMap<String, Frame>,
+    // label -> contents
+    strings: IndexMap<String, String>,
+    labels: IndexMap<String, usize>,
+    pub output: String,
+}
+
+// `strings` maps contents to labels, as produced by irgen
+pub fn new_interpreter<'a>(instructions: &'a [Instruction], frames: &'a IndexMap<String, Frame>, strings: &IndexMap<String, String>) -> Interpreter<'a> {
+    let mut labels = IndexMap::new();
+    for (i, inst) in instructions.iter().enumerate() {
+        if let Instruction::Label(l) = inst {
+            labels.insert(l.clone(), i);
+        }
+    }
+    Interpreter {
+        instructions,
+        frames,
+        strings: strings.iter().map(|(s, label)| (label.clone(), s.clone())).collect(),
+        labels,
+        output: String::new(),
+    }
+}
+
+// the state of one call
+struct Activation {
+    func: String,
+    ret_type: Type,
+    pc: usize,
+    values: IndexMap<String, Value>,
+    varargs: Vec<Value>,
+    params: Vec<Value>,
+}
+
+enum Step {
+    Next,
+    Call(String, Vec<Value>),
+    Return(Value),
+}
+
+impl<'a> Interpreter<'a> {
+    // calls run on an explicit stack, deep recursion in the program does
+    // not recurse here
+    pub fn run(&mut self, entry: &str, args: &[Value]) -> Result<Value, Trap> {
+        if !self.is_defined(entry) {
+            return self.call_external(entry, args.to_vec());
+        }
+        let mut stack = vec![self.enter(entry, args.to_vec())?];
+        loop {
+            let act = stack.last_mut().unwrap();
+            match self.step(act)? {
+                Step::Next => (),
+                Step::Call(name, args) if self.is_defined(&name) => {
+                    if stack.len() >= MAX_DEPTH {
+                        return Err(Trap::StackOverflow(name));
+                    }
+                    stack.push(self.enter(&name, args)?);
+                },
+                Step::Call(name, args) => {
+                    let ret = self.call_external(&name, args)?;
+                    act.values.insert(String::from("_ret"), ret);
+                },
+                Step::Return(value) => {
+                    stack.pop();
+                    match stack.last_mut() {
+                        Some(caller) => { caller.values.insert(String::from("_ret"), value); },
+                        None => return Ok(value),
+                    }
+                },
+            }
+        }
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.frames.contains_key(name) && self.labels.contains_key(name)
+    }
+
+    fn enter(&self, name: &str, args: Vec<Value>) -> Result<Activation, Trap> {
+        let frame = &self.frames[name];
+        let expected = frame.params.len();
+        if args.len() < expected || (args.len() > expected && !frame.variadic) {
+            return Err(Trap::ArgumentCount { func: name.to_string(), expected, found: args.len() });
+        }
+        let mut act = Activation {
+            func: name.to_string(),
+            ret_type: frame.ret_type,
+            pc: self.labels[name],
+            values: IndexMap::new(),
+            varargs: args[expected..].to_vec(),
+            params: Vec::new(),
+        };
+        for (param, arg) in frame.params.iter().zip(&args) {
+            act.values.insert(param.name.clone(), cast(*arg, param.stype));
+        }
+        Ok(act)
+    }
+
+    fn step(&mut self, act: &mut Activation) -> Result<Step, Trap> {
+        let inst = &self.instructions[act.pc];
+        act.pc += 1;
+        match inst {
+            Instruction::Label(_) | Instruction::BeginFunc(_) | Instruction::PopParams(_) => (),
+            Instruction::EndFunc => return Ok(Step::Return(Value::Void)),
+            Instruction::Goto(label) => act.pc = self.labels[label],
+            Instruction::IfZero { cond, label } => {
+                if !self.read(act, cond, cond.ty())?.truthy() {
+                    act.pc = self.labels[label];
+                }
+            },
+            Instruction::Return { dest: Operand::None } => return Ok(Step::Return(Value::Void)),
+            Instruction::Return { dest } => {
+                let value = cast(self.read(act, dest, act.ret_type)?, act.ret_type);
+                return Ok(Step::Return(value));
+            },
+            Instruction::PushParam(op, ty) => {
+                let value = cast(self.read(act, op, *ty)?, *ty);
+                act.params.push(value);
+            },
+            Instruction::LCall(name) => {
+                let args = std::mem::take(&mut act.params);
+                return Ok(Step::Call(name.clone(), args));
+            },
+            Instruction::LoadAddress { dest, label } => {
+                let index = self.strings.get_index_of(label)
+                    .ok_or_else(|| invalid(&act.func, inst))?;
+                write(act, dest, Value::Str(index));
+            },
+            Instruction::VaStart { dest } => write(act, dest, Value::VaList(0)),
+            Instruction::VaArg { dest, list, ty } => {
+                let Value::VaList(next) = self.read(act, list, Type::Pointer)? else {
+                    return Err(invalid(&act.func, inst));
+                };
+                let value = *act.varargs.get(next).ok_or_else(|| Trap::MissingVaArg(act.func.clone()))?;
+                write(act, list, Value::VaList(next + 1));
+                write(act, dest, cast(value, *ty));
+            },
+            _ => {
+                let value = evaluate(inst, &act.func, |op, ty| self.read(act, op, ty))?;
+                match (inst.def(), value) {
+                    (Some(dest), Some(value)) => write(act, &dest, value),
+                    _ => return Err(invalid(&act.func, inst)),
+                }
+            },
+        }
+        Ok(Step::Next)
+    }
+
+    // constants take the type they are used as
+    fn read(&self, act: &Activation, op: &Operand, ty: Type) -> Result<Value, Trap> {
+        match op {
+            Operand::Const(c) => Ok(const_value(c, ty)),
+            Operand::Var(name, _) | Operand::Temp(name, _) => act.values.get(name).copied()
+                .ok_or_else(|| Trap::UndefinedValue { func: act.func.clone(), name: name.clone() }),
+            Operand::None => Err(Trap::UndefinedValue { func: act.func.clone(), name: String::from("None") }),
+        }
+    }
+
+    fn call_external(&mut self, name: &str, args: Vec<Value>) -> Result<Value, Trap> {
+        match (name, args.first()) {
+            ("printf", Some(Value::Str(i))) => {
+                let format = self.strings[*i].clone();
+                let text = printf(&format, &args[1..], &self.strings);
+                self.output.push_str(&text);
+                Ok(Value::Int(text.len() as i64))
+            },
+            _ => Err(Trap::UnknownFunction(name.to_string())),
+        }
+    }
+}
+
+// the value of an instruction that only computes from its operands, None
+// for any other. `read` gives an operand converted to the type it is read as.
+// shared with constant folding, so both follow the same C semantics
+pub fn evaluate(inst: &Instruction, func: &str, read: impl Fn(&Operand, Type) -> Result<Value, Trap>) -> Result<Option<Value>, Trap> {
+    let value = match inst {
+        Instruction::Assign { dest, arg1 } => normalize(read(arg1, dest.ty())?, dest.ty()),
+        Instruction::SignExtend { arg1, from, to, .. } |
+        Instruction::ZeroExtend { arg1, from, to, .. } |
+        Instruction::Truncate { arg1, from, to, .. } |
+        Instruction::FloatToInt { arg1, from, to, .. } |
+        Instruction::FloatConvert { arg1, from, to, .. } => cast(read(arg1, *from)?, *to),
+        Instruction::IntToFloat { arg1, from, to, .. } => match read(arg1, *from)? {
+            Value::Int(n) if *from == Type::UnsignedLong => float_of(n as u64 as f64, *to),
+            value => cast(value, *to),
+        },
+        Instruction::Neg { dest, arg1 } |
+        Instruction::Complement { dest, arg1 } |
+        Instruction::Not { dest, arg1 } => {
+            let result = match (inst, read(arg1, dest.ty())?) {
+                (Instruction::Not { .. }, v) => Value::Int(!v.truthy() as i64),
+                (Instruction::Neg { .. }, Value::Int(n)) => Value::Int(n.wrapping_neg()),
+                (Instruction::Neg { .. }, Value::Float(f)) => Value::Float(-f),
+                (Instruction::Neg { .. }, Value::Double(d)) => Value::Double(-d),
+                (Instruction::Complement { .. }, Value::Int(n)) => Value::Int(!n),
+                _ => return Err(invalid(func, inst)),
+            };
+            normalize(result, dest.ty())
+        },
+        Instruction::ShiftLeft { dest, arg1, arg2 } |
+        Instruction::ShiftRight { dest, arg1, arg2 } => {
+            let ty = dest.ty();
+            let (Value::Int(a), Value::Int(b)) = (read(arg1, ty)?, read(arg2, arg2.ty())?) else {
+                return Err(invalid(func, inst));
+            };
+            let count = (b as u32) & if ty.size() == 8 { 63 } else { 31 };
+            let result = match inst {
+                Instruction::ShiftLeft { .. } => a.wrapping_shl(count),
+                _ if ty.is_signed() => a >> count,
+                _ => ((a as u64) >> count) as i64,
+            };
+            normalize(Value::Int(result), ty)
+        },
+        Instruction::LogicalOr { arg1, arg2, .. } |
+        Instruction::LogicalAnd { arg1, arg2, .. } => {
+            let a = read(arg1, arg1.ty())?.truthy();
+            let b = read(arg2, arg2.ty())?.truthy();
+            let result = match inst {
+                Instruction::LogicalOr { .. } => a || b,
+                _ => a && b,
+            };
+            Value::Int(result as i64)
+        },
+        Instruction::Equal { arg1, arg2, .. } | Instruction::NotEqual { arg1, arg2, .. } |
+        Instruction::Greater { arg1, arg2, .. } | Instruction::GreaterEqual { arg1, arg2, .. } |
+        Instruction::Less { arg1, arg2, .. } | Instruction::LessEqual { arg1, arg2, .. } => {
+            let ty = arg1.binary_type(arg2);
+            let a = read(arg1, ty)?;
+            let b = read(arg2, ty)?;
+            let ordering = if ty.is_floating() {
+                a.to_f64().partial_cmp(&b.to_f64())
+            } else if ty.is_signed() {
+                Some(a.to_i64().cmp(&b.to_i64()))
+            } else {
+                Some((a.to_i64() as u64).cmp(&(b.to_i64() as u64)))
+            };
+            let result = ordering.is_some_and(|o| match inst {
+                Instruction::Equal { .. } => o.is_eq(),
+                Instruction::NotEqual { .. } => o.is_ne(),
+                Instruction::Greater { .. } => o.is_gt(),
+                Instruction::GreaterEqual { .. } => o.is_ge(),
+                Instruction::Less { .. } => o.is_lt(),
+                _ => o.is_le(),
+            });
+            // unordered operands are only different
+            let result = result || (ordering.is_none() && matches!(inst, Instruction::NotEqual { .. }));
+            Value::Int(result as i64)
+        },
+        Instruction::Add { dest, arg1, arg2 } | Instruction::Sub { dest, arg1, arg2 } |
+        Instruction::Mul { dest, arg1, arg2 } | Instruction::Div { dest, arg1, arg2 } |
+        Instruction::Mod { dest, arg1, arg2 } | Instruction::BitwiseAnd { dest, arg1, arg2 } |
+        Instruction::BitwiseOr { dest, arg1, arg2 } | Instruction::BitwiseXor { dest, arg1, arg2 } => {
+            let ty = arg1.binary_type(arg2);
+            let a = read(arg1, ty)?;
+            let b = read(arg2, ty)?;
+            let result = match (a, b) {
+                (Value::Int(a), Value::Int(b)) => Value::Int(integer_op(func, inst, ty, a, b)?),
+                (Value::Float(_) | Value::Double(_), Value::Float(_) | Value::Double(_)) => {
+                    let (a, b) = (a.to_f64(), b.to_f64());
+                    let result = match inst {
+                        Instruction::Add { .. } => a + b,
+                        Instruction::Sub { .. } => a - b,
+                        Instruction::Mul { .. } => a * b,
+                        Instruction::Div { .. } => a / b,
+                        _ => return Err(invalid(func, inst)),
+                    };
+                    float_of(result, ty)
+                },
+                _ => return Err(invalid(func, inst)),
+            };
+            normalize(result, dest.ty())
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(value))
+}
+
+fn integer_op(func: &str, inst: &Instruction, ty: Type, a: i64, b: i64) -> Result<i64, Trap> {
+    let result = match inst {
+        Instruction::Add { .. } => a.wrapping_add(b),
+        Instruction::Sub { .. } => a.wrapping_sub(b),
+        Instruction::Mul { .. } => a.wrapping_mul(b),
+        Instruction::BitwiseAnd { .. } => a & b,
+        Instruction::BitwiseOr { .. } => a | b,
+        Instruction::BitwiseXor { .. } => a ^ b,
+        _ => {
+            if b == 0 {
+                return Err(Trap::DivisionByZero(func.to_string()));
+            }
+            // idiv faults on the one quotient that does not fit
+            let min = if ty.size() == 8 { i64::MIN } else { i32::MIN as i64 };
+            if ty.is_signed() && a == min && b == -1 {
+                return Err(Trap::DivisionOverflow(func.to_string()));
+            }
+            match (inst, ty.is_signed()) {
+                (Instruction::Div { .. }, true) => a / b,
+                (Instruction::Div { .. }, false) => ((a as u64) / (b as u64)) as i64,
+                (_, true) => a % b,
+                (_, false) => ((a as u64) % (b as u64)) as i64,
+            }
+        },
+    };
+    Ok(result)
+}
+
+fn invalid(func: &str, inst: &Instruction) -> Trap {
+    Trap::InvalidOperation { func: func.to_string(), inst: inst.print() }
+}
+
+fn write(act: &mut Activation, dest: &Operand, value: Value) {
+    if let Operand::Var(name, _) | Operand::Temp(name, _) = dest {
+        act.values.insert(name.clone(), value);
+    }
+}
+
+
+// the conversions printf supports: d i u x X o c s f e % with flags, width,
+// precision and the h/l/ll/z length modifiers
+fn printf(format: &str, args: &[Value], strings: &IndexMap<String, String>) -> String {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut flags = String::new();
+        while let Some(f) = chars.next_if(|f| "-+ 0#".contains(*f)) {
+            flags.push(f);
+        }
+        let mut width = 0;
+        while let Some(d) = chars.next_if(|d| d.is_ascii_digit()) {
+            width = width * 10 + d.to_digit(10).unwrap() as usize;
+        }
+        let mut precision = None;
+        if chars.next_if_eq(&'.').is_some() {
+            let mut p = 0;
+            while let Some(d) = chars.next_if(|d| d.is_ascii_digit()) {
+                p = p * 10 + d.to_digit(10).unwrap() as usize;
+            }
+            precision = Some(p);
+        }
+        let mut long = false;
+        while let Some(l) = chars.next_if(|l| "hlz".contains(*l)) {
+            long |= l != 'h';
+        }
+        let Some(conv) = chars.next() else { break };
+        if conv == '%' {
+            out.push('%');
+            continue;
+        }
+        let arg = args.next().copied().unwrap_or(Value::Int(0));
+        let n = arg.to_i64();
+        let unsigned = if long { n as u64 } else { n as u32 as u64 };
+        let body = match conv {
+            'd' | 'i' => {
+                let n = if long { n } else { n as i32 as i64 };
+                if n >= 0 && flags.contains('+') { format!("+{}", n) } else { n.to_string() }
+            },
+            'u' => unsigned.to_string(),
+            'x' => format!("{:x}", unsigned),
+            'X' => format!("{:X}", unsigned),
+            'o' => format!("{:o}", unsigned),
+            'c' => (n as u8 as char).to_string(),
+            's' => match arg {
+                Value::Str(i) => strings[i].clone(),
+                _ => String::from("(null)"),
+            },
+            'f' | 'F' => format!("{:.*}", precision.unwrap_or(6), arg.to_f64()),
+            'e' | 'E' => exponent(arg.to_f64(), precision.unwrap_or(6), conv == 'E'),
+            _ => format!("%{}", conv),
+        };
+        let pad = width.saturating_sub(body.chars().count());
+        if flags.contains('-') {
+            out.push_str(&body);
+            out.push_str(&" ".repeat(pad));
+        } else if flags.contains('0') && !matches!(conv, 's' | 'c') {
+            let (sign, digits) = body.split_at(if body.starts_with(['-', '+']) { 1 } else { 0 });
+            out.push_str(sign);
+            out.push_str(&"0".repeat(pad));
+            out.push_str(digits);
+        } else {
+            out.push_str(&" ".repeat(pad));
+            out.push_str(&body);
+        }
+    }
+    out
+}
+
+// `d.ddde+xx` as printed by C
+fn exponent(x: f64, precision: usize, upper: bool) -> String {
+    let text = format!("{:.*e}", precision, x);
+    let (mantissa, exp) = text.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let sign = if exp < 0 { '-' } else { '+' };
+    let e = if upper { 'E' } else { 'e' };
+    format!("{}{}{}{:02}", mantissa, e, sign, exp.abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{intermediate::{analyzer::new_analyzer, irgen::new_codegen}, parser::parser::new_parser};
+
+    use super::*;
+
+    fn interpret(input: &str, entry: &str, args: &[Value]) -> (Result<Value, Trap>, String) {
+        let mut parser = new_parser(input).unwrap();
+        let mut program_node = parser.parse().unwrap();
+        let mut analyzer = new_analyzer();
+        analyzer.analyze(&mut program_node).unwrap();
+        let mut code_gen = new_codegen(analyzer.function_frames, analyzer.function_signatures);
+        code_gen.cgen(&program_node);
+        let mut interpreter = new_interpreter(&code_gen.instructions, &code_gen.frames, &code_gen.strings);
+        let result = interpreter.run(entry, args);
+        (result, interpreter.output)
+    }
+
+    #[test]
+    fn interpreter_results() {
+        let cases = [
+            ("int fib(int n) { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); }", "fib", vec![Value::Int(15)], Value::Int(610)),
+            ("int f(int n) { int s = 0; while (n > 0) { s = s + n % 7; n = n - 1; } return s; }", "f", vec![Value::Int(100)], Value::Int(297)),
+            ("int f(int a, int b) { return a / b * 10 + a % b; }", "f", vec![Value::Int(-7), Value::Int(2)], Value::Int(-31)),
+            ("unsigned int f(unsigned int a) { return a / 2 + (a >> 30); }", "f", vec![Value::Int(4000000000)], Value::Int(2000000003)),
+            ("int f() { int x = 2147483647; return x + 1; }", "f", vec![], Value::Int(-2147483648)),
+            ("long f(int a) { long b = a; return b * 100000; }", "f", vec![Value::Int(100000)], Value::Int(10000000000)),
+            ("int f(int a) { return -a >> 1 | ~a & 12 ^ !a; }", "f", vec![Value::Int(5)], Value::Int(-3)),
+            ("double f(double x, int n) { return x * n - 0.5; }", "f", vec![Value::Double(1.5), Value::Int(3)], Value::Double(4.0)),
+            ("int f(double x) { return x > 2.5 && x != 3.0 || x < -1.0; }", "f", vec![Value::Double(2.75)], Value::Int(1)),
+            ("int v(int n, ...) { va_list ap; va_start(ap, n); int s = 0; int i = 0; while (i < n) { s = s + va_arg(ap, int); i = i + 1; } va_end(ap); return s; } int f() { return v(3, 10, 20, 30); }", "f", vec![], Value::Int(60)),
+        ];
+
+        for (input, entry, args, expected) in cases {
+            let (got, _) = interpret(input, entry, &args);
+            assert!(matches!(got, Ok(v) if v == expected), "failed at: {}\ngot {:?}", input, got);
+        }
+    }
+
+    #[test]
+    fn interpreter_printf() {
+        let input = "int printf(char *fmt, ...); int f() { float x = 1.5; printf(\"%s %d|%5d|%-3d|%05.1f %x %c\\n\", \"hi\", 42, 7, 1, x, 255, 65); return 0; }";
+        let (got, output) = interpret(input, "f", &[]);
+        assert!(matches!(got, Ok(Value::Int(0))), "failed at: {}", input);
+        assert_eq!(output, "hi 42|    7|1  |001.5 ff A\n");
+    }
+
+    #[test]
+    fn interpreter_traps() {
+        // whether the trap is the one the case is about
+        type Expected = fn(&Trap) -> bool;
+        let cases: [(&str, &str, Vec<Value>, Expected); 8] = [
+            ("int f(int a) { return 10 / a; }", "f", vec![Value::Int(0)],
+                |t| matches!(t, Trap::DivisionByZero(func) if func == "f")),
+            ("int f(int a) { return 10 % a; }", "f", vec![Value::Int(0)],
+                |t| matches!(t, Trap::DivisionByZero(func) if func == "f")),
+            ("int f(int a, int b) { return a / b; }", "f", vec![Value::Int(-2147483647 - 1), Value::Int(-1)],
+                |t| matches!(t, Trap::DivisionOverflow(func) if func == "f")),
+            ("int f(int n) { return f(n + 1); }", "f", vec![Value::Int(0)],
+                |t| matches!(t, Trap::StackOverflow(func) if func == "f")),
+            ("int g(int a); int f() { return g(1); }", "f", vec![],
+                |t| matches!(t, Trap::UnknownFunction(func) if func == "g")),
+            ("int f(int a) { return a; }", "f", vec![],
+                |t| matches!(t, Trap::ArgumentCount { expected: 1, found: 0, .. })),
+            // the value a path without a definition reads
+            ("int f(int a) { int x; if (a) { x = 1; } return x; }", "f", vec![Value::Int(0)],
+                |t| matches!(t, Trap::UndefinedValue { name, .. } if name == "x")),
+            ("int v(int n, ...) { va_list ap; va_start(ap, n); int x = va_arg(ap, int); return x; } int f() { return v(1); }", "f", vec![],
+                |t| matches!(t, Trap::MissingVaArg(func) if func == "v")),
+        ];
+
+        for (input, entry, args, expected) in cases {
+            let (got, _) = interpret(input, entry, &args);
+            assert!(got.as_ref().is_err_and(expected), "failed at: {}\n{:?}", input, got);
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use indexmap::{IndexMap};
-use crate::{intermediate::frame::Frame, intermediate::instruction::Instruction, parser::node::{ConstValue, ParserNode}};
+use crate::{intermediate::{analyzer::Signature, frame::Frame, instruction::{Instruction, const_literal, type_name}, tac::print_program}, parser::{node::{ConstValue, ParserNode}, token::Type}};
 
 static DEBUG_IR_GEN: bool = false;
 
@@ -7,14 +7,18 @@ static DEBUG_IR_GEN: bool = false;
 pub struct CodeGen {
     pub instructions: Vec<Instruction>,
     pub frames: IndexMap<String, Frame>,
+    pub signatures: IndexMap<String, Signature>,
+    pub strings: IndexMap<String, String>,
     temp_count: usize,
     label_count: usize,
 }
 
-pub fn new_codegen(frames: IndexMap<String, Frame>) -> CodeGen {
+pub fn new_codegen(frames: IndexMap<String, Frame>, signatures: IndexMap<String, Signature>) -> CodeGen {
     CodeGen {
         instructions: Vec::new(),
         frames,
+        signatures,
+        strings: IndexMap::new(),
         temp_count: 0,
         label_count: 0,
     }
@@ -23,8 +27,8 @@ pub fn new_codegen(frames: IndexMap<String, Frame>) -> CodeGen {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Operand {
     Const(ConstValue),
-    Var(String),
-    Temp(String),
+    Var(String, Type),
+    Temp(String, Type),
     None,
 }
 
@@ -34,15 +38,145 @@ impl Operand {
             Operand::Const(val) => {
                 format!("{}", val.to_string())
             },
-            Operand::Var(v) => {
+            Operand::Var(v, _) => {
                 v.clone()
             },
-            Operand::Temp(t) => {
+            Operand::Temp(t, _) => {
                 t.clone()
             },
             Operand::None => String::new()
         }
     }
+
+    // the operand as written in the TAC, names carry their type
+    pub fn print_typed(&self) -> String {
+        match self {
+            Operand::Var(name, ty) | Operand::Temp(name, ty) => {
+                format!("{}:{}", name, type_name(*ty))
+            },
+            Operand::Const(val) => const_literal(val),
+            Operand::None => String::new(),
+        }
+    }
+
+    pub fn ty(&self) -> Type {
+        match self {
+            Operand::Const(val) => val.ty(),
+            Operand::Var(_, ty) | Operand::Temp(_, ty) => *ty,
+            Operand::None => Type::Void,
+        }
+    }
+
+    // the type both operands of a binary instruction were converted to,
+    // constants take the type of the other side
+    pub fn binary_type(&self, other: &Operand) -> Type {
+        match self {
+            Operand::Const(_) => other.ty(),
+            _ => self.ty(),
+        }
+    }
+}
+
+// the value of an operand as the interpreter and constant folding see it,
+// integers are kept as i64 normalized to their type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f32),
+    Double(f64),
+    // index into the string literals
+    Str(usize),
+    // index of the next variadic argument
+    VaList(usize),
+    Void,
+}
+
+impl Value {
+    pub fn to_string(self, ty: Type) -> String {
+        match self {
+            Value::Int(n) if ty == Type::UnsignedLong => format!("{}", n as u64),
+            Value::Int(n) => format!("{}", n),
+            Value::Float(f) => format!("{:.6}", f),
+            Value::Double(d) => format!("{:.6}", d),
+            Value::Str(i) => format!("<string {}>", i),
+            Value::VaList(i) => format!("<va_list {}>", i),
+            Value::Void => String::from("void"),
+        }
+    }
+
+    pub fn truthy(&self) -> bool {
+        match self {
+            Value::Int(n) => *n != 0,
+            Value::Float(f) => *f != 0.0,
+            Value::Double(d) => *d != 0.0,
+            Value::Str(_) | Value::VaList(_) => true,
+            Value::Void => false,
+        }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        match self {
+            Value::Int(n) => n as f64,
+            Value::Float(f) => f as f64,
+            Value::Double(d) => d,
+            _ => 0.0,
+        }
+    }
+
+    pub fn to_i64(self) -> i64 {
+        match self {
+            Value::Int(n) => n,
+            Value::Float(f) => f as i64,
+            Value::Double(d) => d as i64,
+            Value::Str(i) | Value::VaList(i) => i as i64,
+            Value::Void => 0,
+        }
+    }
+}
+
+pub fn const_value(c: &ConstValue, ty: Type) -> Value {
+    match c {
+        ConstValue::Int(n) => cast(Value::Int(*n as i64), ty),
+        ConstValue::Char(c) => cast(Value::Int(*c as i64), ty),
+        ConstValue::Float(f) => cast(Value::Float(*f), ty),
+        ConstValue::Double(d) => cast(Value::Double(*d), ty),
+        ConstValue::Void => Value::Void,
+    }
+}
+
+// wraps integers to the width and signedness of `ty`
+pub fn normalize(value: Value, ty: Type) -> Value {
+    let Value::Int(n) = value else { return value };
+    let n = match ty {
+        Type::Char => n as i8 as i64,
+        Type::UnsignedChar => n as u8 as i64,
+        Type::Short => n as i16 as i64,
+        Type::UnsignedShort => n as u16 as i64,
+        Type::Int => n as i32 as i64,
+        Type::UnsignedInt => n as u32 as i64,
+        _ => n,
+    };
+    Value::Int(n)
+}
+
+pub fn float_of(x: f64, ty: Type) -> Value {
+    match ty {
+        Type::Float => Value::Float(x as f32),
+        _ => Value::Double(x),
+    }
+}
+
+// the value converted as by a C cast
+pub fn cast(value: Value, ty: Type) -> Value {
+    match value {
+        Value::Int(n) if ty.is_floating() => float_of(n as f64, ty),
+        Value::Float(_) | Value::Double(_) if ty.is_floating() => float_of(value.to_f64(), ty),
+        Value::Float(_) | Value::Double(_) if ty.is_integer() && !ty.is_signed() => {
+            normalize(Value::Int(value.to_f64() as u64 as i64), ty)
+        },
+        Value::Float(_) | Value::Double(_) if ty.is_integer() => normalize(Value::Int(value.to_i64()), ty),
+        _ => normalize(value, ty),
+    }
 }
 
 
@@ -86,7 +220,9 @@ impl CodeGen {
                 }
             }
 
-            ParserNode::FuncDecl { ident, args:_, block, ntype:_ } => {
+            ParserNode::FuncProto { .. } => Operand::None,
+
+            ParserNode::FuncDecl { ident, args:_, block, .. } => {
                 self.emit(Instruction::Label(ident.to_string()));
                 let frame = self.frames.get(ident.to_string().as_str()).unwrap();
                 self.emit(Instruction::BeginFunc(frame.locals_size));
@@ -97,27 +233,32 @@ impl CodeGen {
             },
 
             ParserNode::FuncCall { ident, args } => {
-                self.emit(Instruction::CallStart(Vec::new()));
+                // every argument is evaluated before the first PushParam,
+                // so nested calls never interleave with this call's params
+                let mut params = Vec::new();
                 for arg in args {
                     let mut t1 = self.cgen(arg);
                     match t1 {
                         Operand::Const(num) => {
-                            t1 = self.new_temp();
+                            t1 = self.new_temp(num.ty());
                             self.emit(Instruction::Assign { dest: t1.clone(), arg1: Operand::Const(num) });
                         },
                         _ => (),
                     }
-                    self.emit(Instruction::PushParam(t1));
+                    params.push((t1, self.expr_type(arg)));
+                }
+                let mut size = 0;
+                for (t1, ty) in params {
+                    size += ty.size();
+                    self.emit(Instruction::PushParam(t1, ty));
                 }
                 self.emit(Instruction::LCall(ident.clone()));
 
-                let t2 = self.new_temp();
-                self.emit(Instruction::Assign { dest: t2.clone(), arg1: Operand::Temp("_ret".to_string()) });
+                let ret_type = self.expr_type(node);
+                let t2 = self.new_temp(ret_type);
+                self.emit(Instruction::Assign { dest: t2.clone(), arg1: Operand::Temp("_ret".to_string(), ret_type) });
 
-                if args.len() > 0 {
-                    let size = args.len() * 4;
-                    self.emit(Instruction::PopParams(size));
-                }
+                self.emit(Instruction::PopParams(size));
                 t2
             },
 
@@ -205,7 +346,7 @@ impl CodeGen {
                 let mut dest = self.cgen(exp);
                 match dest {
                     Operand::Const(num) => {
-                        dest = self.new_temp();
+                        dest = self.new_temp(num.ty());
                         self.emit(Instruction::Assign { dest: dest.clone(), arg1: Operand::Const(num) });
                     },
                     _ => (),
@@ -218,14 +359,14 @@ impl CodeGen {
             ParserNode::LogicalOr { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(Type::Int);  
                 self.emit(Instruction::LogicalOr { dest: dest.clone(), arg1, arg2 });
                 dest
             },
             ParserNode::LogicalAnd { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(Type::Int);  
                 self.emit(Instruction::LogicalAnd { dest: dest.clone(), arg1, arg2 });
                 dest
             },
@@ -234,21 +375,21 @@ impl CodeGen {
             ParserNode::BitwiseOr { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(self.expr_type(node));  
                 self.emit(Instruction::BitwiseOr { dest: dest.clone(), arg1, arg2 });
                 dest
             },
             ParserNode::BitwiseXor { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(self.expr_type(node));  
                 self.emit(Instruction::BitwiseXor { dest: dest.clone(), arg1, arg2 });
                 dest
             },
             ParserNode::BitwiseAnd { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(self.expr_type(node));  
                 self.emit(Instruction::BitwiseAnd { dest: dest.clone(), arg1, arg2 });
                 dest
             },
@@ -257,14 +398,14 @@ impl CodeGen {
             ParserNode::Equal { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(Type::Int);  
                 self.emit(Instruction::Equal { dest: dest.clone(), arg1, arg2 });
                 dest
             },
             ParserNode::NotEqual { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(Type::Int);  
                 self.emit(Instruction::NotEqual { dest: dest.clone(), arg1, arg2 });
                 dest
             },
@@ -273,28 +414,28 @@ impl CodeGen {
             ParserNode::Greater { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(Type::Int);  
                 self.emit(Instruction::Greater { dest: dest.clone(), arg1, arg2 });
                 dest
             },
             ParserNode::GreaterEqual { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(Type::Int);  
                 self.emit(Instruction::GreaterEqual { dest: dest.clone(), arg1, arg2 });
                 dest
             },
             ParserNode::Less { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(Type::Int);  
                 self.emit(Instruction::Less { dest: dest.clone(), arg1, arg2 });
                 dest
             },
             ParserNode::LessEqual { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(Type::Int);  
                 self.emit(Instruction::LessEqual { dest: dest.clone(), arg1, arg2 });
                 dest
             },
@@ -303,14 +444,14 @@ impl CodeGen {
             ParserNode::ShiftLeft { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(self.expr_type(node));  
                 self.emit(Instruction::ShiftLeft { dest: dest.clone(), arg1, arg2 });
                 dest
             },
             ParserNode::ShiftRight { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(self.expr_type(node));  
                 self.emit(Instruction::ShiftRight { dest: dest.clone(), arg1, arg2 });
                 dest
             },
@@ -319,14 +460,14 @@ impl CodeGen {
             ParserNode::Add { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(self.expr_type(node));  
                 self.emit(Instruction::Add { dest: dest.clone(), arg1, arg2 });
                 dest
             },
             ParserNode::Sub { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(self.expr_type(node));  
                 self.emit(Instruction::Sub { dest: dest.clone(), arg1, arg2 });
                 dest
             },
@@ -335,47 +476,78 @@ impl CodeGen {
             ParserNode::Mul { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(self.expr_type(node));  
                 self.emit(Instruction::Mul { dest: dest.clone(), arg1, arg2 });
                 dest
             },
             ParserNode::Div { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(self.expr_type(node));  
                 self.emit(Instruction::Div { dest: dest.clone(), arg1, arg2 });
                 dest
             },
             ParserNode::Mod { left, right } => {
                 let arg1 = self.cgen(left);     
                 let arg2 = self.cgen(right); 
-                let dest = self.new_temp();  
+                let dest = self.new_temp(self.expr_type(node));  
                 self.emit(Instruction::Mod { dest: dest.clone(), arg1, arg2 });
                 dest
             },
 
             // unary
             ParserNode::Neg { val} => {
-                let dest = self.new_temp();
+                let dest = self.new_temp(self.expr_type(node));
                 let arg1 = self.cgen(val);    
                 self.emit(Instruction::Neg { dest: dest.clone(), arg1 });
                 dest
             },
             ParserNode::Complement { val} => {
-                let dest = self.new_temp();
+                let dest = self.new_temp(self.expr_type(node));
                 let arg1 = self.cgen(val);    
                 self.emit(Instruction::Complement { dest: dest.clone(), arg1 });
                 dest
             },
             ParserNode::Not { val} => {
-                let dest = self.new_temp();
+                let dest = self.new_temp(Type::Int);
                 let arg1 = self.cgen(val);    
                 self.emit(Instruction::Not { dest: dest.clone(), arg1 });
                 dest
             },
 
+            ParserNode::Cast { ntype, from, val } => {
+                let arg1 = self.cgen(val);
+                self.cgen_conversion(arg1, *from, *ntype)
+            },
+            ParserNode::SizeOf { ntype, .. } => {
+                Operand::Const(ConstValue::Int(ntype.size() as i32))
+            },
+
+            // variadic builtins
+            ParserNode::VaStart { list, .. } => {
+                let dest = self.cgen(list);
+                self.emit(Instruction::VaStart { dest: dest.clone() });
+                dest
+            },
+            ParserNode::VaArg { list, ntype } => {
+                let list = self.cgen(list);
+                let dest = self.new_temp(*ntype);
+                self.emit(Instruction::VaArg { dest: dest.clone(), list, ty: *ntype });
+                dest
+            },
+            ParserNode::VaEnd { .. } => Operand::None,
+
+            ParserNode::StringLiteral(s) => {
+                let count = self.strings.len();
+                let label = self.strings.entry(s.clone())
+                    .or_insert_with(|| format!(".LC{}", count)).clone();
+                let dest = self.new_temp(Type::Pointer);
+                self.emit(Instruction::LoadAddress { dest: dest.clone(), label });
+                dest
+            },
+
             // factor
-            ParserNode::Var{ ident, ntype: _} => Operand::Var(ident.clone()),
+            ParserNode::Var{ ident, ntype} => Operand::Var(ident.clone(), *ntype),
             ParserNode::Const(val) => Operand::Const(val.clone()),
             ParserNode::SubExp { val} => {
                 self.cgen(val)
@@ -396,10 +568,70 @@ impl CodeGen {
         }
     }
 
-    fn new_temp(&mut self) -> Operand {
+    fn cgen_conversion(&mut self, arg1: Operand, from: Type, to: Type) -> Operand {
+        let dest_type = to;
+        // a pointer converts like the address sized integer
+        let from = if from == Type::Pointer { Type::UnsignedLong } else { from };
+        let to = if to == Type::Pointer { Type::UnsignedLong } else { to };
+        if from == to {
+            return arg1;
+        }
+        let dest = self.new_temp(dest_type);
+        let inst = if from.is_integer() && to.is_integer() {
+            if to.size() == from.size() {
+                // same width, only the signedness changes
+                Instruction::Assign { dest: dest.clone(), arg1 }
+            } else if to.size() < from.size() {
+                Instruction::Truncate { dest: dest.clone(), arg1, from, to }
+            } else if from.is_signed() {
+                Instruction::SignExtend { dest: dest.clone(), arg1, from, to }
+            } else {
+                Instruction::ZeroExtend { dest: dest.clone(), arg1, from, to }
+            }
+        } else if from.is_integer() {
+            Instruction::IntToFloat { dest: dest.clone(), arg1, from, to }
+        } else if to.is_integer() {
+            Instruction::FloatToInt { dest: dest.clone(), arg1, from, to }
+        } else {
+            Instruction::FloatConvert { dest: dest.clone(), arg1, from, to }
+        };
+        self.emit(inst);
+        dest
+    }
+
+    // the analyzer already converted every operand, so the type of an
+    // expression can be read back from its leaves
+    fn expr_type(&self, node: &ParserNode) -> Type {
+        match node {
+            ParserNode::Var { ntype, .. } | ParserNode::Cast { ntype, .. } |
+            ParserNode::VaArg { ntype, .. } => *ntype,
+            ParserNode::Const(ConstValue::Float(_)) => Type::Float,
+            ParserNode::Const(ConstValue::Double(_)) => Type::Double,
+            ParserNode::Const(ConstValue::Char(_)) => Type::Char,
+            ParserNode::StringLiteral(_) => Type::Pointer,
+            ParserNode::FuncCall { ident, .. } => {
+                match self.signatures.get(ident) {
+                    Some(sig) => sig.ret_type,
+                    None => Type::Int,
+                }
+            },
+            ParserNode::SubExp { val } => self.expr_type(val),
+            ParserNode::Neg { val } | ParserNode::Complement { val } => self.expr_type(val).promote(),
+            ParserNode::Assign { left, .. } |
+            ParserNode::BitwiseOr { left, .. } | ParserNode::BitwiseXor { left, .. } |
+            ParserNode::BitwiseAnd { left, .. } |
+            ParserNode::ShiftLeft { left, .. } | ParserNode::ShiftRight { left, .. } |
+            ParserNode::Add { left, .. } | ParserNode::Sub { left, .. } |
+            ParserNode::Mul { left, .. } | ParserNode::Div { left, .. } |
+            ParserNode::Mod { left, .. } => self.expr_type(left),
+            _ => Type::Int,
+        }
+    }
+
+    fn new_temp(&mut self, ty: Type) -> Operand {
         let t = format!("t{}", self.temp_count);
         self.temp_count += 1;
-        Operand::Temp(t)
+        Operand::Temp(t, ty)
     }
     fn new_label(&mut self) -> String {
         let l = format!("L{}", self.label_count);
@@ -408,12 +640,7 @@ impl CodeGen {
     }
 
     pub fn print_instructions(&self) -> String {
-        let mut s = String::new();
-        for inst in self.instructions.iter() {
-            s.push_str(&inst.print().as_str());
-            s.push_str("\n");
-        }
-        s
+        print_program(&self.instructions, &self.frames, &self.signatures, &self.strings)
     }
 
 }
@@ -1,4 +1,7 @@
 pub mod analyzer;
 pub mod frame;
 pub mod instruction;
+pub mod interpreter;
 pub mod irgen;
+pub mod tac;
+pub mod verify;
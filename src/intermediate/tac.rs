@@ -0,0 +1,441 @@
+use std::fmt;
+
+use indexmap::IndexMap;
+
+use crate::{intermediate::{analyzer::Signature, frame::{Frame, new_frame}, instruction::{Instruction, type_name}, irgen::Operand}, parser::{node::ConstValue, token::Type}};
+
+// Textual TAC, one item per line, blank lines and '#' comments are ignored:
+//
+//   string .LC0 "text"                       string literal and its label
+//   declare int printf(ptr, ...)             external function
+//   function double pick(n:int, ...)         starts a function, its label follows
+//   function inline int sq(x:int)            one declared inline
+//   pick:                                    label
+//      BeginFunc 20 ... EndFunc              instructions, as Instruction::print
+//
+// names are written `name:type`, temporaries are the names t<n>, their SSA
+// versions t<n>.<v>, and _ret.
+// constants are `5`, `1.5`, `1.5f`, `'a'` (escaped), or `void`.
+// types are int, float, double, char, short, long, uchar, ushort, uint,
+// ulong, ptr and void
+
+pub struct TacProgram {
+    pub instructions: Vec<Instruction>,
+    pub frames: IndexMap<String, Frame>,
+    pub signatures: IndexMap<String, Signature>,
+    pub strings: IndexMap<String, String>,
+}
+
+#[derive(Debug)]
+pub enum TacError {
+    InvalidLine(usize, String),
+    InvalidOperand(usize, String),
+    UnknownType(usize, String),
+    InvalidFunction(usize, String),
+}
+
+impl fmt::Display for TacError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TacError::InvalidLine(line, s) => write!(f, "TacError: line {}: invalid instruction '{}'", line, s),
+            TacError::InvalidOperand(line, s) => write!(f, "TacError: line {}: invalid operand '{}'", line, s),
+            TacError::UnknownType(line, s) => write!(f, "TacError: line {}: unknown type '{}'", line, s),
+            TacError::InvalidFunction(line, s) => write!(f, "TacError: line {}: {}", line, s),
+        }
+    }
+}
+
+pub fn print_program(instructions: &[Instruction], frames: &IndexMap<String, Frame>,
+    signatures: &IndexMap<String, Signature>, strings: &IndexMap<String, String>) -> String {
+    let mut s = String::new();
+    for (text, label) in strings {
+        s.push_str(&format!("string {} {}\n", label, quote(text)));
+    }
+    for (name, signature) in signatures {
+        if frames.contains_key(name) { continue; }
+        let mut params: Vec<String> = signature.params.iter().map(|ty| type_name(*ty).to_string()).collect();
+        if signature.variadic {
+            params.push(String::from("..."));
+        }
+        s.push_str(&format!("declare {} {}({})\n", type_name(signature.ret_type), name, params.join(", ")));
+    }
+    for inst in instructions {
+        if let Instruction::Label(l) = inst && let Some(frame) = frames.get(l) {
+            let mut params: Vec<String> = frame.params.iter()
+                .map(|p| format!("{}:{}", p.name, type_name(p.stype))).collect();
+            if frame.variadic {
+                params.push(String::from("..."));
+            }
+            let hint = if frame.inline { "inline " } else { "" };
+            s.push_str(&format!("function {}{} {}({})\n", hint, type_name(frame.ret_type), l, params.join(", ")));
+        }
+        s.push_str(&inst.print());
+        s.push('\n');
+    }
+    s
+}
+
+pub fn parse_program(input: &str) -> Result<TacProgram, TacError> {
+    let mut program = TacProgram {
+        instructions: Vec::new(),
+        frames: IndexMap::new(),
+        signatures: IndexMap::new(),
+        strings: IndexMap::new(),
+    };
+    let mut current: Option<String> = None;
+
+    for (i, line) in input.lines().enumerate() {
+        let n = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("string ") {
+            let (label, text) = rest.split_once(' ').ok_or(TacError::InvalidLine(n, line.to_string()))?;
+            let text = unquote(text.trim()).ok_or(TacError::InvalidLine(n, line.to_string()))?;
+            program.strings.insert(text, label.to_string());
+        } else if let Some(rest) = line.strip_prefix("declare ") {
+            let (name, ret_type, params, variadic) = parse_header(n, rest, false)?;
+            let params = params.into_iter().map(|(_, ty)| ty).collect();
+            program.signatures.insert(name, Signature { ret_type, params, variadic });
+        } else if let Some(rest) = line.strip_prefix("function ") {
+            let (inline, rest) = match rest.strip_prefix("inline ") {
+                Some(rest) => (true, rest),
+                None => (false, rest),
+            };
+            let (name, ret_type, params, variadic) = parse_header(n, rest, true)?;
+            let mut frame = new_frame(name.clone(), ret_type);
+            frame.variadic = variadic;
+            frame.inline = inline;
+            for (param, ty) in params.iter() {
+                frame.allocate_param(param.clone(), 1, *ty);
+            }
+            let params = params.into_iter().map(|(_, ty)| ty).collect();
+            program.signatures.insert(name.clone(), Signature { ret_type, params, variadic });
+            program.frames.insert(name.clone(), frame);
+            current = Some(name);
+        } else {
+            let inst = parse_instruction(n, line)?;
+            match &inst {
+                Instruction::BeginFunc(size) => {
+                    let frame = current.as_ref().and_then(|name| program.frames.get_mut(name))
+                        .ok_or(TacError::InvalidFunction(n, String::from("BeginFunc outside a function")))?;
+                    frame.locals_size = *size;
+                },
+                Instruction::EndFunc => current = None,
+                _ => (),
+            }
+            program.instructions.push(inst);
+        }
+    }
+    Ok(program)
+}
+
+// name, return type, params, variadic
+type Header = (String, Type, Vec<(String, Type)>, bool);
+
+// `type name(params)`, params are `name:type` in functions and bare types in declarations
+fn parse_header(n: usize, text: &str, named: bool) -> Result<Header, TacError> {
+    let invalid = || TacError::InvalidFunction(n, format!("invalid header '{}'", text));
+    let (head, rest) = text.split_once('(').ok_or_else(invalid)?;
+    let params_text = rest.trim().strip_suffix(')').ok_or_else(invalid)?;
+    let (ret, name) = head.trim().split_once(' ').ok_or_else(invalid)?;
+    let ret_type = parse_type(n, ret)?;
+
+    let mut params = Vec::new();
+    let mut variadic = false;
+    for param in params_text.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        if variadic {
+            return Err(invalid());
+        }
+        if param == "..." {
+            variadic = true;
+        } else if named {
+            let (param_name, ty) = param.rsplit_once(':').ok_or_else(invalid)?;
+            params.push((param_name.to_string(), parse_type(n, ty)?));
+        } else {
+            params.push((String::new(), parse_type(n, param)?));
+        }
+    }
+    Ok((name.trim().to_string(), ret_type, params, variadic))
+}
+
+fn parse_instruction(n: usize, line: &str) -> Result<Instruction, TacError> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let invalid = || TacError::InvalidLine(n, line.to_string());
+    let number = |s: &str| s.parse::<usize>().map_err(|_| invalid());
+
+    let inst = match tokens.as_slice() {
+        [label] if label.ends_with(':') && label.len() > 1 => {
+            Instruction::Label(label[..label.len() - 1].to_string())
+        },
+        ["Goto", label] => Instruction::Goto(label.to_string()),
+        ["BeginFunc", size] => Instruction::BeginFunc(number(size)?),
+        ["EndFunc"] => Instruction::EndFunc,
+        ["LCall", name] => Instruction::LCall(name.to_string()),
+        ["PushParam", ty, op] => Instruction::PushParam(parse_operand(n, op)?, parse_type(n, ty)?),
+        ["PopParams", size] => Instruction::PopParams(number(size)?),
+        ["IfZero", cond, "Goto", label] => Instruction::IfZero { cond: parse_operand(n, cond)?, label: label.to_string() },
+        ["Return"] => Instruction::Return { dest: Operand::None },
+        ["Return", op] => Instruction::Return { dest: parse_operand(n, op)? },
+        [dest, "=", rest @ ..] => {
+            let dest = parse_operand(n, dest)?;
+            match rest {
+                ["va_start"] => Instruction::VaStart { dest },
+                [label] if label.starts_with('&') => Instruction::LoadAddress { dest, label: label[1..].to_string() },
+                [arg] => Instruction::Assign { dest, arg1: parse_operand(n, arg)? },
+                ["va_arg", list, ty] => Instruction::VaArg { dest, list: parse_operand(n, list)?, ty: parse_type(n, ty)? },
+                [op, arg] => {
+                    let arg1 = parse_operand(n, arg)?;
+                    match *op {
+                        "neg" => Instruction::Neg { dest, arg1 },
+                        "compl" => Instruction::Complement { dest, arg1 },
+                        "not" => Instruction::Not { dest, arg1 },
+                        _ => return Err(invalid()),
+                    }
+                },
+                [conv, from, arg, "to", to] => {
+                    let arg1 = parse_operand(n, arg)?;
+                    let from = parse_type(n, from)?;
+                    let to = parse_type(n, to)?;
+                    match *conv {
+                        "sext" => Instruction::SignExtend { dest, arg1, from, to },
+                        "zext" => Instruction::ZeroExtend { dest, arg1, from, to },
+                        "trunc" => Instruction::Truncate { dest, arg1, from, to },
+                        "itof" => Instruction::IntToFloat { dest, arg1, from, to },
+                        "ftoi" => Instruction::FloatToInt { dest, arg1, from, to },
+                        "fconv" => Instruction::FloatConvert { dest, arg1, from, to },
+                        _ => return Err(invalid()),
+                    }
+                },
+                [a, op, b] => {
+                    let arg1 = parse_operand(n, a)?;
+                    let arg2 = parse_operand(n, b)?;
+                    match *op {
+                        "||" => Instruction::LogicalOr { dest, arg1, arg2 },
+                        "&&" => Instruction::LogicalAnd { dest, arg1, arg2 },
+                        "|" => Instruction::BitwiseOr { dest, arg1, arg2 },
+                        "^" => Instruction::BitwiseXor { dest, arg1, arg2 },
+                        "&" => Instruction::BitwiseAnd { dest, arg1, arg2 },
+                        "==" => Instruction::Equal { dest, arg1, arg2 },
+                        "!=" => Instruction::NotEqual { dest, arg1, arg2 },
+                        ">" => Instruction::Greater { dest, arg1, arg2 },
+                        ">=" => Instruction::GreaterEqual { dest, arg1, arg2 },
+                        "<" => Instruction::Less { dest, arg1, arg2 },
+                        "<=" => Instruction::LessEqual { dest, arg1, arg2 },
+                        "<<" => Instruction::ShiftLeft { dest, arg1, arg2 },
+                        ">>" => Instruction::ShiftRight { dest, arg1, arg2 },
+                        "+" => Instruction::Add { dest, arg1, arg2 },
+                        "-" => Instruction::Sub { dest, arg1, arg2 },
+                        "*" => Instruction::Mul { dest, arg1, arg2 },
+                        "/" => Instruction::Div { dest, arg1, arg2 },
+                        "%" => Instruction::Mod { dest, arg1, arg2 },
+                        _ => return Err(invalid()),
+                    }
+                },
+                _ => return Err(invalid()),
+            }
+        },
+        _ => return Err(invalid()),
+    };
+    Ok(inst)
+}
+
+fn parse_operand(n: usize, token: &str) -> Result<Operand, TacError> {
+    let invalid = || TacError::InvalidOperand(n, token.to_string());
+    if token.starts_with('\'') {
+        let inner = token.strip_prefix('\'').and_then(|t| t.strip_suffix('\'')).ok_or_else(invalid)?;
+        let text = unescape(inner).ok_or_else(invalid)?;
+        let mut chars = text.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(Operand::Const(ConstValue::Char(c))),
+            _ => Err(invalid()),
+        };
+    }
+    if let Some((name, ty)) = token.rsplit_once(':') {
+        if name.is_empty() {
+            return Err(invalid());
+        }
+        let ty = parse_type(n, ty)?;
+        let is_temp = name == "_ret" || name.strip_prefix('t').is_some_and(|num| {
+            num.split('.').all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+        });
+        return Ok(if is_temp { Operand::Temp(name.to_string(), ty) } else { Operand::Var(name.to_string(), ty) });
+    }
+    if token == "void" {
+        return Ok(Operand::Const(ConstValue::Void));
+    }
+    if let Ok(v) = token.parse::<i32>() {
+        return Ok(Operand::Const(ConstValue::Int(v)));
+    }
+    if let Some(f) = token.strip_suffix('f') && let Ok(v) = f.parse::<f32>() {
+        return Ok(Operand::Const(ConstValue::Float(v)));
+    }
+    match token.parse::<f64>() {
+        Ok(v) => Ok(Operand::Const(ConstValue::Double(v))),
+        Err(_) => Err(invalid()),
+    }
+}
+
+fn parse_type(n: usize, token: &str) -> Result<Type, TacError> {
+    let ty = match token {
+        "int" => Type::Int,
+        "float" => Type::Float,
+        "double" => Type::Double,
+        "char" => Type::Char,
+        "short" => Type::Short,
+        "long" => Type::Long,
+        "uchar" => Type::UnsignedChar,
+        "ushort" => Type::UnsignedShort,
+        "uint" => Type::UnsignedInt,
+        "ulong" => Type::UnsignedLong,
+        "ptr" => Type::Pointer,
+        "void" => Type::Void,
+        _ => return Err(TacError::UnknownType(n, token.to_string())),
+    };
+    Ok(ty)
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.escape_default())
+}
+
+fn unquote(s: &str) -> Option<String> {
+    unescape(s.strip_prefix('"')?.strip_suffix('"')?)
+}
+
+// reverses char::escape_default
+fn unescape(s: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => out.push('\n'),
+            't' => out.push('\t'),
+            'r' => out.push('\r'),
+            '0' => out.push('\0'),
+            '\\' => out.push('\\'),
+            '\'' => out.push('\''),
+            '"' => out.push('"'),
+            'u' => {
+                let rest: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                let hex = rest.strip_prefix('{')?;
+                out.push(char::from_u32(u32::from_str_radix(hex, 16).ok()?)?);
+            },
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{intermediate::{analyzer::new_analyzer, irgen::new_codegen}, parser::parser::new_parser};
+
+    use super::*;
+
+    fn tac_of(input: &str) -> (Vec<Instruction>, String) {
+        let mut parser = new_parser(input).unwrap();
+        let mut program_node = parser.parse().unwrap();
+        let mut analyzer = new_analyzer();
+        analyzer.analyze(&mut program_node).unwrap();
+        let mut code_gen = new_codegen(analyzer.function_frames, analyzer.function_signatures);
+        code_gen.cgen(&program_node);
+        let text = code_gen.print_instructions();
+        (code_gen.instructions, text)
+    }
+
+    #[test]
+    fn tac_round_trip() {
+        let cases = [
+            "int f(int a, int b) { int c = a / b + 2; if (c < a) { c = -c; } return c % 3; }",
+            "int printf(char *fmt, ...); int f() { printf(\"a b\\n\\t\\\"q\\\"\", 7); return 0; }",
+            "double g(float x, long y) { double d = x * 1.5; return d + y; }",
+            "unsigned int h(unsigned char c, short s) { return ~c & (s << 2) | !s ^ (c >> 1); }",
+            "int v(int n, ...) { va_list ap; va_start(ap, n); int x = va_arg(ap, int); va_end(ap); return x && n || 0; }",
+            "int w(int n) { int s = 0; while (n > 0) { s = s + n; n = n - 1; } return s; } int main() { return w(10); }",
+        ];
+
+        for input in cases {
+            let (instructions, text) = tac_of(input);
+            let program = parse_program(&text);
+            assert!(program.is_ok(), "failed at: {}\n{}", input, text);
+            let program = program.unwrap();
+            assert_eq!(program.instructions, instructions, "failed at: {}", input);
+            let reprinted = print_program(&program.instructions, &program.frames,
+                &program.signatures, &program.strings);
+            assert_eq!(reprinted, text, "failed at: {}", input);
+        }
+    }
+
+    #[test]
+    fn tac_constants() {
+        let cases = [
+            "   t0:int = -5",
+            "   t0:double = 1.0",
+            "   t0:double = 1e100",
+            "   t0:float = 2.5f",
+            "   t0:char = 'x'",
+            "   t0:char = '\\u{20}'",
+            "   t0:char = '\\n'",
+            "   t0:char = '\\''",
+            "   t0:int = t1:int - -5",
+            "   t0:int = neg 5",
+            "   Return void",
+        ];
+
+        for input in cases {
+            let got = parse_program(input).unwrap();
+            assert_eq!(got.instructions[0].print(), input, "failed at: {}", input);
+        }
+    }
+
+    #[test]
+    fn tac_frames() {
+        let input = "
+            declare int printf(ptr, ...)
+            function double pick(n:int, x:double, ...)
+            pick:
+               BeginFunc 12
+               Return x:double
+               EndFunc
+            function inline int sq(x:int)
+            sq:
+               BeginFunc 0
+               Return x:int
+               EndFunc
+        ";
+        let program = parse_program(input).unwrap();
+        let frame = &program.frames["pick"];
+        assert_eq!(frame.locals_size, 12);
+        assert!(frame.variadic);
+        assert_eq!(frame.params.len(), 2);
+        assert!(!frame.inline && program.frames["sq"].inline);
+        assert!(program.signatures["printf"].variadic);
+        assert_eq!(program.signatures["printf"].params, vec![Type::Pointer]);
+        assert_eq!(program.signatures["pick"].ret_type, Type::Double);
+    }
+
+    #[test]
+    fn tac_errors() {
+        let cases = [
+            "   t0:int = a:int ** b:int",
+            "   t0:int = a:integer + 1",
+            "   t0:int = 1.5.5",
+            "   IfZero t0 Goto L0",
+            "   BeginFunc 4",
+            "function int f(a, ...)",
+            "declare int f(..., int)",
+        ];
+
+        for input in cases {
+            let got = parse_program(input);
+            assert!(got.is_err(), "failed at: {}", input);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::fmt;
+
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{intermediate::{analyzer::Signature, instruction::Instruction, irgen::Operand}, parser::token::Type};
+
+// checks the invariants the backend relies on: functions are `name:` +
+// BeginFunc ... EndFunc, jumps stay inside their function, temporaries are
+// defined on every path to a use and calls push as many params as the
+// callee takes. runs after irgen and after every pass that rewrites the IR
+
+#[derive(Debug)]
+pub enum VerifyError {
+    OutsideFunction(usize, String),
+    UnbalancedFunction(String),
+    DuplicateLabel { func: String, label: String },
+    UndefinedLabel { func: String, label: String },
+    UndefinedTemp { func: String, temp: String, inst: String },
+    NoneOperand { func: String, inst: String },
+    UnknownCallee { func: String, callee: String },
+    ArityMismatch { func: String, callee: String, expected: usize, found: usize },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyError::OutsideFunction(i, inst) => write!(f, "VerifyError: instruction {} '{}' is outside a function", i, inst.trim()),
+            VerifyError::UnbalancedFunction(s) => write!(f, "VerifyError: {}", s),
+            VerifyError::DuplicateLabel { func, label } => write!(f, "VerifyError: in '{}': label '{}' defined twice", func, label),
+            VerifyError::UndefinedLabel { func, label } => write!(f, "VerifyError: in '{}': jump to undefined label '{}'", func, label),
+            VerifyError::UndefinedTemp { func, temp, inst } => write!(f, "VerifyError: in '{}': '{}' may be used before definition at '{}'", func, temp, inst.trim()),
+            VerifyError::NoneOperand { func, inst } => write!(f, "VerifyError: in '{}': missing operand at '{}'", func, inst.trim()),
+            VerifyError::UnknownCallee { func, callee } => write!(f, "VerifyError: in '{}': call to undeclared function '{}'", func, callee),
+            VerifyError::ArityMismatch { func, callee, expected, found }
+            => write!(f, "VerifyError: in '{}': '{}' takes {} params, {} pushed", func, callee, expected, found),
+        }
+    }
+}
+
+pub fn verify(instructions: &[Instruction], signatures: &IndexMap<String, Signature>) -> Result<(), VerifyError> {
+    for (name, start, end) in function_ranges(instructions)? {
+        let body = &instructions[start..=end];
+        let labels = check_labels(&name, body)?;
+        check_calls(&name, body, signatures)?;
+        check_operands(&name, body, signatures)?;
+        check_temps(&name, body, &labels)?;
+    }
+    Ok(())
+}
+
+// (name, index of the function label, index of EndFunc)
+fn function_ranges(instructions: &[Instruction]) -> Result<Vec<(String, usize, usize)>, VerifyError> {
+    let mut ranges = Vec::new();
+    let mut open: Option<(String, usize)> = None;
+    let mut i = 0;
+    while i < instructions.len() {
+        match (&instructions[i], &open) {
+            (Instruction::Label(name), None) => {
+                if !matches!(instructions.get(i + 1), Some(Instruction::BeginFunc(_))) {
+                    return Err(VerifyError::UnbalancedFunction(format!("label '{}' outside a function is not followed by BeginFunc", name)));
+                }
+                open = Some((name.clone(), i));
+                i += 1;
+            },
+            (Instruction::BeginFunc(_), Some((name, _))) => {
+                return Err(VerifyError::UnbalancedFunction(format!("BeginFunc inside '{}' before its EndFunc", name)));
+            },
+            (Instruction::EndFunc, Some((name, start))) => {
+                ranges.push((name.clone(), *start, i));
+                open = None;
+            },
+            (Instruction::EndFunc, None) => {
+                return Err(VerifyError::UnbalancedFunction(format!("EndFunc at instruction {} without BeginFunc", i)));
+            },
+            (_, Some(_)) => (),
+            (inst, None) => return Err(VerifyError::OutsideFunction(i, inst.print())),
+        }
+        i += 1;
+    }
+    if let Some((name, _)) = open {
+        return Err(VerifyError::UnbalancedFunction(format!("'{}' has no EndFunc", name)));
+    }
+    Ok(ranges)
+}
+
+// maps every label of the function to its index in `body`
+fn check_labels(func: &str, body: &[Instruction]) -> Result<IndexMap<String, usize>, VerifyError> {
+    let mut labels = IndexMap::new();
+    for (i, inst) in body.iter().enumerate() {
+        if let Instruction::Label(l) = inst
+            && labels.insert(l.clone(), i).is_some() {
+            return Err(VerifyError::DuplicateLabel { func: func.to_string(), label: l.clone() });
+        }
+    }
+    for inst in body {
+        if let Instruction::Goto(label) | Instruction::IfZero { label, .. } = inst
+            && !labels.contains_key(label) {
+            return Err(VerifyError::UndefinedLabel { func: func.to_string(), label: label.clone() });
+        }
+    }
+    Ok(labels)
+}
+
+fn check_calls(func: &str, body: &[Instruction], signatures: &IndexMap<String, Signature>) -> Result<(), VerifyError> {
+    let mut pushed = 0;
+    for inst in body {
+        match inst {
+            Instruction::PushParam(..) => pushed += 1,
+            Instruction::LCall(callee) => {
+                let sig = signatures.get(callee).ok_or_else(|| VerifyError::UnknownCallee {
+                    func: func.to_string(), callee: callee.clone()
+                })?;
+                let expected = sig.params.len();
+                if pushed < expected || (pushed > expected && !sig.variadic) {
+                    return Err(VerifyError::ArityMismatch { func: func.to_string(), callee: callee.clone(), expected, found: pushed });
+                }
+                pushed = 0;
+            },
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+// `Return` without a value is only valid in a void function
+fn check_operands(func: &str, body: &[Instruction], signatures: &IndexMap<String, Signature>) -> Result<(), VerifyError> {
+    let returns_void = signatures.get(func).is_some_and(|s| s.ret_type == Type::Void);
+    for inst in body {
+        if returns_void && matches!(inst, Instruction::Return { dest: Operand::None }) {
+            continue;
+        }
+        let none_def = matches!(inst.def(), Some(Operand::None));
+        if none_def || inst.uses().contains(&Operand::None) {
+            return Err(VerifyError::NoneOperand { func: func.to_string(), inst: inst.print() });
+        }
+    }
+    Ok(())
+}
+
+fn successors(i: usize, body: &[Instruction], labels: &IndexMap<String, usize>) -> Vec<usize> {
+    match &body[i] {
+        Instruction::Goto(label) => vec![labels[label]],
+        Instruction::IfZero { label, .. } => vec![i + 1, labels[label]],
+        Instruction::Return { .. } | Instruction::EndFunc => Vec::new(),
+        _ => vec![i + 1],
+    }
+}
+
+// temporaries defined on every path reaching an instruction (a forward must
+// analysis, unreached instructions stay None). LCall defines `_ret`
+fn check_temps(func: &str, body: &[Instruction], labels: &IndexMap<String, usize>) -> Result<(), VerifyError> {
+    let temp_name = |op: &Operand| match op {
+        Operand::Temp(name, _) => Some(name.clone()),
+        _ => None,
+    };
+    let defined_by = |inst: &Instruction| match inst {
+        Instruction::LCall(_) => Some(String::from("_ret")),
+        _ => inst.def().as_ref().and_then(temp_name),
+    };
+
+    let mut defined_in: Vec<Option<IndexSet<String>>> = vec![None; body.len()];
+    defined_in[0] = Some(IndexSet::new());
+    let mut worklist = vec![0];
+    while let Some(i) = worklist.pop() {
+        let mut out = defined_in[i].clone().unwrap();
+        out.extend(defined_by(&body[i]));
+        for s in successors(i, body, labels) {
+            let next = match &defined_in[s] {
+                None => out.clone(),
+                Some(prev) => prev.intersection(&out).cloned().collect(),
+            };
+            if defined_in[s].as_ref() != Some(&next) {
+                defined_in[s] = Some(next);
+                worklist.push(s);
+            }
+        }
+    }
+
+    for (inst, defined) in body.iter().zip(&defined_in) {
+        let Some(defined) = defined else { continue };
+        for temp in inst.uses().iter().filter_map(temp_name) {
+            if !defined.contains(&temp) {
+                return Err(VerifyError::UndefinedTemp { func: func.to_string(), temp, inst: inst.print() });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{intermediate::{analyzer::new_analyzer, irgen::new_codegen, tac::parse_program}, parser::parser::new_parser};
+
+    use super::*;
+
+    #[test]
+    fn verify_generated_ir() {
+        let cases = [
+            "int f(int a, int b) { int c = a / b + 2; if (c < a) { c = -c; } else { c = c + 1; } return c % 3; }",
+            "int printf(char *fmt, ...); int f() { printf(\"%d %d\\n\", 7, 8); return 0; }",
+            "int w(int n) { int s = 0; while (n > 0) { s = s + n; n = n - 1; } return s; } int main() { return w(w(10)); }",
+            "int v(int n, ...) { va_list ap; va_start(ap, n); int x = va_arg(ap, int); va_end(ap); return x && n || 0; }",
+            "void g(int n) { for (int i = 0; i < n; i = i + 1) { n = n - 1; } }",
+        ];
+
+        for input in cases {
+            let mut parser = new_parser(input).unwrap();
+            let mut program_node = parser.parse().unwrap();
+            let mut analyzer = new_analyzer();
+            analyzer.analyze(&mut program_node).unwrap();
+            let mut code_gen = new_codegen(analyzer.function_frames, analyzer.function_signatures);
+            code_gen.cgen(&program_node);
+            let got = verify(&code_gen.instructions, &code_gen.signatures);
+            assert!(got.is_ok(), "failed at: {}\n{}", input, got.unwrap_err());
+        }
+    }
+
+    #[test]
+    fn verify_errors() {
+        // whether the error is the one the case is about
+        type Expected = fn(&VerifyError) -> bool;
+        let cases: [(&str, Expected); 12] = [
+            // jump to a missing label
+            ("function int f()\nf:\n BeginFunc 0\n Goto L9\n EndFunc",
+                |e| matches!(e, VerifyError::UndefinedLabel { func, label } if func == "f" && label == "L9")),
+            // label of another function
+            ("function int f()\nf:\n BeginFunc 0\nL0:\n EndFunc\nfunction int g()\ng:\n BeginFunc 0\n Goto L0\n EndFunc",
+                |e| matches!(e, VerifyError::UndefinedLabel { func, label } if func == "g" && label == "L0")),
+            // missing EndFunc
+            ("function int f()\nf:\n BeginFunc 0\n Return 1",
+                |e| matches!(e, VerifyError::UnbalancedFunction(s) if s.contains("no EndFunc"))),
+            // nested BeginFunc
+            ("function int f()\nf:\n BeginFunc 0\n BeginFunc 0\n EndFunc",
+                |e| matches!(e, VerifyError::UnbalancedFunction(s) if s.contains("BeginFunc inside"))),
+            // instruction outside a function
+            ("t0:int = 1", |e| matches!(e, VerifyError::OutsideFunction(0, _))),
+            // temp defined on one path only
+            ("function int f(a:int)\nf:\n BeginFunc 4\n IfZero a:int Goto L0\n t0:int = 1\nL0:\n Return t0:int\n EndFunc",
+                |e| matches!(e, VerifyError::UndefinedTemp { temp, .. } if temp == "t0")),
+            // temp defined after its use in a loop
+            ("function int f()\nf:\n BeginFunc 0\nL0:\n t1:int = t0:int + 1\n t0:int = 1\n Goto L0\n EndFunc",
+                |e| matches!(e, VerifyError::UndefinedTemp { temp, .. } if temp == "t0")),
+            // _ret read without a call
+            ("function int f()\nf:\n BeginFunc 0\n t0:int = _ret:int\n Return t0:int\n EndFunc",
+                |e| matches!(e, VerifyError::UndefinedTemp { temp, .. } if temp == "_ret")),
+            // too few params
+            ("declare int g(int, int)\nfunction int f()\nf:\n BeginFunc 0\n t0:int = 1\n PushParam int t0:int\n LCall g\n t1:int = _ret:int\n PopParams 4\n Return t1:int\n EndFunc",
+                |e| matches!(e, VerifyError::ArityMismatch { callee, expected: 2, found: 1, .. } if callee == "g")),
+            // too many params for a non variadic callee
+            ("declare int g()\nfunction int f()\nf:\n BeginFunc 0\n t0:int = 1\n PushParam int t0:int\n LCall g\n PopParams 4\n Return 0\n EndFunc",
+                |e| matches!(e, VerifyError::ArityMismatch { callee, expected: 0, found: 1, .. } if callee == "g")),
+            // undeclared callee
+            ("function int f()\nf:\n BeginFunc 0\n LCall h\n Return 0\n EndFunc",
+                |e| matches!(e, VerifyError::UnknownCallee { callee, .. } if callee == "h")),
+            // missing return value in a non void function, the parser takes
+            // the bare Return and the verifier rejects it
+            ("function int f()\nf:\n BeginFunc 0\n Return\n EndFunc",
+                |e| matches!(e, VerifyError::NoneOperand { inst, .. } if inst.contains("Return"))),
+        ];
+
+        for (input, expected) in cases {
+            let program = parse_program(input).unwrap();
+            let got = verify(&program.instructions, &program.signatures);
+            assert!(got.as_ref().is_err_and(expected), "failed at: {}\n{:?}", input, got);
+        }
+    }
+}
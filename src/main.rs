@@ -4,11 +4,65 @@ mod intermediate;
 mod codegen;
 mod compiler;
 
-use crate::compiler::new_compiler;
+use std::env;
 
+use crate::{compiler::{Emit, new_compiler}, intermediate::irgen::Value, optimizer::passes::{PassError, parse_passes, pass_named, optimization_level, with_unrolling}};
+
+// usage: compilador [file.c] [--emit=tac] [--from-tac file.tac] [--run=func[,arg...]]
+//                   [-O0|-O1|-O2] [--passes=pass,...] [--print-after=pass]
+//                   [-funroll-loops] [--unroll-factor=n] [--unroll-budget=n]
 fn main() {
     let mut compiler = new_compiler();
-    compiler.compile("code.c");
+    let mut source = String::from("code.c");
+    let mut from_tac = None;
+    let mut unroll = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--emit=tac" => compiler.emit = Emit::Tac,
+            "--from-tac" => from_tac = Some(args.next().expect("--from-tac needs a file")),
+            "-funroll-loops" => unroll = true,
+            _ if arg.starts_with("--unroll-factor=") => {
+                compiler.pass_manager.unrolling.factor = arg["--unroll-factor=".len()..].parse().expect("--unroll-factor is a number");
+            },
+            _ if arg.starts_with("--unroll-budget=") => {
+                compiler.pass_manager.unrolling.budget = arg["--unroll-budget=".len()..].parse().expect("--unroll-budget is a number");
+            },
+            _ if arg.starts_with("--run=") => compiler.emit = run_spec(&arg["--run=".len()..]),
+            _ if arg.starts_with("-O") => compiler.pass_manager.passes = or_exit(optimization_level(&arg["-O".len()..])),
+            _ if arg.starts_with("--passes=") => compiler.pass_manager.passes = or_exit(parse_passes(&arg["--passes=".len()..])),
+            _ if arg.starts_with("--print-after=") => {
+                compiler.pass_manager.print_after = Some(or_exit(pass_named(&arg["--print-after=".len()..])));
+            },
+            _ => source = arg,
+        }
+    }
+    // added to whichever passes were chosen
+    if unroll {
+        compiler.pass_manager.passes = with_unrolling(std::mem::take(&mut compiler.pass_manager.passes));
+    }
 
+    match from_tac {
+        Some(path) => compiler.compile_tac(&path),
+        None => compiler.compile(&source),
+    }
 }
 
+// `func,1,2.5` calls func(1, 2.5), arguments are converted to the param types
+fn run_spec(spec: &str) -> Emit {
+    let mut parts = spec.split(',');
+    let entry = parts.next().unwrap_or("func").to_string();
+    let args = parts.map(|a| match a.trim().parse::<i64>() {
+        Ok(n) => Value::Int(n),
+        Err(_) => Value::Double(a.trim().parse().expect("--run arguments are numbers")),
+    }).collect();
+    Emit::Run { entry, args }
+}
+
+fn or_exit<T>(result: Result<T, PassError>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
@@ -215,6 +215,16 @@ impl CFGBuilder {
 }
 
 
+// the CFG of a single function, `instructions` runs from its label to EndFunc
+pub fn function_cfg(instructions: &[Instruction]) -> ControlFlowGraph {
+    let name = match instructions.first() {
+        Some(Instruction::Label(l)) => l.clone(),
+        _ => String::new(),
+    };
+    let mut cfg_builder = new_cfg_builder(name, instructions.to_vec());
+    cfg_builder.build((0, instructions.len() - 1))
+}
+
 pub fn create_cfgs(frames: &mut IndexMap<String, Frame>, instructions: &Vec<Instruction>) -> Vec<ControlFlowGraph> { // tbd
     let mut cfgs: Vec<ControlFlowGraph> = Vec::new();
     for (frame_name, fr) in frames {
@@ -0,0 +1,295 @@
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{intermediate::{instruction::Instruction, interpreter::{Trap, evaluate}, irgen::{Operand, Value, const_value}}, optimizer::ssa::{SsaFunction, variable_name}, parser::{node::ConstValue, token::Type}};
+
+// sparse conditional constant propagation (Wegman and Zadeck) on a function
+// in SSA form. instructions are folded by the interpreter's `evaluate`, so
+// integers wrap to their type and conversions round as the generated code
+// does. a division by zero or the MIN / -1 overflow traps at run time, it
+// is never folded and stays where it is. a branch on a constant becomes a
+// fallthrough and the blocks only it reached are removed
+
+#[derive(Debug, Clone, Copy)]
+enum Lattice {
+    // no executable path has defined it yet
+    Unknown,
+    Constant(Value),
+    Varying,
+}
+
+pub fn propagate_constants(function: &mut SsaFunction) {
+    let mut solver = new_solver(function);
+    solver.solve();
+    let Solver { values, edges, .. } = solver;
+    rewrite(function, &values, &edges);
+}
+
+struct Solver<'a> {
+    function: &'a SsaFunction,
+    // the names defined in the function, params are not and always vary
+    values: IndexMap<String, Lattice>,
+    // (from, to) edges found to run
+    edges: IndexSet<(usize, usize)>,
+    reached: Vec<bool>,
+    // the blocks that read each name
+    users: IndexMap<String, IndexSet<usize>>,
+}
+
+fn new_solver(function: &SsaFunction) -> Solver<'_> {
+    let mut values = IndexMap::new();
+    let mut users: IndexMap<String, IndexSet<usize>> = IndexMap::new();
+    for (b, block) in function.blocks.iter().enumerate() {
+        let phi_args = block.phis.iter().flat_map(|phi| phi.args.iter().cloned());
+        let uses = block.instructions.iter().flat_map(|inst| inst.uses());
+        for name in phi_args.chain(uses).filter_map(|op| variable_name(&op)) {
+            users.entry(name).or_default().insert(b);
+        }
+        let phi_dests = block.phis.iter().map(|phi| phi.dest.clone());
+        let defs = block.instructions.iter().filter_map(|inst| inst.def());
+        for name in phi_dests.chain(defs).filter_map(|op| variable_name(&op)) {
+            values.insert(name, Lattice::Unknown);
+        }
+    }
+    let reached = vec![false; function.blocks.len()];
+    Solver { function, values, edges: IndexSet::new(), reached, users }
+}
+
+impl Solver<'_> {
+    fn solve(&mut self) {
+        if self.function.blocks.is_empty() {
+            return;
+        }
+        self.reached[0] = true;
+        let mut worklist = vec![0];
+        while let Some(b) = worklist.pop() {
+            let block = &self.function.blocks[b];
+            let mut changed = Vec::new();
+            for phi in &block.phis {
+                // only the edges that run bring a value
+                let mut value = Lattice::Unknown;
+                for (p, arg) in block.preds.iter().zip(&phi.args) {
+                    if self.edges.contains(&(*p, b)) {
+                        value = meet(value, self.operand(arg, phi.dest.ty()));
+                    }
+                }
+                changed.extend(self.update(&phi.dest, value));
+            }
+            for inst in &block.instructions {
+                if let Some(dest) = inst.def() {
+                    let value = self.instruction(inst);
+                    changed.extend(self.update(&dest, value));
+                }
+            }
+
+            let targets = match block.instructions.last() {
+                Some(Instruction::IfZero { cond, .. }) => match self.operand(cond, cond.ty()) {
+                    Lattice::Unknown => Vec::new(),
+                    Lattice::Constant(v) if v.truthy() => vec![block.succs[0]],
+                    Lattice::Constant(_) => vec![block.succs[1]],
+                    Lattice::Varying => block.succs.clone(),
+                },
+                _ => block.succs.clone(),
+            };
+            for t in targets {
+                if self.edges.insert((b, t)) {
+                    self.reached[t] = true;
+                    worklist.push(t);
+                }
+            }
+            for name in changed {
+                let users = self.users.get(&name).into_iter().flatten();
+                worklist.extend(users.filter(|u| self.reached[**u]));
+            }
+        }
+    }
+
+    // lowers the value of `dest`, the name when it changed
+    fn update(&mut self, dest: &Operand, value: Lattice) -> Option<String> {
+        let name = variable_name(dest)?;
+        let old = *self.values.get(&name)?;
+        let value = meet(old, value);
+        if same(old, value) {
+            return None;
+        }
+        self.values.insert(name.clone(), value);
+        Some(name)
+    }
+
+    // constants are read as `ty`, names not defined here are params or `_ret`
+    fn operand(&self, op: &Operand, ty: Type) -> Lattice {
+        match op {
+            Operand::Const(c) => Lattice::Constant(const_value(c, ty)),
+            _ => variable_name(op).and_then(|name| self.values.get(&name).copied()).unwrap_or(Lattice::Varying),
+        }
+    }
+
+    fn instruction(&self, inst: &Instruction) -> Lattice {
+        let operands: Vec<Lattice> = inst.uses().iter().map(|op| self.operand(op, op.ty())).collect();
+        if operands.iter().any(|v| matches!(v, Lattice::Varying)) {
+            return Lattice::Varying;
+        }
+        if operands.iter().any(|v| matches!(v, Lattice::Unknown)) {
+            return Lattice::Unknown;
+        }
+        let read = |op: &Operand, ty: Type| match self.operand(op, ty) {
+            Lattice::Constant(v) => Ok(v),
+            _ => Err(Trap::UndefinedValue { func: self.function.name.clone(), name: op.print() }),
+        };
+        match evaluate(inst, &self.function.name, read) {
+            Ok(Some(v)) => Lattice::Constant(v),
+            // no value (calls, va_arg) or a trap left for run time
+            _ => Lattice::Varying,
+        }
+    }
+}
+
+fn rewrite(function: &mut SsaFunction, values: &IndexMap<String, Lattice>, edges: &IndexSet<(usize, usize)>) {
+    let constant_of = |op: &Operand| -> Option<Operand> {
+        let name = variable_name(op)?;
+        let Some(Lattice::Constant(v)) = values.get(&name) else { return None };
+        constant(*v, op.ty())
+    };
+
+    for block in function.blocks.iter_mut() {
+        // a phi of a constant becomes a copy of it
+        let mut copies = Vec::new();
+        block.phis.retain(|phi| match constant_of(&phi.dest) {
+            Some(c) => {
+                copies.push(Instruction::Assign { dest: phi.dest.clone(), arg1: c });
+                false
+            },
+            None => true,
+        });
+        for arg in block.phis.iter_mut().flat_map(|phi| phi.args.iter_mut()) {
+            if let Some(c) = constant_of(arg) {
+                *arg = c;
+            }
+        }
+        block.instructions.splice(0..0, copies);
+
+        for inst in block.instructions.iter_mut() {
+            if let Some(dest) = inst.def()
+                && let Some(c) = constant_of(&dest) {
+                *inst = Instruction::Assign { dest, arg1: c };
+                continue;
+            }
+            substitute(inst, &constant_of);
+        }
+    }
+
+    // a branch with one edge that runs falls through to it. both or none
+    // run when the condition varies or the block is dead
+    for b in 0..function.blocks.len() {
+        if !matches!(function.blocks[b].instructions.last(), Some(Instruction::IfZero { .. })) {
+            continue;
+        }
+        let dead: Vec<usize> = function.blocks[b].succs.iter().copied().filter(|s| !edges.contains(&(b, *s))).collect();
+        if let [dead] = dead[..] {
+            function.blocks[b].instructions.pop();
+            function.remove_edge(b, dead);
+        }
+    }
+    function.remove_unreachable();
+}
+
+// the uses the backend takes an immediate for: copies, returns, params and
+// either side of a binary instruction, as long as the other side is not
+// a constant too. floating constants are 64 bits and only go into copies
+fn substitute(inst: &mut Instruction, constant_of: &impl Fn(&Operand) -> Option<Operand>) {
+    let is_copy = matches!(inst, Instruction::Assign { .. });
+    let takes_immediate = is_copy || matches!(inst, Instruction::Return { .. } | Instruction::PushParam(..));
+    let mut uses = inst.uses_mut();
+    let binary = uses.len() == 2;
+    if !takes_immediate && !binary {
+        return;
+    }
+    let replacements: Vec<Option<Operand>> = uses.iter()
+        .map(|op| constant_of(op).filter(|c| is_copy || matches!(c, Operand::Const(ConstValue::Int(_)))))
+        .collect();
+    let all_constant = uses.iter().zip(&replacements).all(|(op, c)| c.is_some() || matches!(op, Operand::Const(_)));
+    if binary && all_constant {
+        return;
+    }
+    for (op, c) in uses.iter_mut().zip(replacements) {
+        if let Some(c) = c {
+            **op = c;
+        }
+    }
+}
+
+// the operand for a value of type `ty`. integers are 32-bit immediates, one
+// that does not fit keeps the instruction computing it
+pub fn constant(value: Value, ty: Type) -> Option<Operand> {
+    let c = match value {
+        Value::Int(n) if ty.size() <= 4 => ConstValue::Int(n as i32),
+        Value::Int(n) => ConstValue::Int(i32::try_from(n).ok()?),
+        Value::Float(f) => ConstValue::Float(f),
+        Value::Double(d) => ConstValue::Double(d),
+        _ => return None,
+    };
+    Some(Operand::Const(c))
+}
+
+fn meet(a: Lattice, b: Lattice) -> Lattice {
+    match (a, b) {
+        (Lattice::Unknown, x) | (x, Lattice::Unknown) => x,
+        (Lattice::Constant(x), Lattice::Constant(y)) if same_value(x, y) => a,
+        _ => Lattice::Varying,
+    }
+}
+
+fn same(a: Lattice, b: Lattice) -> bool {
+    match (a, b) {
+        (Lattice::Unknown, Lattice::Unknown) | (Lattice::Varying, Lattice::Varying) => true,
+        (Lattice::Constant(x), Lattice::Constant(y)) => same_value(x, y),
+        _ => false,
+    }
+}
+
+// floats by their bits, 0.0 and -0.0 are different constants
+fn same_value(a: Value, b: Value) -> bool {
+    match (a, b) {
+        (Value::Float(x), Value::Float(y)) => x.to_bits() == y.to_bits(),
+        (Value::Double(x), Value::Double(y)) => x.to_bits() == y.to_bits(),
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::optimizer::tests::{assert_equivalent, int_calls, optimize_ssa};
+
+    use super::*;
+
+    #[test]
+    fn constant_propagation() {
+        // (program, lines the folded TAC has, lines it no longer has)
+        let cases: [(&str, &[&str], &[&str]); 6] = [
+            ("int f(int n) { int x = 3; int y = x * 4; if (y > 10) { n = n + y; } else { n = n - 1; } return n; }",
+                &["+ 12"], &["IfZero", "- 1"]),
+            // the phi of k only ever sees 5
+            ("int f(int n) { int k = 5; int i = 0; while (i < n) { k = k * 1; i = i + 1; } return k; }",
+                &["Return 5"], &[]),
+            // signed overflow wraps as the machine does
+            ("int f(int n) { int m = 2147483647; m = m + 1; return m; }", &["Return -2147483648"], &["+"]),
+            ("int f(int n) { unsigned char c = 250; c = c + 10; unsigned int u = 0; u = u - 1; return c + (u > 5); }",
+                &["Return 5"], &[]),
+            // a division that traps is kept
+            ("int f(int n) { int z = 0; if (n > 100) { return 5 / z; } return 1; }", &["/"], &[]),
+            ("int f(int n) { int m = -2147483647 - 1; int d = -1; if (n > 100) { return m / d; } return 2; }", &["/"], &[]),
+        ];
+
+        for (input, present, absent) in cases {
+            let (code_gen, instructions) = optimize_ssa(input, propagate_constants);
+
+            let text: Vec<String> = instructions.iter().map(|i| i.print()).collect();
+            for p in present {
+                assert!(text.iter().any(|l| l.contains(p)), "'{}' missing in: {}\n{}", p, input, text.join("\n"));
+            }
+            for a in absent {
+                assert!(!text.iter().any(|l| l.contains(a)), "'{}' left in: {}\n{}", a, input, text.join("\n"));
+            }
+            assert_equivalent(&code_gen, &instructions, input, &int_calls(&[0, 3, 200]));
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{intermediate::{instruction::Instruction, irgen::Operand}, optimizer::ssa::{SsaFunction, variable_name}};
+
+// copy propagation on a function in SSA form. a name defined as a copy of
+// another name of the same type, or by a phi whose arguments are all one
+// name, is replaced by that name everywhere and its definition goes. `_ret`
+// is not propagated, every call changes it and the backend keeps it in %rax,
+// and neither are va_lists, va_arg advances its list in place
+
+pub fn propagate_copies(function: &mut SsaFunction) {
+    let excluded = function.va_lists();
+
+    // name -> the name it copies, found until no phi becomes trivial
+    let mut copies: IndexMap<String, Operand> = IndexMap::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in &function.blocks {
+            for phi in &block.phis {
+                let Some(dest) = variable_name(&phi.dest) else { continue };
+                if copies.contains_key(&dest) {
+                    continue;
+                }
+                let args: Vec<Operand> = phi.args.iter().map(|arg| resolve(&copies, arg)).filter(|arg| *arg != phi.dest).collect();
+                if let Some(first) = args.first()
+                    && args.iter().all(|arg| arg == first)
+                    && is_copy(&phi.dest, first, &excluded) {
+                    copies.insert(dest, first.clone());
+                    changed = true;
+                }
+            }
+            for inst in &block.instructions {
+                if let Instruction::Assign { dest, arg1 } = inst
+                    && let Some(name) = variable_name(dest)
+                    && !copies.contains_key(&name)
+                    && is_copy(dest, arg1, &excluded) {
+                    copies.insert(name, resolve(&copies, arg1));
+                    changed = true;
+                }
+            }
+        }
+    }
+    if copies.is_empty() {
+        return;
+    }
+
+    let copied = |op: &Operand| variable_name(op).is_some_and(|name| copies.contains_key(&name));
+    for block in function.blocks.iter_mut() {
+        block.phis.retain(|phi| !copied(&phi.dest));
+        block.instructions.retain(|inst| !matches!(inst, Instruction::Assign { dest, .. } if copied(dest)));
+        for arg in block.phis.iter_mut().flat_map(|phi| phi.args.iter_mut()) {
+            *arg = resolve(&copies, arg);
+        }
+        for inst in block.instructions.iter_mut() {
+            for op in inst.uses_mut() {
+                *op = resolve(&copies, op);
+            }
+        }
+    }
+}
+
+// a copy between two names the pass may merge
+fn is_copy(dest: &Operand, src: &Operand, excluded: &IndexSet<String>) -> bool {
+    let (Some(d), Some(s)) = (variable_name(dest), variable_name(src)) else { return false };
+    dest.ty() == src.ty() && !excluded.contains(&d) && !excluded.contains(&s)
+}
+
+// the name at the end of a chain of copies. in SSA the source of a copy
+// dominates it, so chains never loop
+fn resolve(copies: &IndexMap<String, Operand>, op: &Operand) -> Operand {
+    let mut op = op.clone();
+    while let Some(next) = variable_name(&op).and_then(|name| copies.get(&name)) {
+        op = next.clone();
+    }
+    op
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::optimizer::tests::{assert_equivalent, int_calls, optimize_ssa};
+
+    use super::*;
+
+    #[test]
+    fn copy_propagation() {
+        let cases = [
+            "int f(int n) { int a = n; int b = a; int c = b + a; return c; }",
+            "int g(int x) { return x + 1; } int f(int n) { int r = g(n); int s = r; return s * 2; }",
+            "int f(int n) { int a = n; int i = 0; while (i < n) { int t = a; a = t; i = i + 1; } return a; }",
+            "int f(int n) { int a = 1; int b = 2; int i = 0; while (i < n) { int t = a; a = b; b = t; i = i + 1; } return a * 10 + b; }",
+        ];
+
+        for input in cases {
+            let (code_gen, instructions) = optimize_ssa(input, |function| {
+                propagate_copies(function);
+                // only copies of `_ret` are left
+                for block in &function.blocks {
+                    for inst in &block.instructions {
+                        if let Instruction::Assign { dest, arg1 } = inst {
+                            assert!(!is_copy(dest, arg1, &IndexSet::new()), "{} left in: {}", inst.print(), input);
+                        }
+                    }
+                }
+            });
+            assert_equivalent(&code_gen, &instructions, input, &int_calls(&[0, 1, 2, 5]));
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use indexmap::IndexMap;
+
+use crate::{intermediate::instruction::Instruction, optimizer::{cfg::{Block, function_cfg}, liveness::new_liveness_analyzer}};
+
+// dead code elimination on the flat TAC of a function: an instruction that
+// does nothing but compute its result goes when the result is not live
+// after it. that can leave the instructions feeding it dead too, so each
+// removal re-checks the defs of the names it read, liveness runs only once.
+// a dead division that would trap goes as well, dividing by zero is
+// undefined in C
+
+pub fn eliminate_dead_code(instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let cfg = function_cfg(&instructions);
+    let mut lv_analyzer = new_liveness_analyzer(instructions.clone(), cfg.blocks);
+    lv_analyzer.gen_live_out();
+    lv_analyzer.gen_inst_live_out();
+
+    let mut block_of = vec![0; instructions.len()];
+    for (id, block) in lv_analyzer.blocks.iter().enumerate() {
+        let (a, b) = block.get_range();
+        block_of[a..=b].fill(id);
+    }
+    let mut readers: IndexMap<String, usize> = IndexMap::new();
+    let mut defs: IndexMap<String, Vec<usize>> = IndexMap::new();
+    for (i, inst) in instructions.iter().enumerate() {
+        for op in inst.uses() {
+            *readers.entry(op.print()).or_default() += 1;
+        }
+        if let Some(dest) = inst.def() {
+            defs.entry(dest.print()).or_default().push(i);
+        }
+    }
+
+    let mut dead = vec![false; instructions.len()];
+    let mut worklist = Vec::new();
+    for (i, inst) in instructions.iter().enumerate() {
+        if removable(inst) && inst.def().is_some_and(|dest| !lv_analyzer.live_out(i).contains(&dest.print())) {
+            dead[i] = true;
+            worklist.push(i);
+        }
+    }
+    while let Some(i) = worklist.pop() {
+        for op in instructions[i].uses() {
+            let name = op.print();
+            let Some(count) = readers.get_mut(&name) else { continue };
+            *count -= 1;
+            // with no reader left every def of the name goes, otherwise only
+            // the one read here may have lost its last reader
+            let candidates = if *count == 0 {
+                defs.get(&name).cloned().unwrap_or_default()
+            } else {
+                let block = &lv_analyzer.blocks[block_of[i]];
+                let (a, _) = block.get_range();
+                (a..i).rev().filter(|d| !dead[*d]).find(|d| defines(&instructions[*d], &name))
+                    .filter(|d| !read_after(&instructions, &dead, block, *d, &name))
+                    .into_iter().collect()
+            };
+            for d in candidates {
+                if !dead[d] && removable(&instructions[d]) {
+                    dead[d] = true;
+                    worklist.push(d);
+                }
+            }
+        }
+    }
+
+    instructions.into_iter().zip(dead)
+        .filter(|(_, dead)| !dead)
+        .map(|(inst, _)| inst)
+        .collect()
+}
+
+fn defines(inst: &Instruction, name: &str) -> bool {
+    inst.def().is_some_and(|dest| dest.print() == name)
+}
+
+// whether the value instruction `d` gives `name` is read by one still there,
+// in the rest of its block or after it when live out of the block
+fn read_after(instructions: &[Instruction], dead: &[bool], block: &Block, d: usize, name: &str) -> bool {
+    let (_, b) = block.get_range();
+    for inst in (d + 1..=b).filter(|i| !dead[*i]).map(|i| &instructions[i]) {
+        if inst.uses().iter().any(|op| op.print() == name) {
+            return true;
+        }
+        if defines(inst, name) {
+            return false;
+        }
+    }
+    block.live_out.contains(name)
+}
+
+fn removable(inst: &Instruction) -> bool {
+    inst.def().is_some() && !has_side_effects(inst)
+}
+
+// va_arg also moves its list to the next argument
+fn has_side_effects(inst: &Instruction) -> bool {
+    matches!(inst, Instruction::VaArg { .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{intermediate::{interpreter::new_interpreter, irgen::Value}, optimizer::{cfg::create_cfgs, tests::optimize_flat}};
+
+    use super::*;
+
+    #[test]
+    fn dead_code_elimination() {
+        // (program, function, lines it still has, lines it no longer has)
+        let cases: [(&str, &str, &[&str], &[&str]); 5] = [
+            ("int f(int n) { int a = n * 3; int b = a + 1; return n; }", "f", &["Return n"], &["*", "+"]),
+            // x is still read, but not the value its first def gives
+            ("int f(int n) { int x = n * 3; int y = x + 1; x = 5; return x; }", "f", &["5"], &["*", "+"]),
+            // the division is dead, the loop it reads from is not
+            ("int f(int n) { int s = 0; int i = 0; while (i < n) { s = s + i; i = i + 1; } int d = s / 0; return i; }",
+                "f", &["+ 1"], &["/"]),
+            // calls and va_arg stay when their result is unused
+            ("int printf(char *fmt, ...); int f(int n) { int r = printf(\"%d\\n\", n); return 0; }", "f", &["LCall printf"], &["_ret"]),
+            ("int v(int n, ...) { va_list ap; va_start(ap, n); int x = va_arg(ap, int); return n; } int f(int n) { return v(n, 1); }",
+                "v", &["va_arg"], &[]),
+        ];
+
+        for (input, func, present, absent) in cases {
+            let (code_gen, instructions) = optimize_flat(input, |code_gen| {
+                create_cfgs(&mut code_gen.frames, &code_gen.instructions).iter()
+                    .flat_map(|cfg| eliminate_dead_code(code_gen.instructions[cfg.range.0..=cfg.range.1].to_vec()))
+                    .collect()
+            });
+
+            let start = instructions.iter().position(|i| *i == Instruction::Label(func.to_string())).unwrap();
+            let end = start + instructions[start..].iter().position(|i| *i == Instruction::EndFunc).unwrap();
+            let text: Vec<String> = instructions[start..=end].iter().map(|i| i.print()).collect();
+            for p in present {
+                assert!(text.iter().any(|l| l.contains(p)), "'{}' missing in: {}\n{}", p, input, text.join("\n"));
+            }
+            for a in absent {
+                assert!(!text.iter().any(|l| l.contains(a)), "'{}' left in: {}\n{}", a, input, text.join("\n"));
+            }
+
+            for n in [0, 1, 4] {
+                let args = [Value::Int(n)];
+                let expected = new_interpreter(&code_gen.instructions, &code_gen.frames, &code_gen.strings).run("f", &args);
+                let got = new_interpreter(&instructions, &code_gen.frames, &code_gen.strings).run("f", &args);
+                // a removed division no longer traps
+                assert!(matches!((&got, &expected), (Ok(a), Ok(b)) if a == b) || expected.is_err(), "failed at: {} with {}", input, n);
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use indexmap::IndexSet;
+
+use crate::optimizer::cfg::ControlFlowGraph;
+
+// dominators of a CFG given by its successor lists, block 0 is the entry.
+// idoms come from the iterative algorithm of Cooper, Harvey and Kennedy
+#[derive(Debug)]
+pub struct DominatorTree {
+    // None for the entry and for unreachable blocks
+    pub idom: Vec<Option<usize>>,
+    pub children: Vec<Vec<usize>>,
+    pub frontiers: Vec<IndexSet<usize>>,
+}
+
+pub fn new_dominator_tree(successors: &[Vec<usize>]) -> DominatorTree {
+    let n = successors.len();
+    let order = reverse_postorder(successors);
+    let mut position = vec![usize::MAX; n];
+    for (i, b) in order.iter().enumerate() {
+        position[*b] = i;
+    }
+    let preds = predecessors(successors);
+
+    // usize::MAX marks blocks not processed yet, the entry is its own idom
+    let mut idom = vec![usize::MAX; n];
+    if n > 0 {
+        idom[0] = 0;
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &b in order.iter().skip(1) {
+            let mut new_idom = usize::MAX;
+            for &p in &preds[b] {
+                if idom[p] == usize::MAX {
+                    continue;
+                }
+                new_idom = match new_idom {
+                    usize::MAX => p,
+                    other => intersect(&idom, &position, p, other),
+                };
+            }
+            if idom[b] != new_idom {
+                idom[b] = new_idom;
+                changed = true;
+            }
+        }
+    }
+
+    let mut children = vec![Vec::new(); n];
+    for b in 1..n {
+        if idom[b] != usize::MAX {
+            children[idom[b]].push(b);
+        }
+    }
+
+    // walk up from each predecessor of a join until its idom
+    let mut frontiers = vec![IndexSet::new(); n];
+    for &b in &order {
+        let reachable: Vec<usize> = preds[b].iter().copied().filter(|p| idom[*p] != usize::MAX).collect();
+        if reachable.len() < 2 {
+            continue;
+        }
+        for p in reachable {
+            let mut runner = p;
+            while runner != idom[b] {
+                frontiers[runner].insert(b);
+                runner = idom[runner];
+            }
+        }
+    }
+
+    let idom = idom.iter().enumerate()
+        .map(|(b, d)| if b == 0 || *d == usize::MAX { None } else { Some(*d) })
+        .collect();
+    DominatorTree { idom, children, frontiers }
+}
+
+impl DominatorTree {
+    pub fn is_reachable(&self, b: usize) -> bool {
+        b == 0 || self.idom[b].is_some()
+    }
+
+    // whether every path from the entry to `b` goes through `a`
+    pub fn dominates(&self, a: usize, b: usize) -> bool {
+        let mut runner = Some(b);
+        while let Some(r) = runner {
+            if r == a {
+                return true;
+            }
+            runner = self.idom[r];
+        }
+        false
+    }
+}
+
+impl ControlFlowGraph {
+    // the edges of every block, without the fallthrough past the last one
+    pub fn successors(&self) -> Vec<Vec<usize>> {
+        let n = self.blocks.len();
+        self.blocks.iter()
+            .map(|b| b.edges.iter().copied().filter(|e| *e < n).collect())
+            .collect()
+    }
+}
+
+pub fn predecessors(successors: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut preds = vec![Vec::new(); successors.len()];
+    for (b, succs) in successors.iter().enumerate() {
+        for s in succs {
+            if !preds[*s].contains(&b) {
+                preds[*s].push(b);
+            }
+        }
+    }
+    preds
+}
+
+fn reverse_postorder(successors: &[Vec<usize>]) -> Vec<usize> {
+    let mut order = Vec::new();
+    if successors.is_empty() {
+        return order;
+    }
+    let mut visited = vec![false; successors.len()];
+    // (block, index of the next successor to visit)
+    let mut stack = vec![(0, 0)];
+    visited[0] = true;
+    while let Some((b, i)) = stack.pop() {
+        match successors[b].get(i) {
+            Some(&s) => {
+                stack.push((b, i + 1));
+                if !visited[s] {
+                    visited[s] = true;
+                    stack.push((s, 0));
+                }
+            },
+            None => order.push(b),
+        }
+    }
+    order.reverse();
+    order
+}
+
+fn intersect(idom: &[usize], position: &[usize], mut a: usize, mut b: usize) -> usize {
+    while a != b {
+        while position[a] > position[b] {
+            a = idom[a];
+        }
+        while position[b] > position[a] {
+            b = idom[b];
+        }
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dominators() {
+        // 0 -> 1, 1 -> 2 | 3, 2 -> 4, 3 -> 4, 4 -> 1 | 5, 6 unreachable -> 5
+        let succs = vec![vec![1], vec![2, 3], vec![4], vec![4], vec![1, 5], vec![], vec![5]];
+        let tree = new_dominator_tree(&succs);
+
+        assert_eq!(tree.idom, vec![None, Some(0), Some(1), Some(1), Some(1), Some(4), None]);
+        assert_eq!(tree.children[1], vec![2, 3, 4]);
+        assert_eq!(tree.frontiers[2], IndexSet::from([4]));
+        assert_eq!(tree.frontiers[3], IndexSet::from([4]));
+        assert_eq!(tree.frontiers[4], IndexSet::from([1]));
+        assert_eq!(tree.frontiers[1], IndexSet::from([1]));
+        assert!(tree.frontiers[0].is_empty());
+        assert!(tree.is_reachable(5) && !tree.is_reachable(6));
+        assert!(tree.dominates(1, 5) && tree.dominates(4, 4) && !tree.dominates(2, 4) && !tree.dominates(0, 6));
+    }
+}
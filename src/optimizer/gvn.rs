@@ -0,0 +1,166 @@
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{intermediate::{instruction::Instruction, irgen::Operand}, optimizer::{dominance::DominatorTree, ssa::{SsaBlock, SsaFunction, variable_name}}};
+
+// value numbering on a function in SSA form. every name gets a value, the
+// name it is known to be equal to, and every pure computation a key, the
+// instruction with its operands replaced by their values. a computation
+// whose key was seen before becomes a copy of the first result, copy
+// propagation and dead code elimination clean that up.
+// locally the keys seen are those of the block so far. globally they are
+// those of the blocks dominating it too, walking the dominator tree and
+// dropping the keys of a block when leaving it, so the first result
+// always dominates the copy
+
+pub fn number_values(function: &mut SsaFunction, global: bool) {
+    let mut numbering = Numbering { values: IndexMap::new(), keys: IndexMap::new(), excluded: function.va_lists() };
+    if global {
+        let tree = function.dominator_tree();
+        numbering.visit(function, 0, &tree);
+    } else {
+        for block in function.blocks.iter_mut() {
+            numbering.number_block(block);
+            numbering.keys.clear();
+        }
+    }
+}
+
+struct Numbering {
+    // name -> the operand it equals
+    values: IndexMap<String, Operand>,
+    // key -> the first result computing it
+    keys: IndexMap<String, Operand>,
+    excluded: IndexSet<String>,
+}
+
+impl Numbering {
+    fn visit(&mut self, function: &mut SsaFunction, b: usize, tree: &DominatorTree) {
+        let added = self.number_block(&mut function.blocks[b]);
+        for &c in &tree.children[b] {
+            self.visit(function, c, tree);
+        }
+        for key in added {
+            self.keys.shift_remove(&key);
+        }
+    }
+
+    // returns the keys the block added
+    fn number_block(&mut self, block: &mut SsaBlock) -> Vec<String> {
+        // a phi whose arguments all have one value has that value. the
+        // arguments of back edges are not numbered yet and only match
+        // themselves
+        for phi in &block.phis {
+            let Some(name) = variable_name(&phi.dest) else { continue };
+            let args: Vec<Operand> = phi.args.iter().map(|arg| self.value(arg)).collect();
+            if let Some(first) = args.first()
+                && args.iter().all(|arg| arg == first)
+                && first.ty() == phi.dest.ty() {
+                self.values.insert(name, first.clone());
+            }
+        }
+
+        let mut added = Vec::new();
+        for inst in block.instructions.iter_mut() {
+            let Some(dest) = inst.def() else { continue };
+            let Some(name) = variable_name(&dest) else { continue };
+            if self.excluded.contains(&name) || inst.uses().iter().any(|op| !self.is_numbered(op)) {
+                continue;
+            }
+            if let Instruction::Assign { arg1, .. } = inst {
+                if arg1.ty() == dest.ty() {
+                    self.values.insert(name, self.value(arg1));
+                }
+                continue;
+            }
+            let Some(key) = self.key(inst) else { continue };
+            match self.keys.get(&key) {
+                Some(first) => {
+                    self.values.insert(name, first.clone());
+                    *inst = Instruction::Assign { dest, arg1: first.clone() };
+                },
+                None => {
+                    self.keys.insert(key.clone(), dest);
+                    added.push(key);
+                },
+            }
+        }
+        added
+    }
+
+    fn value(&self, op: &Operand) -> Operand {
+        variable_name(op).and_then(|name| self.values.get(&name)).cloned().unwrap_or_else(|| op.clone())
+    }
+
+    // `_ret` changes with every call and va_lists with every va_arg
+    fn is_numbered(&self, op: &Operand) -> bool {
+        match op {
+            Operand::Var(..) | Operand::Temp(..) => variable_name(op).is_some_and(|name| !self.excluded.contains(&name)),
+            _ => true,
+        }
+    }
+
+    // the key of a pure computation. the operands of a commutative one are
+    // put in order, a constant always goes second as the type of a binary
+    // operation is that of its first name
+    fn key(&self, inst: &Instruction) -> Option<String> {
+        if matches!(inst, Instruction::Assign { .. } | Instruction::VaStart { .. } | Instruction::VaArg { .. }) {
+            return None;
+        }
+        let mut key = inst.clone();
+        let dest = key.def_mut()?;
+        *dest = Operand::Temp(String::new(), dest.ty());
+        let commutative = is_commutative(&key);
+        let mut ops = key.uses_mut();
+        for op in ops.iter_mut() {
+            **op = self.value(op);
+        }
+        if let [a, b] = &mut ops[..]
+            && commutative
+            && a.ty() == b.ty()
+            && (matches!(a, Operand::Const(_)) || (!matches!(b, Operand::Const(_)) && a.print() > b.print())) {
+            std::mem::swap(*a, *b);
+        }
+        Some(key.print())
+    }
+}
+
+fn is_commutative(inst: &Instruction) -> bool {
+    matches!(inst,
+        Instruction::Add { .. } | Instruction::Mul { .. } |
+        Instruction::BitwiseAnd { .. } | Instruction::BitwiseOr { .. } | Instruction::BitwiseXor { .. } |
+        Instruction::LogicalAnd { .. } | Instruction::LogicalOr { .. } |
+        Instruction::Equal { .. } | Instruction::NotEqual { .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::optimizer::tests::{assert_equivalent, int_calls, optimize_ssa};
+
+    use super::*;
+
+    #[test]
+    fn value_numbering() {
+        // (program, global, operator, times it is left in f)
+        let cases = [
+            ("int f(int n) { int a = n * 3 + 1; int b = 3 * n + 2; return a + b; }", false, "*", 1),
+            ("int f(int n) { int a = n - 1; int b = 0; if (n > 2) { b = n - 1; } else { b = (n - 1) * 2; } return a + b; }", true, "-", 1),
+            // only the blocks dominating a computation count
+            ("int f(int n) { int b = 0; if (n > 2) { b = n - 1; } else { b = 5; } return b + (n - 1); }", true, "-", 2),
+            ("int f(int n) { int a = n - 1; int b = 0; if (n > 2) { b = n - 1; } return a + b; }", false, "-", 2),
+            ("int f(int n) { int s = 0; int i = 0; while (i < n) { s = s + i * i; s = s + i * i; i = i + 1; } return s; }", true, "*", 1),
+            ("int f(int n) { int q = n % 4; if (n > 1) { q = q * (n % 4); } return q + n % 4; }", true, "%", 1),
+            ("int g(int x) { return x + 1; } int f(int n) { int a = g(n) + 1; int b = g(n) + 1; return a * b; }", true, "+ 1", 2),
+        ];
+
+        for (input, global, operator, left) in cases {
+            let (code_gen, instructions) = optimize_ssa(input, |function| {
+                number_values(function, global);
+                if function.name == "f" {
+                    let count = function.blocks.iter().flat_map(|b| &b.instructions).filter(|i| i.print().contains(operator)).count();
+                    assert_eq!(count, left, "failed at: {}", input);
+                }
+            });
+            assert_equivalent(&code_gen, &instructions, input, &int_calls(&[0, 1, 3, 7]));
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{intermediate::{frame::Frame, instruction::Instruction, irgen::Operand}, optimizer::ssa::renamed, parser::token::Type};
+
+// function inlining on the flat TAC of the whole program. a call
+//   PushParam a ... LCall g, t = _ret, PopParams n
+// becomes a copy of the body of g: its parameters are assigned the pushed
+// arguments, a Return assigns the result and jumps past the copy, or stays
+// a Return when the caller returns the result right away. every
+// copy gets names of its own, temporaries and labels are numbered on from
+// the largest in the program and a variable x of g becomes `x.g_<site>`.
+// a callee is inlined when it is small, or somewhat larger when declared
+// `inline`, and never into itself or when it is variadic. functions are
+// done in program order and a callee already done brings what was inlined
+// into it along, the callee itself stays for other callers
+
+// instructions of a body, labels not counted
+const SIZE_LIMIT: usize = 12;
+const HINTED_SIZE_LIMIT: usize = 60;
+// a caller stops growing past this
+const CALLER_SIZE_LIMIT: usize = 1000;
+
+pub fn inline_functions(instructions: &[Instruction], frames: &IndexMap<String, Frame>) -> Vec<Instruction> {
+    let mut bodies: IndexMap<String, Vec<Instruction>> = IndexMap::new();
+    let mut current: Option<String> = None;
+    for inst in instructions {
+        match (inst, &current) {
+            (Instruction::Label(name), None) => {
+                current = Some(name.clone());
+                bodies.insert(name.clone(), vec![inst.clone()]);
+            },
+            (_, Some(name)) => {
+                bodies.get_mut(name).unwrap().push(inst.clone());
+                if *inst == Instruction::EndFunc {
+                    current = None;
+                }
+            },
+            _ => (),
+        }
+    }
+
+    let mut inliner = Inliner {
+        next_temp: 1 + instructions.iter().flat_map(operands).filter_map(|op| temp_number(&op)).max().unwrap_or(0),
+        next_label: 1 + instructions.iter().filter_map(label_number).max().unwrap_or(0),
+        site: 1,
+        taken: instructions.iter().flat_map(operands).filter_map(|op| match op {
+            Operand::Var(name, _) => Some(name),
+            _ => None,
+        }).collect(),
+    };
+    for name in bodies.keys().cloned().collect::<Vec<String>>() {
+        let body = inliner.inline_calls(&name, &bodies, frames);
+        bodies.insert(name, body);
+    }
+    bodies.into_values().flatten().collect()
+}
+
+struct Inliner {
+    next_temp: usize,
+    next_label: usize,
+    site: usize,
+    // variables of the program, a copy never reuses one
+    taken: IndexSet<String>,
+}
+
+impl Inliner {
+    fn inline_calls(&mut self, caller: &str, bodies: &IndexMap<String, Vec<Instruction>>, frames: &IndexMap<String, Frame>) -> Vec<Instruction> {
+        let body = &bodies[caller];
+        let mut inlined: Vec<Instruction> = Vec::new();
+        let mut i = 0;
+        while i < body.len() {
+            if let Instruction::LCall(callee) = &body[i]
+                && callee != caller
+                && inlined.len() < CALLER_SIZE_LIMIT
+                && let (Some(frame), Some(callee_body)) = (frames.get(callee), bodies.get(callee))
+                && is_inlinable(frame, callee_body)
+                && let Some(site) = call_site(&inlined, &body[i..], frame) {
+                inlined.truncate(inlined.len() - frame.params.len());
+                let copy = self.copy_body(frame, callee_body, &site);
+                inlined.extend(copy);
+                i += site.len;
+                continue;
+            }
+            inlined.push(body[i].clone());
+            i += 1;
+        }
+        inlined
+    }
+
+    fn copy_body(&mut self, frame: &Frame, body: &[Instruction], site: &CallSite) -> Vec<Instruction> {
+        let mut names: IndexMap<String, String> = IndexMap::new();
+        let mut temps: IndexMap<String, String> = IndexMap::new();
+        let mut labels: IndexMap<String, String> = IndexMap::new();
+        let variables: IndexSet<String> = frame.params.iter().map(|p| p.name.clone())
+            .chain(body.iter().flat_map(operands).filter_map(|op| match op {
+                Operand::Var(name, _) => Some(name),
+                _ => None,
+            }))
+            .collect();
+        while variables.iter().any(|v| self.taken.contains(&format!("{}.{}_{}", v, frame.name, self.site))) {
+            self.site += 1;
+        }
+        for v in variables {
+            let name = format!("{}.{}_{}", v, frame.name, self.site);
+            self.taken.insert(name.clone());
+            names.insert(v, name);
+        }
+        self.site += 1;
+
+        let end = self.new_label();
+        let mut copy = Vec::new();
+        for (param, arg) in frame.params.iter().zip(&site.args) {
+            copy.push(Instruction::Assign { dest: Operand::Var(names[&param.name].clone(), param.stype), arg1: arg.clone() });
+        }
+        let mut rename = |op: &mut Operand| {
+            *op = match &*op {
+                Operand::Var(name, _) => renamed(op, names[name].clone()),
+                Operand::Temp(name, _) if name != "_ret" => {
+                    let name = temps.entry(name.clone()).or_insert_with(|| {
+                        self.next_temp += 1;
+                        format!("t{}", self.next_temp - 1)
+                    });
+                    renamed(op, name.clone())
+                },
+                _ => op.clone(),
+            };
+        };
+        // without the label, BeginFunc and EndFunc
+        let mut copied = Vec::new();
+        for inst in &body[2..body.len() - 1] {
+            let mut inst = inst.clone();
+            inst.uses_mut().into_iter().for_each(&mut rename);
+            inst.def_mut().into_iter().for_each(&mut rename);
+            copied.push(inst);
+        }
+        for mut inst in copied {
+            if let Instruction::Label(label) | Instruction::Goto(label) | Instruction::IfZero { label, .. } = &mut inst {
+                *label = labels.entry(label.clone()).or_insert_with(|| self.new_label()).clone();
+            }
+            match inst {
+                // the result of a tail call is returned as it is, so are the
+                // tail calls of the callee
+                Instruction::Return { dest } if site.tail => copy.push(Instruction::Return { dest }),
+                Instruction::Return { dest } => {
+                    if let Some(result) = &site.result && dest != Operand::None {
+                        copy.push(Instruction::Assign { dest: result.clone(), arg1: dest });
+                    }
+                    copy.push(Instruction::Goto(end.clone()));
+                },
+                _ => copy.push(inst),
+            }
+        }
+        copy.push(Instruction::Label(end));
+        copy
+    }
+
+    fn new_label(&mut self) -> String {
+        self.next_label += 1;
+        format!("L{}", self.next_label - 1)
+    }
+}
+
+// the pushed arguments, where the result goes and the number of
+// instructions from LCall to PopParams, or to the Return of the result
+struct CallSite {
+    args: Vec<Operand>,
+    result: Option<Operand>,
+    len: usize,
+    tail: bool,
+}
+
+// `before` ends with the instructions of the caller so far, `after` starts
+// at the LCall
+fn call_site(before: &[Instruction], after: &[Instruction], frame: &Frame) -> Option<CallSite> {
+    let n = frame.params.len();
+    let pushes = before.get(before.len().checked_sub(n)?..)?;
+    let mut args = Vec::new();
+    for (push, param) in pushes.iter().zip(&frame.params) {
+        match push {
+            Instruction::PushParam(arg, ty) if *ty == param.stype && arg.ty() == param.stype => args.push(arg.clone()),
+            _ => return None,
+        }
+    }
+    let (result, len) = match after.get(1) {
+        Some(Instruction::Assign { dest, arg1: Operand::Temp(name, _) }) if name == "_ret" => {
+            // a void result is never read
+            (if frame.ret_type == Type::Void { None } else { Some(dest.clone()) }, 3)
+        },
+        _ => (None, 2),
+    };
+    if !matches!(after.get(len - 1), Some(Instruction::PopParams(_))) {
+        return None;
+    }
+    match (after.get(len), &result) {
+        (Some(Instruction::Return { dest }), Some(result)) if dest == result => Some(CallSite { args, result: None, len: len + 1, tail: true }),
+        _ => Some(CallSite { args, result, len, tail: false }),
+    }
+}
+
+fn is_inlinable(frame: &Frame, body: &[Instruction]) -> bool {
+    let size = body.iter().filter(|inst| !matches!(inst, Instruction::Label(_))).count() - 2;
+    let limit = if frame.inline { HINTED_SIZE_LIMIT } else { SIZE_LIMIT };
+    !frame.variadic
+        && size <= limit
+        && body.iter().all(|inst| match inst {
+            Instruction::LCall(callee) => *callee != frame.name,
+            Instruction::Return { dest } => *dest == Operand::None || dest.ty() == frame.ret_type,
+            _ => true,
+        })
+}
+
+fn operands(inst: &Instruction) -> Vec<Operand> {
+    inst.uses().into_iter().chain(inst.def()).collect()
+}
+
+// `t7` and its SSA versions `t7.2` -> 7
+pub(crate) fn temp_number(op: &Operand) -> Option<usize> {
+    match op {
+        Operand::Temp(name, _) => name.strip_prefix('t')?.split('.').next()?.parse().ok(),
+        _ => None,
+    }
+}
+
+fn label_number(inst: &Instruction) -> Option<usize> {
+    match inst {
+        Instruction::Label(label) => label.strip_prefix('L')?.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::optimizer::tests::{assert_equivalent, int_calls, optimize_flat};
+
+    use super::*;
+
+    #[test]
+    fn function_inlining() {
+        // (program, calls left in f)
+        let cases = [
+            ("int g(int x) { return x * 2 + 1; } int f(int n) { return g(n) + g(n + 1); }", 0),
+            // two returns, a loop and a parameter assigned to
+            ("int g(int x) { if (x > 2) { return x; } while (x < 10) { x = x + 3; } return x; } int f(int n) { return g(n) - g(n - 5); }", 0),
+            ("void g(int x) { int y = x; } int f(int n) { g(n); return n; }", 0),
+            // the call of h comes along with g, then is inlined too
+            ("int h(double d) { return 3; } int g(int x) { return h(1.5) + x; } int f(int n) { return g(n) * 2; }", 0),
+            ("int g(int x) { if (x > 0) { return g(x - 1) + 1; } return 0; } int f(int n) { return g(n); }", 1),
+            ("int g(int x) { int s = 0; for (int i = 0; i < x; i = i + 1) { s = s + i * x; if (s > 100) { s = s - 100; } } return s; } \
+                int f(int n) { return g(n); }", 1),
+            ("inline int g(int x) { int s = 0; for (int i = 0; i < x; i = i + 1) { s = s + i * x; if (s > 100) { s = s - 100; } } return s; } \
+                int f(int n) { return g(n) + g(2); }", 0),
+            ("int printf(char *fmt, ...); int g(long a, char c) { printf(\"%ld %c\\n\", a, c); return 1; } int f(int n) { return g(n, 'a') + n; }", 1),
+        ];
+
+        for (input, calls) in cases {
+            let (code_gen, instructions) = optimize_flat(input, |code_gen| inline_functions(&code_gen.instructions, &code_gen.frames));
+
+            let start = instructions.iter().position(|i| *i == Instruction::Label(String::from("f"))).unwrap();
+            let left = instructions[start..].iter().filter(|i| matches!(i, Instruction::LCall(_))).count();
+            assert_eq!(left, calls, "failed at: {}", input);
+
+            assert_equivalent(&code_gen, &instructions, input, &int_calls(&[0, 1, 4, 9]));
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use indexmap::IndexSet;
+
+use crate::{intermediate::{instruction::Instruction, irgen::Operand}, optimizer::{loops::{Loop, find_loops}, ssa::{Phi, SsaBlock, SsaFunction, renamed, variable_name}}};
+
+// loop-invariant code motion on a function in SSA form. every loop first
+// gets a preheader, a block that is the only way into its header from
+// outside the loop. then a computation in the loop whose operands are all
+// defined outside of it moves to the end of the preheader, inner loops
+// first so that what leaves an inner loop can leave the outer one too.
+// a name has one definition in SSA, so the moved definition still
+// dominates every use. divisions stay, the loop may never run or guard
+// them with a condition, and so does va_arg
+
+pub fn hoist_invariants(function: &mut SsaFunction) {
+    insert_preheaders(function);
+    let mut loops = find_loops(&function.successors(), &function.dominator_tree()).loops;
+    loops.sort_by_key(|lp| std::cmp::Reverse(lp.depth));
+    let excluded = function.va_lists();
+    for lp in &loops {
+        let pre = preheader(function, lp).unwrap();
+        hoist(function, lp, pre, &excluded);
+    }
+}
+
+pub fn insert_preheaders(function: &mut SsaFunction) {
+    loop {
+        let forest = find_loops(&function.successors(), &function.dominator_tree());
+        match forest.loops.iter().find(|lp| preheader(function, lp).is_none()) {
+            Some(lp) => insert_preheader(function, lp),
+            None => break,
+        }
+    }
+}
+
+// the one block outside the loop that enters it, when it has no other
+// successor
+pub fn preheader(function: &SsaFunction, lp: &Loop) -> Option<usize> {
+    let entries: Vec<usize> = function.blocks[lp.header].preds.iter().copied().filter(|p| !lp.body.contains(p)).collect();
+    match entries[..] {
+        [p] if function.blocks[p].succs == [lp.header] => Some(p),
+        _ => None,
+    }
+}
+
+// a new block between the entries of the loop and its header. a phi of the
+// header takes the value coming from outside through a phi of the new block,
+// or directly when all entries agree on it
+fn insert_preheader(function: &mut SsaFunction, lp: &Loop) {
+    let h = lp.header;
+    let m = function.blocks.len();
+    let preds = function.blocks[h].preds.clone();
+    let (entries, inside): (Vec<usize>, Vec<usize>) = (0..preds.len()).partition(|j| !lp.body.contains(&preds[*j]));
+
+    let mut phis = Vec::new();
+    for k in 0..function.blocks[h].phis.len() {
+        let phi = function.blocks[h].phis[k].clone();
+        let args: Vec<Operand> = entries.iter().map(|j| phi.args[*j].clone()).collect();
+        let arg = if args.iter().all(|a| *a == args[0]) {
+            args[0].clone()
+        } else {
+            let dest = renamed(&phi.dest, function.fresh_name(&phi.dest.print()));
+            phis.push(Phi { dest: dest.clone(), args });
+            dest
+        };
+        function.blocks[h].phis[k].args = std::iter::once(arg).chain(inside.iter().map(|j| phi.args[*j].clone())).collect();
+    }
+
+    let outside: Vec<usize> = entries.iter().map(|j| preds[*j]).collect();
+    for &p in &outside {
+        for s in function.blocks[p].succs.iter_mut() {
+            if *s == h {
+                *s = m;
+            }
+        }
+    }
+    function.blocks[h].preds = std::iter::once(m).chain(inside.iter().map(|j| preds[*j])).collect();
+    function.blocks.push(SsaBlock { label: None, phis, instructions: Vec::new(), preds: outside, succs: vec![h] });
+}
+
+fn hoist(function: &mut SsaFunction, lp: &Loop, pre: usize, excluded: &IndexSet<String>) {
+    let mut defined = IndexSet::new();
+    for &b in &lp.body {
+        let block = &function.blocks[b];
+        defined.extend(block.phis.iter().filter_map(|phi| variable_name(&phi.dest)));
+        defined.extend(block.instructions.iter().filter_map(|inst| inst.def().and_then(|dest| variable_name(&dest))));
+    }
+    // `_ret` is written by every call
+    let invariant = |op: &Operand, defined: &IndexSet<String>| match op {
+        Operand::Var(..) | Operand::Temp(..) => variable_name(op).is_some_and(|name| !defined.contains(&name) && !excluded.contains(&name)),
+        _ => true,
+    };
+
+    // in the order they are found, every operand of one is hoisted first
+    let mut hoisted = Vec::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &b in &lp.body {
+            let instructions = std::mem::take(&mut function.blocks[b].instructions);
+            for inst in instructions {
+                let name = inst.def().and_then(|dest| variable_name(&dest));
+                match name {
+                    Some(name) if is_movable(&inst) && !excluded.contains(&name) && inst.uses().iter().all(|op| invariant(op, &defined)) => {
+                        defined.shift_remove(&name);
+                        hoisted.push(inst);
+                        changed = true;
+                    },
+                    _ => function.blocks[b].instructions.push(inst),
+                }
+            }
+        }
+    }
+
+    append_to_preheader(&mut function.blocks[pre], hoisted);
+}
+
+// before the jump into the header, if there is one
+pub fn append_to_preheader(block: &mut SsaBlock, instructions: Vec<Instruction>) {
+    let at = match block.instructions.last() {
+        Some(Instruction::Goto(_)) => block.instructions.len() - 1,
+        _ => block.instructions.len(),
+    };
+    block.instructions.splice(at..at, instructions);
+}
+
+fn is_movable(inst: &Instruction) -> bool {
+    !matches!(inst, Instruction::Div { .. } | Instruction::Mod { .. } | Instruction::VaStart { .. } | Instruction::VaArg { .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::optimizer::tests::{assert_equivalent, int_calls, optimize_ssa};
+
+    use super::*;
+
+    #[test]
+    fn loop_invariant_code_motion() {
+        // (program, operators left in a loop of f, operators no longer in one)
+        let cases: [(&str, &[&str], &[&str]); 5] = [
+            ("int f(int n) { int s = 0; int i = 0; while (i < n) { s = s + n * 4; i = i + 1; } return s; }", &["+"], &["*"]),
+            // the product reads the sum, both leave
+            ("int f(int n) { int s = 0; for (int i = 0; i < n; i = i + 1) { int k = n + 3; s = s + k * k; } return s; }", &[], &["* "]),
+            // out of both loops
+            ("int f(int n) { int s = 0; for (int i = 0; i < n; i = i + 1) { for (int j = 0; j < n; j = j + 1) { s = s + (n << 2); } } return s; }",
+                &[], &["<<"]),
+            // the guarded division stays, its divisor leaves
+            ("int f(int n) { int s = 0; int i = 0; while (i < n) { if (n > 2) { s = s + 100 / (n + 3); } i = i + 1; } return s; }", &["/"], &["+ 3"]),
+            ("int g(int x) { return x * 2; } int f(int n) { int s = 0; int i = 0; while (i < n) { s = s + g(n) * 3; i = i + 1; } return s; }",
+                &["* 3", "LCall g"], &[]),
+        ];
+
+        for (input, inside, outside) in cases {
+            let (code_gen, instructions) = optimize_ssa(input, |function| {
+                hoist_invariants(function);
+                if function.name != "f" {
+                    return;
+                }
+                let forest = find_loops(&function.successors(), &function.dominator_tree());
+                assert!(!forest.loops.is_empty(), "no loop in: {}", input);
+                for lp in &forest.loops {
+                    assert!(preheader(function, lp).is_some(), "no preheader in: {}", input);
+                }
+                let in_loops: Vec<String> = function.blocks.iter().enumerate()
+                    .filter(|(b, _)| forest.depth[*b] > 0)
+                    .flat_map(|(_, block)| block.instructions.iter().map(|i| i.print()))
+                    .collect();
+                for op in inside {
+                    assert!(in_loops.iter().any(|l| l.contains(op)), "'{}' hoisted in: {}", op, input);
+                }
+                for op in outside {
+                    assert!(!in_loops.iter().any(|l| l.contains(op)), "'{}' left in: {}", op, input);
+                }
+            });
+            assert_equivalent(&code_gen, &instructions, input, &int_calls(&[0, 1, 3, 4]));
+        }
+    }
+}
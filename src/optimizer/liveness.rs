@@ -7,7 +7,6 @@ pub struct LivenessAnalyzer {
     pub blocks: Vec<Block>,
     pub interference_graph: InterferenceGraph,
     pub inst_liveness: Vec<InstructionLiveness>,
-    pub callstarts: Vec<Vec<Operand>>,
 }
 
 pub fn new_liveness_analyzer(instructions: Vec<Instruction>, blocks: Vec<Block>) -> LivenessAnalyzer {
@@ -17,8 +16,7 @@ pub fn new_liveness_analyzer(instructions: Vec<Instruction>, blocks: Vec<Block>)
             live_out: IndexSet::new()
         }; instructions.len()], 
         instructions, blocks, 
-        interference_graph: InterferenceGraph { variables: IndexMap::new(), edges: IndexMap::new() },
-        callstarts: Vec::new(),
+        interference_graph: InterferenceGraph { variables: IndexMap::new(), edges: IndexMap::new(), spill_costs: IndexMap::new() },
     }
         
 }
@@ -26,6 +24,16 @@ pub fn new_liveness_analyzer(instructions: Vec<Instruction>, blocks: Vec<Block>)
 pub struct InterferenceGraph {
     pub variables: IndexMap<String, Variable>,
     pub edges: IndexMap<String, IndexSet<String>>,
+    // what spilling a variable would cost, in loads and stores
+    pub spill_costs: IndexMap<String, usize>,
+}
+
+// the fixed registers an instruction overwrites. besides the values live
+// across it, `late_uses` are operands still read after the registers are
+// written, so they cannot be held in them either
+pub struct Clobbers {
+    pub registers: &'static [&'static str],
+    pub late_uses: Vec<Operand>,
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -33,6 +41,8 @@ pub struct Variable {
     pub name: String,
     pub register_id: usize, 
     pub spilled: bool,
+    // a machine register, named after it, that the allocator cannot move
+    pub precolored: bool,
 }
 
 /* impl LiveRange {
@@ -56,8 +66,14 @@ impl LivenessAnalyzer {
         let mut changed = true;
         while changed {
             changed = false;
+            // a live in that grows has to be seen by the predecessors placed
+            // after its block, which were already visited in this pass
             for i in (0..self.blocks.len()).rev() {
-                self.blocks[i].live_in = self.block_live_in(i);
+                let live_in = self.block_live_in(i);
+                if live_in != self.blocks[i].live_in {
+                    changed = true;
+                }
+                self.blocks[i].live_in = live_in;
                 if self.block_live_out(i) {
                     changed = true;
                 }
@@ -101,29 +117,33 @@ impl LivenessAnalyzer {
             assert!(a <= b, "invalid range");
             assert!(b < self.instructions.len(), "b out of bounds");
             for ii in a..=b {
-                
-                if let Some(op) = self.instructions[ii].def() {
-                    
-                    def_set.insert(op.print());
-                }
+                // read before written, `x = x + 1` uses the x coming in
                 let operands = self.instructions[ii].uses();
                 use_set.extend(operands.iter().filter_map(|x| {
                     if def_set.contains(&x.print()) { return None }
-                    if let Operand::Var(s) = x {
+                    if let Operand::Var(s, _) = x {
                         Some(s.clone())
-                    } else if let Operand::Temp(s) = x {
+                    } else if let Operand::Temp(s, _) = x {
                         Some(s.clone())   
                     }else {
                         None
                     }
                 }));
-                
+
+                if let Some(op) = self.instructions[ii].def() {
+                    def_set.insert(op.print());
+                }
             } 
             self.blocks[i].def_set = def_set;
             self.blocks[i].use_set = use_set;
         }
     }
 
+    // the names live right after instruction `i` of the function
+    pub fn live_out(&self, i: usize) -> &IndexSet<String> {
+        &self.inst_liveness[i].live_out
+    }
+
     pub fn gen_inst_live_out(&mut self) {
         for i in 0..self.blocks.len() {
             let (a, b) = self.blocks[i].get_range();
@@ -170,26 +190,32 @@ impl LivenessAnalyzer {
     }
 
  
-    pub fn create_interference_graph(&mut self) {
+    // `clobbers` names the registers an instruction overwrites, every value
+    // live across it interferes with them
+    pub fn create_interference_graph(&mut self, clobbers: fn(&Instruction) -> Clobbers) {
         for i in 0..self.instructions.len() {
             let uses = self.instructions[i].uses();
             for op in uses.iter() {
-                if let Operand::Var(s) = op {
+                if let Operand::Var(s, _) = op {
                     self.ensure_variable_exists(&s);
-                } else if let Operand::Temp(s) = op {
+                } else if let Operand::Temp(s, _) = op {
                     self.ensure_variable_exists(&s);
                 }
             }
             let out = self.inst_liveness[i].live_out.clone();
 
-            // handle callstart
-            if let Instruction::CallStart(_) = &self.instructions[i] {
-                let mut ops = Vec::new();
-                for s in &out {
-                    if s == "_ret" { continue; }
-                    ops.push(Operand::Var(s.to_string()));
+            let def = self.instructions[i].def().map(|op| op.print());
+            let clobbers = clobbers(&self.instructions[i]);
+            let late_uses: Vec<String> = clobbers.late_uses.iter()
+                .filter(|op| matches!(op, Operand::Var(..) | Operand::Temp(..)))
+                .map(|op| op.print())
+                .collect();
+            for reg in clobbers.registers {
+                for out_var in out.iter().chain(late_uses.iter()) {
+                    if out_var == "_ret" || Some(out_var) == def.as_ref() { continue; }
+                    self.ensure_register_exists(reg);
+                    self.add_edge(&reg.to_string(), out_var);
                 }
-                self.callstarts.push(ops);
             }
 
             if let Some(op) = self.instructions[i].def() {
@@ -201,12 +227,28 @@ impl LivenessAnalyzer {
             } 
         }
     }
+    // every use and def of a variable counts 10 times for each loop it is
+    // in, `loop_depths` has the depth of every block
+    pub fn weigh_spill_costs(&mut self, loop_depths: &[usize]) {
+        for (block, depth) in self.blocks.iter().zip(loop_depths) {
+            let weight = 10usize.pow((*depth).min(6) as u32);
+            let (a, b) = block.get_range();
+            for inst in &self.instructions[a..=b] {
+                for op in inst.uses().iter().chain(inst.def().iter()) {
+                    if let Operand::Var(name, _) | Operand::Temp(name, _) = op {
+                        *self.interference_graph.spill_costs.entry(name.clone()).or_default() += weight;
+                    }
+                }
+            }
+        }
+    }
+
     fn add_edge(&mut self, src: &String, dest: &String) {
         if src == dest { return; }
         self.interference_graph.variables.entry(src.to_string()).or_insert_with(|| 
-            Variable { name:src.to_string(), register_id: 0, spilled: false });
+            Variable { name:src.to_string(), register_id: 0, spilled: false, precolored: false });
         self.interference_graph.variables.entry(dest.to_string()).or_insert_with(|| 
-            Variable { name:dest.to_string(), register_id: 0, spilled: false });
+            Variable { name:dest.to_string(), register_id: 0, spilled: false, precolored: false });
 
         self.interference_graph.edges.entry(src.to_string())
         .or_default().insert(dest.to_string());
@@ -214,12 +256,59 @@ impl LivenessAnalyzer {
         .or_default().insert(src.to_string());   
     }
 
+    fn ensure_register_exists(&mut self, name: &str) {
+        self.interference_graph.variables.entry(name.to_string()).or_insert_with(|| 
+            Variable { name:name.to_string(), register_id: 0, spilled: false, precolored: true });
+    }
+
     fn ensure_variable_exists(&mut self, name: &str) {
         let key = name.to_string();
         self.interference_graph.variables.entry(key.clone()).or_insert_with(|| 
-            Variable { name:name.to_string(), register_id: 0, spilled: false });
+            Variable { name:name.to_string(), register_id: 0, spilled: false, precolored: false });
         self.interference_graph.edges.entry(key).or_insert_with(IndexSet::new);
     }
 
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{optimizer::cfg::function_cfg, parser::{node::ConstValue, token::Type}};
+
+    use super::*;
+
+    #[test]
+    fn live_out_reaches_earlier_predecessors() {
+        let v = || Operand::Var("v".to_string(), Type::Int);
+        let t = || Operand::Temp("t".to_string(), Type::Int);
+        // blocks 0 -> 3 -> 2 -> 1: v is used in 1, every other block only
+        // reaches it through a successor placed before it
+        let instructions = vec![
+            Instruction::Label("f".to_string()),
+            Instruction::BeginFunc(0),
+            Instruction::Assign { dest: v(), arg1: Operand::Const(ConstValue::Int(1)) },
+            Instruction::Goto("L3".to_string()),
+            Instruction::Label("L1".to_string()),
+            Instruction::Add { dest: t(), arg1: v(), arg2: Operand::Const(ConstValue::Int(1)) },
+            Instruction::Return { dest: t() },
+            Instruction::Label("L2".to_string()),
+            Instruction::Goto("L1".to_string()),
+            Instruction::Label("L3".to_string()),
+            Instruction::Goto("L2".to_string()),
+            Instruction::EndFunc,
+        ];
+        let cfg = function_cfg(&instructions);
+        let mut analyzer = new_liveness_analyzer(instructions, cfg.blocks);
+        analyzer.gen_live_out();
+
+        let edges: Vec<Vec<usize>> = analyzer.blocks.iter().map(|b| b.edges.clone()).collect();
+        assert_eq!(edges[..4], [vec![3], vec![2], vec![1], vec![2]]);
+        for b in [0, 3, 2] {
+            let block = &analyzer.blocks[b];
+            assert!(block.live_out.contains("v"), "failed at block {}: {:?}", b, block.live_out);
+        }
+        for (b, block) in analyzer.blocks[..4].iter().enumerate() {
+            assert_eq!(block.live_in.contains("v"), b != 0, "failed at block {}: {:?}", b, block.live_in);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use indexmap::IndexSet;
+
+use crate::optimizer::{cfg::ControlFlowGraph, dominance::{DominatorTree, new_dominator_tree, predecessors}};
+
+// natural loops of a CFG given by its successor lists. an edge into a block
+// that dominates its source is a back edge, the loop of a header is every
+// block reaching one of its back edges without going through the header.
+// loops sharing a header are one loop, and a loop is nested in the smallest
+// loop containing its header. a loop without a back edge to a dominating
+// header (irreducible control flow) is not found, C without goto has none
+
+#[derive(Debug, Clone)]
+pub struct Loop {
+    pub header: usize,
+    // the header included
+    pub body: IndexSet<usize>,
+    // the sources of the back edges
+    pub latches: Vec<usize>,
+    // blocks outside the loop the body branches to
+    pub exits: IndexSet<usize>,
+    // the innermost loop containing this one
+    pub parent: Option<usize>,
+    // 1 for an outermost loop
+    pub depth: usize,
+}
+
+#[derive(Debug)]
+pub struct LoopForest {
+    // outer loops come before the loops nested in them
+    pub loops: Vec<Loop>,
+    // for each block, the number of loops it is in
+    pub depth: Vec<usize>,
+}
+
+pub fn find_loops(successors: &[Vec<usize>], tree: &DominatorTree) -> LoopForest {
+    let preds = predecessors(successors);
+    let mut loops: Vec<Loop> = Vec::new();
+    for (b, succs) in successors.iter().enumerate() {
+        if !tree.is_reachable(b) {
+            continue;
+        }
+        for &h in succs {
+            if !tree.dominates(h, b) {
+                continue;
+            }
+            let at = match loops.iter().position(|l| l.header == h) {
+                Some(at) => at,
+                None => {
+                    loops.push(Loop { header: h, body: IndexSet::from([h]), latches: Vec::new(), exits: IndexSet::new(), parent: None, depth: 0 });
+                    loops.len() - 1
+                },
+            };
+            let lp = &mut loops[at];
+            lp.latches.push(b);
+            // walk back from the latch until the header
+            let mut stack = vec![b];
+            while let Some(x) = stack.pop() {
+                if lp.body.insert(x) {
+                    stack.extend(preds[x].iter().copied().filter(|p| tree.is_reachable(*p)));
+                }
+            }
+        }
+    }
+
+    loops.sort_by_key(|l| std::cmp::Reverse(l.body.len()));
+    for i in 0..loops.len() {
+        let (outer, rest) = loops.split_at_mut(i);
+        let lp = &mut rest[0];
+        // the last containing loop is the smallest, it comes after the others
+        lp.parent = outer.iter().rposition(|o| o.body.contains(&lp.header));
+        lp.depth = lp.parent.map_or(1, |p| outer[p].depth + 1);
+        lp.exits = lp.body.iter()
+            .flat_map(|b| successors[*b].iter().copied())
+            .filter(|s| !lp.body.contains(s))
+            .collect();
+    }
+
+    let mut depth = vec![0; successors.len()];
+    for lp in &loops {
+        for &b in &lp.body {
+            depth[b] = depth[b].max(lp.depth);
+        }
+    }
+    LoopForest { loops, depth }
+}
+
+impl ControlFlowGraph {
+    // how many loops each block is in
+    pub fn loop_depths(&self) -> Vec<usize> {
+        let successors = self.successors();
+        find_loops(&successors, &new_dominator_tree(&successors)).depth
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_loops() {
+        // 0 -> 1, 1 -> 2 | 6, 2 -> 3, 3 -> 3 | 4, 4 -> 1 | 5, 5 -> 2, 6 -> 7 | 8, 7 -> 6, 8
+        let succs = vec![vec![1], vec![2, 6], vec![3], vec![3, 4], vec![1, 5], vec![2], vec![7, 8], vec![6], vec![]];
+        let tree = new_dominator_tree(&succs);
+        let forest = find_loops(&succs, &tree);
+
+        let headers: Vec<usize> = forest.loops.iter().map(|l| l.header).collect();
+        assert_eq!(headers, vec![1, 2, 6, 3]);
+        let outer = &forest.loops[0];
+        assert_eq!(outer.body, IndexSet::from([1, 4, 3, 2, 5]));
+        assert_eq!(outer.latches, vec![4]);
+        assert_eq!(outer.exits, IndexSet::from([6]));
+        assert_eq!(outer.parent, None);
+        // 2 is entered again from 5
+        let middle = &forest.loops[1];
+        assert_eq!(middle.body, IndexSet::from([2, 5, 4, 3]));
+        assert_eq!((middle.parent, middle.depth), (Some(0), 2));
+        assert_eq!(middle.exits, IndexSet::from([1]));
+        let inner = &forest.loops[3];
+        assert_eq!(inner.body, IndexSet::from([3]));
+        assert_eq!((inner.parent, inner.depth), (Some(1), 3));
+        assert_eq!(forest.loops[2].parent, None);
+        assert_eq!(forest.depth, vec![0, 1, 2, 3, 2, 2, 1, 1, 0]);
+    }
+}
@@ -1,2 +1,70 @@
 pub mod cfg;
-pub mod liveness;
\ No newline at end of file
+pub mod constant;
+pub mod copies;
+pub mod dce;
+pub mod dominance;
+pub mod gvn;
+pub mod inline;
+pub mod licm;
+pub mod liveness;
+pub mod loops;
+pub mod passes;
+pub mod simplify;
+pub mod strength;
+pub mod tailcall;
+pub mod unroll;
+pub mod ssa;
+
+
+#[cfg(test)]
+pub mod tests {
+    use crate::{intermediate::{analyzer::new_analyzer, instruction::Instruction, interpreter::new_interpreter, irgen::{CodeGen, Value, new_codegen}, verify::verify}, optimizer::{cfg::create_cfgs, ssa::{SsaFunction, new_ssa_function}}, parser::parser::new_parser};
+
+    // the TAC of `input` as generated, and after `optimize`, which has to verify
+    pub fn optimize_flat(input: &str, optimize: impl FnOnce(&mut CodeGen) -> Vec<Instruction>) -> (CodeGen, Vec<Instruction>) {
+        let mut parser = new_parser(input).unwrap();
+        let mut program_node = parser.parse().unwrap();
+        let mut analyzer = new_analyzer();
+        analyzer.analyze(&mut program_node).unwrap();
+        let mut code_gen = new_codegen(analyzer.function_frames, analyzer.function_signatures);
+        code_gen.cgen(&program_node);
+        let instructions = optimize(&mut code_gen);
+        let got = verify(&instructions, &code_gen.signatures);
+        assert!(got.is_ok(), "failed at: {}\n{}", input, got.unwrap_err());
+        (code_gen, instructions)
+    }
+
+    // `optimize` run on the SSA form of every function
+    pub fn optimize_ssa(input: &str, mut optimize: impl FnMut(&mut SsaFunction)) -> (CodeGen, Vec<Instruction>) {
+        optimize_flat(input, |code_gen| {
+            let cfgs = create_cfgs(&mut code_gen.frames, &code_gen.instructions);
+            cfgs.iter().flat_map(|cfg| {
+                let mut function = new_ssa_function(&code_gen.instructions, cfg);
+                optimize(&mut function);
+                function.destruct()
+            }).collect()
+        })
+    }
+
+    // f returns and prints the same, or traps alike, for every argument list
+    // of `calls` before and after optimizing
+    pub fn assert_equivalent(code_gen: &CodeGen, instructions: &[Instruction], input: &str, calls: &[Vec<Value>]) {
+        for args in calls {
+            let mut before = new_interpreter(&code_gen.instructions, &code_gen.frames, &code_gen.strings);
+            let mut after = new_interpreter(instructions, &code_gen.frames, &code_gen.strings);
+            let (expected, got) = (before.run("f", args), after.run("f", args));
+            let same = match (&got, &expected) {
+                (Ok(a), Ok(b)) => a == b,
+                (Err(a), Err(b)) => a.to_string() == b.to_string(),
+                _ => false,
+            };
+            assert!(same, "failed at: {} with {:?}\n{:?} {:?}", input, args, got, expected);
+            assert_eq!(before.output, after.output, "failed at: {} with {:?}", input, args);
+        }
+    }
+
+    // f(n) for every n
+    pub fn int_calls(ns: &[i64]) -> Vec<Vec<Value>> {
+        ns.iter().map(|n| vec![Value::Int(*n)]).collect()
+    }
+}
@@ -0,0 +1,290 @@
+use std::{fmt, time::{Duration, Instant}};
+
+use indexmap::IndexMap;
+
+use crate::{intermediate::{analyzer::Signature, frame::Frame, instruction::Instruction, verify::{VerifyError, verify}}, optimizer::{cfg::{create_cfgs, function_cfg}, constant::propagate_constants, copies::propagate_copies, dce::eliminate_dead_code, gvn::number_values, inline::inline_functions, licm::hoist_invariants, simplify::simplify_cfg, strength::reduce_strength, tailcall::eliminate_tail_recursion, unroll::{Unrolling, new_unrolling, unroll_loops}, ssa::{SsaFunction, new_ssa_function}}};
+
+// runs a pipeline of passes over each function. a function stays in SSA
+// form across consecutive SSA passes and goes back to flat TAC for the
+// others and at the end. inlining works on the whole program, the function
+// passes run in the stretches between its runs. after each pass the code
+// can be verified and dumped, and the time of every pass is added up over
+// all functions
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pass {
+    Inline,
+    TailRecursion,
+    Constants,
+    Copies,
+    LocalValues,
+    GlobalValues,
+    LoopInvariants,
+    StrengthReduction,
+    Unroll,
+    SimplifyCfg,
+    DeadCode,
+}
+
+const PASSES: [Pass; 11] = [
+    Pass::Inline, Pass::TailRecursion, Pass::Constants, Pass::Copies, Pass::LocalValues, Pass::GlobalValues, Pass::LoopInvariants, Pass::StrengthReduction,
+    Pass::Unroll, Pass::SimplifyCfg, Pass::DeadCode,
+];
+
+impl Pass {
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Inline => "inline",
+            Pass::TailRecursion => "tailrec",
+            Pass::Constants => "sccp",
+            Pass::Copies => "copy-prop",
+            Pass::LocalValues => "lvn",
+            Pass::GlobalValues => "gvn",
+            Pass::LoopInvariants => "licm",
+            Pass::StrengthReduction => "strength-reduce",
+            Pass::Unroll => "unroll",
+            Pass::SimplifyCfg => "simplify-cfg",
+            Pass::DeadCode => "dce",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PassError {
+    UnknownPass(String),
+    UnknownLevel(String),
+    InvalidIr { pass: &'static str, error: VerifyError },
+}
+
+impl fmt::Display for PassError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PassError::UnknownPass(name) => write!(f, "PassError: unknown pass '{}', the passes are {}", name,
+                PASSES.map(|p| p.name()).join(", ")),
+            PassError::UnknownLevel(level) => write!(f, "PassError: unknown optimization level '-O{}'", level),
+            PassError::InvalidIr { pass, error } => write!(f, "PassError: invalid IR after '{}': {}", pass, error),
+        }
+    }
+}
+
+pub fn pass_named(name: &str) -> Result<Pass, PassError> {
+    PASSES.into_iter().find(|p| p.name() == name).ok_or_else(|| PassError::UnknownPass(name.to_string()))
+}
+
+// `sccp,dce` for --passes
+pub fn parse_passes(list: &str) -> Result<Vec<Pass>, PassError> {
+    list.split(',').filter(|s| !s.trim().is_empty()).map(|s| pass_named(s.trim())).collect()
+}
+
+// the -O presets. inlining and tail recursion go first so that the other
+// passes see through the calls they remove. value numbering and strength reduction leave copies
+// for copy-prop. -O2 works across blocks and loops and repeats the round,
+// what one round exposes the next one folds
+pub fn optimization_level(level: &str) -> Result<Vec<Pass>, PassError> {
+    match level {
+        "0" => Ok(Vec::new()),
+        "1" => Ok(vec![
+            Pass::Inline, Pass::TailRecursion, Pass::Constants, Pass::Copies, Pass::LocalValues, Pass::StrengthReduction, Pass::Copies, Pass::SimplifyCfg, Pass::DeadCode,
+        ]),
+        "2" => Ok([Pass::Inline, Pass::TailRecursion].into_iter().chain([
+            Pass::Constants, Pass::Copies, Pass::GlobalValues, Pass::LoopInvariants, Pass::StrengthReduction, Pass::Copies,
+            Pass::SimplifyCfg, Pass::DeadCode,
+        ].repeat(2)).collect()),
+        _ => Err(PassError::UnknownLevel(level.to_string())),
+    }
+}
+
+// -funroll-loops: unrolling and an sccp to fold the loops it empties, before
+// strength reduction so that the copies get reduced too
+pub fn with_unrolling(mut passes: Vec<Pass>) -> Vec<Pass> {
+    if passes.is_empty() {
+        return passes;
+    }
+    let at = passes.iter().position(|p| *p == Pass::StrengthReduction).unwrap_or(passes.len());
+    passes.splice(at..at, [Pass::Unroll, Pass::Constants]);
+    passes
+}
+
+pub struct PassManager {
+    pub passes: Vec<Pass>,
+    pub verify: bool,
+    // dumps every function after each run of this pass
+    pub print_after: Option<Pass>,
+    // the factor and size budget of the unroll pass
+    pub unrolling: Unrolling,
+    pub timings: IndexMap<&'static str, Duration>,
+}
+
+pub fn new_pass_manager(passes: Vec<Pass>) -> PassManager {
+    PassManager { passes, verify: true, print_after: None, unrolling: new_unrolling(), timings: IndexMap::new() }
+}
+
+// a function between passes
+enum Form {
+    Flat(Vec<Instruction>),
+    Ssa(SsaFunction),
+}
+
+impl Form {
+    fn into_ssa(self) -> SsaFunction {
+        match self {
+            Form::Ssa(function) => function,
+            Form::Flat(instructions) => new_ssa_function(&instructions, &function_cfg(&instructions)),
+        }
+    }
+
+    fn into_flat(self) -> Vec<Instruction> {
+        match self {
+            Form::Flat(instructions) => instructions,
+            Form::Ssa(function) => function.destruct(),
+        }
+    }
+
+    fn to_flat(&self) -> Vec<Instruction> {
+        match self {
+            Form::Flat(instructions) => instructions.clone(),
+            Form::Ssa(function) => function.clone().destruct(),
+        }
+    }
+}
+
+impl PassManager {
+    pub fn run(&mut self, instructions: &[Instruction], frames: &mut IndexMap<String, Frame>, signatures: &IndexMap<String, Signature>) -> Result<Vec<Instruction>, PassError> {
+        let passes = self.passes.clone();
+        let mut program = instructions.to_vec();
+        for group in passes.chunk_by(|a, b| *a != Pass::Inline && *b != Pass::Inline) {
+            match group {
+                [Pass::Inline] => {
+                    let now = Instant::now();
+                    program = inline_functions(&program, frames);
+                    *self.timings.entry(Pass::Inline.name()).or_default() += now.elapsed();
+                    self.check(Pass::Inline, &program, signatures)?;
+                },
+                _ => program = self.run_on_functions(group, &program, frames, signatures)?,
+            }
+        }
+        Ok(program)
+    }
+
+    fn run_on_functions(&mut self, passes: &[Pass], instructions: &[Instruction], frames: &mut IndexMap<String, Frame>, signatures: &IndexMap<String, Signature>) -> Result<Vec<Instruction>, PassError> {
+        let mut optimized = Vec::new();
+        let cfgs = create_cfgs(frames, &instructions.to_vec());
+        for (cfg, frame) in cfgs.iter().zip(frames.values()) {
+            let (start, end) = cfg.range;
+            let mut form = Form::Flat(instructions[start..=end].to_vec());
+            for &pass in passes {
+                let now = Instant::now();
+                form = run_pass(pass, form, frame, &self.unrolling);
+                *self.timings.entry(pass.name()).or_default() += now.elapsed();
+
+                if self.verify || self.print_after == Some(pass) {
+                    self.check(pass, &form.to_flat(), signatures)?;
+                }
+            }
+            optimized.extend(form.into_flat());
+        }
+        Ok(optimized)
+    }
+
+    fn check(&self, pass: Pass, instructions: &[Instruction], signatures: &IndexMap<String, Signature>) -> Result<(), PassError> {
+        if self.verify {
+            verify(instructions, signatures).map_err(|error| PassError::InvalidIr { pass: pass.name(), error })?;
+        }
+        if self.print_after == Some(pass) {
+            println!("- After '{}':", pass.name());
+            for inst in instructions {
+                println!("{}", inst.print());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn run_pass(pass: Pass, form: Form, frame: &Frame, unrolling: &Unrolling) -> Form {
+    let on_ssa = |form: Form, pass: fn(&mut SsaFunction)| {
+        let mut function = form.into_ssa();
+        pass(&mut function);
+        Form::Ssa(function)
+    };
+    match pass {
+        // the whole program at once, see `PassManager::run`
+        Pass::Inline => form,
+        Pass::TailRecursion => Form::Flat(eliminate_tail_recursion(form.into_flat(), frame)),
+        Pass::Constants => on_ssa(form, propagate_constants),
+        Pass::Copies => on_ssa(form, propagate_copies),
+        Pass::LocalValues => on_ssa(form, |function| number_values(function, false)),
+        Pass::GlobalValues => on_ssa(form, |function| number_values(function, true)),
+        Pass::LoopInvariants => on_ssa(form, hoist_invariants),
+        Pass::StrengthReduction => on_ssa(form, reduce_strength),
+        Pass::Unroll => {
+            let mut function = form.into_ssa();
+            unroll_loops(&mut function, unrolling);
+            Form::Ssa(function)
+        },
+        Pass::SimplifyCfg => on_ssa(form, simplify_cfg),
+        Pass::DeadCode => Form::Flat(eliminate_dead_code(form.into_flat())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compiler::tests::run_native, optimizer::tests::{assert_equivalent, int_calls, optimize_flat}};
+
+    use super::*;
+
+    #[test]
+    fn pass_pipelines() {
+        let input = "int printf(char *fmt, ...); int g(int a) { int k = 4; if (k > 3) { return a * k; } return 0; } \
+            int f(int n) { int s = 0; int i = 0; while (i < n) { s = s + g(i); i = i + 1; } printf(\"%d\\n\", s); return s; }";
+        let pipelines = [
+            optimization_level("0").unwrap(),
+            optimization_level("1").unwrap(),
+            optimization_level("2").unwrap(),
+            parse_passes("dce,sccp").unwrap(),
+            parse_passes("simplify-cfg, copy-prop,dce,sccp,sccp").unwrap(),
+            parse_passes("gvn,dce,lvn").unwrap(),
+            parse_passes("licm,sccp,licm").unwrap(),
+            parse_passes("sccp,strength-reduce,dce").unwrap(),
+            parse_passes("inline,sccp,inline,dce").unwrap(),
+            parse_passes("tailrec,sccp,tailrec").unwrap(),
+            parse_passes("sccp,copy-prop,unroll,sccp,simplify-cfg,dce").unwrap(),
+            with_unrolling(optimization_level("2").unwrap()),
+        ];
+
+        let (mut code_gen, _) = optimize_flat(input, |code_gen| code_gen.instructions.clone());
+        for passes in pipelines {
+            let mut pass_manager = new_pass_manager(passes.clone());
+            let got = pass_manager.run(&code_gen.instructions, &mut code_gen.frames, &code_gen.signatures);
+            assert!(got.is_ok(), "failed at: {:?}\n{}", passes, got.unwrap_err());
+            let instructions = got.unwrap();
+            if passes.is_empty() {
+                assert_eq!(instructions, code_gen.instructions);
+            }
+            assert!(passes.iter().all(|p| pass_manager.timings.contains_key(p.name())), "failed at: {:?}", passes);
+            assert_equivalent(&code_gen, &instructions, input, &int_calls(&[0, 1, 5]));
+        }
+
+        assert!(parse_passes("sccp,licm2").is_err());
+        assert!(optimization_level("3").is_err());
+    }
+
+    #[test]
+    fn presets_run_natively() {
+        // comparison results read again after the branch on them, once
+        // copies are propagated and once the unrolled copies share one
+        let input = "int g(int a, int b) { int c = a < b; int x = 5; if (c) { x = 1; } return c + x; } \
+            int h(int a, int b) { int s = 0; int i; for (i = 0; i < 8; i = i + 1) { int c = a < b; if (c) { s = s + i; } s = s * 2 + c; } return s; } \
+            int func() { int s = 0; int i; for (i = 0; i < 8; i = i + 1) { int c = i < 5; if (c) { s = s * 2; } s = s + c + g(i, 4); } \
+            return s + h(1, 2) * 1000 + h(3, 2); }";
+        let presets = [
+            optimization_level("0").unwrap(),
+            optimization_level("1").unwrap(),
+            optimization_level("2").unwrap(),
+            with_unrolling(optimization_level("2").unwrap()),
+        ];
+        for passes in presets {
+            let got = run_native(input, passes.clone());
+            assert_eq!(got, "result 'func': 749111\n", "failed at: {:?}", passes);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{intermediate::instruction::Instruction, optimizer::ssa::SsaFunction};
+
+// cleans up the CFG of a function in SSA form, mostly after constant
+// propagation turned branches into fallthroughs:
+// - a block whose only successor has it as only predecessor absorbs it
+// - an empty block, at most a Goto, is skipped by the jumps into it
+// - blocks the entry no longer reaches are removed
+// flattening the function writes the result back as TAC
+
+pub fn simplify_cfg(function: &mut SsaFunction) {
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in 0..function.blocks.len() {
+            if b != 0 && function.blocks[b].preds.is_empty() {
+                continue;
+            }
+            changed |= thread_jumps(function, b);
+            changed |= merge_successor(function, b);
+        }
+    }
+    function.remove_unreachable();
+}
+
+// appends the only successor of `b` when `b` is its only predecessor, the
+// phis of the successor have a single argument and become copies
+fn merge_successor(function: &mut SsaFunction, b: usize) -> bool {
+    let [s] = function.blocks[b].succs[..] else { return false };
+    if s == b || s == 0 || function.blocks[s].preds != [b] {
+        return false;
+    }
+    let absorbed = std::mem::take(&mut function.blocks[s]);
+    let block = &mut function.blocks[b];
+    if matches!(block.instructions.last(), Some(Instruction::Goto(_))) {
+        block.instructions.pop();
+    }
+    for phi in absorbed.phis {
+        block.instructions.push(Instruction::Assign { dest: phi.dest, arg1: phi.args[0].clone() });
+    }
+    block.instructions.extend(absorbed.instructions);
+    block.succs = absorbed.succs;
+    for t in block.succs.clone() {
+        for p in function.blocks[t].preds.iter_mut() {
+            if *p == s {
+                *p = b;
+            }
+        }
+    }
+    true
+}
+
+// when `b` is empty, its predecessors jump straight to its successor. the
+// phis there take for each of them the argument that came through `b`.
+// a predecessor already jumping there keeps going through `b`, one edge
+// cannot carry two arguments
+fn thread_jumps(function: &mut SsaFunction, b: usize) -> bool {
+    let block = &function.blocks[b];
+    let [t] = block.succs[..] else { return false };
+    let empty = block.phis.is_empty() && matches!(block.instructions[..], [] | [Instruction::Goto(_)]);
+    if b == 0 || t == b || !empty {
+        return false;
+    }
+    let through = function.blocks[t].preds.iter().position(|p| *p == b).unwrap();
+    let mut changed = false;
+    for p in function.blocks[b].preds.clone() {
+        if function.blocks[t].preds.contains(&p) {
+            continue;
+        }
+        for s in function.blocks[p].succs.iter_mut() {
+            if *s == b {
+                *s = t;
+            }
+        }
+        let target = &mut function.blocks[t];
+        target.preds.push(p);
+        for phi in target.phis.iter_mut() {
+            phi.args.push(phi.args[through].clone());
+        }
+        function.blocks[b].preds.retain(|q| *q != p);
+        changed = true;
+    }
+    if function.blocks[b].preds.is_empty() {
+        function.remove_edge(b, t);
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::optimizer::{constant::propagate_constants, tests::{assert_equivalent, int_calls, optimize_ssa}};
+
+    use super::*;
+
+    #[test]
+    fn cfg_simplification() {
+        // (program, blocks left in f)
+        let cases = [
+            ("int f(int n) { int x = 1; if (x) { n = n + 1; } else { n = n - 1; } while (0) { n = n * 2; } return n; }", Some(1)),
+            ("int f(int n) { if (n > 0) { if (n > 5) { n = 5; } } else { n = 0; } return n; }", None),
+            ("int f(int n) { int s = 0; for (int i = 0; i < n; i = i + 1) { if (i > 2) { s = s + i; } } return s; }", None),
+            ("int f(int n) { int a = 0; while (n > 0) { if (n > 3) { a = a + 2; } else { a = a - 1; } n = n - 1; } return a; }", None),
+        ];
+
+        for (input, expected_blocks) in cases {
+            let (code_gen, instructions) = optimize_ssa(input, |function| {
+                propagate_constants(function);
+                simplify_cfg(function);
+
+                let blocks = &function.blocks;
+                if let Some(count) = expected_blocks {
+                    assert_eq!(blocks.len(), count, "failed at: {}", input);
+                }
+                for (b, block) in blocks.iter().enumerate() {
+                    assert!(b == 0 || !block.preds.is_empty(), "unreachable block left in: {}", input);
+                    if let [s] = block.succs[..] {
+                        assert!(s == 0 || s == b || blocks[s].preds != [b], "chain left in: {}", input);
+                        let empty = block.phis.is_empty() && matches!(block.instructions[..], [] | [Instruction::Goto(_)]);
+                        assert!(b == 0 || !empty || block.preds.iter().all(|p| blocks[s].preds.contains(p)), "empty block left in: {}", input);
+                    }
+                }
+            });
+            assert_equivalent(&code_gen, &instructions, input, &int_calls(&[-1, 0, 2, 4, 9]));
+        }
+    }
+}
@@ -0,0 +1,586 @@
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{intermediate::{instruction::Instruction, irgen::Operand}, optimizer::{cfg::ControlFlowGraph, dominance::{DominatorTree, new_dominator_tree}}};
+
+// a function in SSA form. `succs` is the truth about control flow: IfZero
+// falls through to succs[0] and jumps to succs[1], Goto jumps to succs[0]
+// and any other block falls through to succs[0]. the one block with no
+// succs that does not end in Return runs into EndFunc, which is not kept.
+// the entry is block 0, it starts with BeginFunc and has no preds
+//
+// new definitions are named `name.N` after the name they replace, names
+// that are read before any definition (params) keep their name
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phi {
+    pub dest: Operand,
+    // one argument per predecessor, in the order of `preds`
+    pub args: Vec<Operand>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SsaBlock {
+    pub label: Option<String>,
+    pub phis: Vec<Phi>,
+    pub instructions: Vec<Instruction>,
+    pub preds: Vec<usize>,
+    pub succs: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SsaFunction {
+    pub name: String,
+    pub blocks: Vec<SsaBlock>,
+    // the last version given out for each base name
+    versions: IndexMap<String, usize>,
+}
+
+pub fn new_ssa_function(instructions: &[Instruction], cfg: &ControlFlowGraph) -> SsaFunction {
+    let (start, end) = cfg.range;
+    let body = &instructions[start..=end];
+    let name = match &body[0] {
+        Instruction::Label(l) => l.clone(),
+        inst => panic!("SSA: function starts with '{}'", inst.print()),
+    };
+
+    let mut blocks = Vec::new();
+    let successors = cfg.successors();
+    for (block, succs) in cfg.blocks.iter().zip(successors) {
+        let (first, last) = block.get_range();
+        let mut label = None;
+        let mut insts = Vec::new();
+        let mut returns = false;
+        for inst in &body[first..=last] {
+            match inst {
+                Instruction::Label(l) if insts.is_empty() && label.is_none() => label = Some(l.clone()),
+                Instruction::EndFunc => (),
+                // whatever follows a Return in the block is dead
+                _ if returns => (),
+                _ => {
+                    returns = matches!(inst, Instruction::Return { .. });
+                    insts.push(inst.clone());
+                },
+            }
+        }
+        let mut succs = if returns { Vec::new() } else { succs };
+        // a branch to the block it falls into anyway
+        if succs.len() == 2 && succs[0] == succs[1] {
+            insts.pop();
+            succs.pop();
+        }
+        blocks.push(SsaBlock { label, phis: Vec::new(), instructions: insts, preds: Vec::new(), succs });
+    }
+
+    let mut function = SsaFunction { name, blocks, versions: IndexMap::new() };
+    function.remove_unreachable();
+    function.link_preds();
+    function.number_versions();
+    function.insert_phis();
+    function.rename();
+    function
+}
+
+impl SsaFunction {
+    pub fn successors(&self) -> Vec<Vec<usize>> {
+        self.blocks.iter().map(|b| b.succs.clone()).collect()
+    }
+
+    pub fn dominator_tree(&self) -> DominatorTree {
+        new_dominator_tree(&self.successors())
+    }
+
+    // the names va_arg reads its arguments through. it advances them in
+    // place, so passes must not merge them with other names
+    pub fn va_lists(&self) -> IndexSet<String> {
+        let mut lists = IndexSet::new();
+        for block in &self.blocks {
+            for inst in &block.instructions {
+                if let Instruction::VaArg { list, .. } = inst {
+                    lists.extend(variable_name(list));
+                }
+            }
+        }
+        lists
+    }
+
+    // a new version of the base name of `name`
+    pub fn fresh_name(&mut self, name: &str) -> String {
+        let base = base_name(name);
+        let version = self.versions.entry(base.to_string()).or_insert(0);
+        *version += 1;
+        format!("{}.{}", base, version)
+    }
+
+    pub fn link_preds(&mut self) {
+        for block in self.blocks.iter_mut() {
+            block.preds.clear();
+        }
+        for b in 0..self.blocks.len() {
+            for s in self.blocks[b].succs.clone() {
+                self.blocks[s].preds.push(b);
+            }
+        }
+    }
+
+    // drops the edge and the phi arguments that came along it
+    pub fn remove_edge(&mut self, from: usize, to: usize) {
+        self.blocks[from].succs.retain(|s| *s != to);
+        let target = &mut self.blocks[to];
+        if let Some(j) = target.preds.iter().position(|p| *p == from) {
+            target.preds.remove(j);
+            for phi in target.phis.iter_mut() {
+                phi.args.remove(j);
+            }
+        }
+    }
+
+    // drops the blocks the entry cannot reach and renumbers the rest
+    pub fn remove_unreachable(&mut self) {
+        let tree = self.dominator_tree();
+        let mut new_id = vec![usize::MAX; self.blocks.len()];
+        let mut kept = 0;
+        for (b, id) in new_id.iter_mut().enumerate() {
+            if tree.is_reachable(b) {
+                *id = kept;
+                kept += 1;
+            }
+        }
+        let blocks = std::mem::take(&mut self.blocks);
+        for (b, mut block) in blocks.into_iter().enumerate() {
+            if new_id[b] == usize::MAX {
+                continue;
+            }
+            block.succs = block.succs.iter().map(|s| new_id[*s]).collect();
+            // phi arguments of removed predecessors go with them
+            let keep: Vec<bool> = block.preds.iter().map(|p| new_id[*p] != usize::MAX).collect();
+            for phi in block.phis.iter_mut() {
+                let mut k = keep.iter();
+                phi.args.retain(|_| *k.next().unwrap());
+            }
+            block.preds = block.preds.iter().filter(|p| new_id[**p] != usize::MAX).map(|p| new_id[*p]).collect();
+            self.blocks.push(block);
+        }
+    }
+
+    // versions already in use, so new names never collide with them
+    fn number_versions(&mut self) {
+        let mut names = Vec::new();
+        for block in &self.blocks {
+            for inst in &block.instructions {
+                names.extend(inst.uses().iter().chain(inst.def().iter()).filter_map(variable_name));
+            }
+        }
+        for name in names {
+            if let Some((base, version)) = name.rsplit_once('.')
+                && let Ok(version) = version.parse::<usize>() {
+                let last = self.versions.entry(base.to_string()).or_insert(0);
+                *last = (*last).max(version);
+            }
+        }
+    }
+
+    // pruned SSA: a name gets a phi in the iterated dominance frontier of
+    // its definitions, where it is live
+    fn insert_phis(&mut self) {
+        let tree = self.dominator_tree();
+        let (live_in, operands) = self.live_in();
+
+        let mut def_sites: IndexMap<String, IndexSet<usize>> = IndexMap::new();
+        for (b, block) in self.blocks.iter().enumerate() {
+            for inst in &block.instructions {
+                if let Some(name) = inst.def().as_ref().and_then(variable_name) {
+                    def_sites.entry(name).or_default().insert(b);
+                }
+            }
+        }
+
+        for (name, sites) in def_sites {
+            let mut has_phi = IndexSet::new();
+            let mut worklist: Vec<usize> = sites.iter().copied().collect();
+            while let Some(b) = worklist.pop() {
+                for &d in &tree.frontiers[b] {
+                    if has_phi.contains(&d) || !live_in[d].contains(&name) {
+                        continue;
+                    }
+                    has_phi.insert(d);
+                    let var = operands[&name].clone();
+                    let block = &mut self.blocks[d];
+                    block.phis.push(Phi { dest: var.clone(), args: vec![var; block.preds.len()] });
+                    if !sites.contains(&d) {
+                        worklist.push(d);
+                    }
+                }
+            }
+        }
+    }
+
+    // names live on entry to each block, and an operand for each name
+    fn live_in(&self) -> (Vec<IndexSet<String>>, IndexMap<String, Operand>) {
+        let n = self.blocks.len();
+        let mut operands = IndexMap::new();
+        let mut uses = vec![IndexSet::new(); n];
+        let mut defs = vec![IndexSet::new(); n];
+        for (b, block) in self.blocks.iter().enumerate() {
+            for inst in &block.instructions {
+                for op in inst.uses() {
+                    if let Some(name) = variable_name(&op) {
+                        if !defs[b].contains(&name) {
+                            uses[b].insert(name.clone());
+                        }
+                        operands.insert(name, op);
+                    }
+                }
+                if let Some(op) = inst.def() && let Some(name) = variable_name(&op) {
+                    defs[b].insert(name.clone());
+                    operands.insert(name, op);
+                }
+            }
+        }
+
+        let mut live_in = uses.clone();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in (0..n).rev() {
+                let mut live = uses[b].clone();
+                for s in &self.blocks[b].succs {
+                    live.extend(live_in[*s].iter().filter(|v| !defs[b].contains(*v)).cloned());
+                }
+                if live.len() != live_in[b].len() {
+                    live_in[b] = live;
+                    changed = true;
+                }
+            }
+        }
+        (live_in, operands)
+    }
+
+    fn rename(&mut self) {
+        // the name each phi stands for, before renaming
+        let phi_names: Vec<Vec<String>> = self.blocks.iter()
+            .map(|b| b.phis.iter().map(|p| p.dest.print()).collect())
+            .collect();
+        let tree = self.dominator_tree();
+        let mut stacks: IndexMap<String, Vec<String>> = IndexMap::new();
+        if !self.blocks.is_empty() {
+            self.rename_block(0, &tree, &phi_names, &mut stacks);
+        }
+    }
+
+    fn rename_block(&mut self, b: usize, tree: &DominatorTree, phi_names: &[Vec<String>], stacks: &mut IndexMap<String, Vec<String>>) {
+        let mut pushed = Vec::new();
+        for (k, name) in phi_names[b].iter().enumerate() {
+            let new_name = self.fresh_name(name);
+            let phi = &mut self.blocks[b].phis[k];
+            phi.dest = renamed(&phi.dest, new_name.clone());
+            stacks.entry(name.clone()).or_default().push(new_name);
+            pushed.push(name.clone());
+        }
+
+        let mut instructions = std::mem::take(&mut self.blocks[b].instructions);
+        for inst in instructions.iter_mut() {
+            for op in inst.uses_mut() {
+                if let Some(name) = variable_name(op)
+                    && let Some(current) = stacks.get(&name).and_then(|s| s.last()) {
+                    *op = renamed(op, current.clone());
+                }
+            }
+            if let Some(dest) = inst.def_mut() && let Some(name) = variable_name(dest) {
+                let new_name = self.fresh_name(&name);
+                *dest = renamed(dest, new_name.clone());
+                stacks.entry(name.clone()).or_default().push(new_name);
+                pushed.push(name);
+            }
+        }
+        self.blocks[b].instructions = instructions;
+
+        for s in self.blocks[b].succs.clone() {
+            let j = self.blocks[s].preds.iter().position(|p| *p == b).unwrap();
+            for (k, name) in phi_names[s].iter().enumerate() {
+                if let Some(current) = stacks.get(name).and_then(|st| st.last()) {
+                    let arg = &mut self.blocks[s].phis[k].args[j];
+                    *arg = renamed(arg, current.clone());
+                }
+            }
+        }
+
+        for &c in &tree.children[b] {
+            self.rename_block(c, tree, phi_names, stacks);
+        }
+        for name in pushed {
+            stacks[&name].pop();
+        }
+    }
+
+    // out of SSA: every phi becomes copies on its incoming edges, in the
+    // predecessor when it has no other successor, in the block when it has
+    // no other predecessor, otherwise in a new block splitting the edge
+    pub fn destruct(mut self) -> Vec<Instruction> {
+        for b in 0..self.blocks.len() {
+            let phis = std::mem::take(&mut self.blocks[b].phis);
+            if phis.is_empty() {
+                continue;
+            }
+            for (j, p) in self.blocks[b].preds.clone().into_iter().enumerate() {
+                let copies = phis.iter().map(|phi| (phi.dest.clone(), phi.args[j].clone())).collect();
+                let moves = self.sequentialize(copies);
+                if self.blocks[b].preds.len() == 1 {
+                    self.blocks[b].instructions.splice(0..0, moves);
+                } else if self.blocks[p].succs.len() == 1 {
+                    let insts = &mut self.blocks[p].instructions;
+                    let at = match insts.last() {
+                        Some(Instruction::Goto(_)) => insts.len() - 1,
+                        _ => insts.len(),
+                    };
+                    insts.splice(at..at, moves);
+                } else {
+                    let m = self.blocks.len();
+                    self.blocks.push(SsaBlock { label: None, phis: Vec::new(), instructions: moves, preds: vec![p], succs: vec![b] });
+                    let edge = self.blocks[p].succs.iter().position(|s| *s == b).unwrap();
+                    self.blocks[p].succs[edge] = m;
+                    self.blocks[b].preds[j] = m;
+                }
+            }
+        }
+        self.flatten()
+    }
+
+    // copies that happen at once, in an order where no source is overwritten
+    // before it is read. a cycle is broken by saving one of its values
+    fn sequentialize(&mut self, copies: Vec<(Operand, Operand)>) -> Vec<Instruction> {
+        let mut pending: Vec<(Operand, Operand)> = copies.into_iter().filter(|(d, s)| !same_variable(d, s)).collect();
+        let mut moves = Vec::new();
+        while !pending.is_empty() {
+            let ready = pending.iter().position(|(d, _)| !pending.iter().any(|(_, s)| same_variable(s, d)));
+            match ready {
+                Some(i) => {
+                    let (dest, arg1) = pending.remove(i);
+                    moves.push(Instruction::Assign { dest, arg1 });
+                },
+                None => {
+                    let saved = pending[0].0.clone();
+                    let temp = renamed(&saved, self.fresh_name(&saved.print()));
+                    moves.push(Instruction::Assign { dest: temp.clone(), arg1: saved.clone() });
+                    for (_, s) in pending.iter_mut() {
+                        if same_variable(s, &saved) {
+                            *s = temp.clone();
+                        }
+                    }
+                },
+            }
+        }
+        moves
+    }
+
+    // back to a flat function: the exit block goes last, every jump target
+    // gets a label and fallthroughs that no longer reach their successor
+    // become jumps
+    fn flatten(&self) -> Vec<Instruction> {
+        let n = self.blocks.len();
+        let is_exit = |b: &SsaBlock| b.succs.is_empty() && !matches!(b.instructions.last(), Some(Instruction::Return { .. }));
+        let mut layout: Vec<usize> = (0..n).filter(|b| *b == 0 || !is_exit(&self.blocks[*b])).collect();
+        layout.extend((1..n).filter(|b| is_exit(&self.blocks[*b])));
+        let next_of = |k: usize| layout.get(k + 1).copied();
+
+        // blocks reached by a jump
+        let mut targets = IndexSet::new();
+        for (k, &b) in layout.iter().enumerate() {
+            let block = &self.blocks[b];
+            match block.instructions.last() {
+                Some(Instruction::Goto(_)) => { targets.insert(block.succs[0]); },
+                Some(Instruction::IfZero { .. }) => {
+                    targets.insert(block.succs[1]);
+                    if next_of(k) != Some(block.succs[0]) {
+                        targets.insert(block.succs[0]);
+                    }
+                },
+                _ => if let Some(&s) = block.succs.first() && next_of(k) != Some(s) {
+                    targets.insert(s);
+                },
+            }
+        }
+
+        let mut labels: Vec<Option<String>> = self.blocks.iter().map(|b| b.label.clone()).collect();
+        let mut taken: IndexSet<String> = labels.iter().flatten().cloned().collect();
+        let mut count = 0;
+        for &t in &targets {
+            if labels[t].is_none() {
+                let label = loop {
+                    count += 1;
+                    let label = format!("{}.{}", self.name, count);
+                    if !taken.contains(&label) {
+                        break label;
+                    }
+                };
+                taken.insert(label.clone());
+                labels[t] = Some(label);
+            }
+        }
+        let label_of = |b: usize| labels[b].clone().unwrap();
+
+        let mut out = vec![Instruction::Label(self.name.clone())];
+        for (k, &b) in layout.iter().enumerate() {
+            let block = &self.blocks[b];
+            if let Some(label) = &labels[b] {
+                out.push(Instruction::Label(label.clone()));
+            }
+            let (body, last) = match block.instructions.split_last() {
+                Some((last, body)) if is_terminator(last) => (body, Some(last)),
+                _ => (&block.instructions[..], None),
+            };
+            out.extend(body.iter().cloned());
+            let fallthrough = match last {
+                Some(Instruction::Goto(_)) => {
+                    out.push(Instruction::Goto(label_of(block.succs[0])));
+                    None
+                },
+                Some(Instruction::IfZero { cond, .. }) => {
+                    out.push(Instruction::IfZero { cond: cond.clone(), label: label_of(block.succs[1]) });
+                    Some(block.succs[0])
+                },
+                Some(inst) => {
+                    out.push(inst.clone());
+                    None
+                },
+                None => block.succs.first().copied(),
+            };
+            if let Some(s) = fallthrough && next_of(k) != Some(s) {
+                out.push(Instruction::Goto(label_of(s)));
+            }
+        }
+        out.push(Instruction::EndFunc);
+        out
+    }
+}
+
+fn is_terminator(inst: &Instruction) -> bool {
+    matches!(inst, Instruction::Goto(_) | Instruction::IfZero { .. } | Instruction::Return { .. })
+}
+
+// the names SSA renames, `_ret` is the return register and stays as it is
+pub fn variable_name(op: &Operand) -> Option<String> {
+    match op {
+        Operand::Var(name, _) | Operand::Temp(name, _) if name != "_ret" => Some(name.clone()),
+        _ => None,
+    }
+}
+
+fn same_variable(a: &Operand, b: &Operand) -> bool {
+    matches!((variable_name(a), variable_name(b)), (Some(x), Some(y)) if x == y)
+}
+
+// `op` under another name, a Var stays a Var and a Temp a Temp
+pub fn renamed(op: &Operand, name: String) -> Operand {
+    match op {
+        Operand::Var(_, ty) => Operand::Var(name, *ty),
+        Operand::Temp(_, ty) => Operand::Temp(name, *ty),
+        _ => op.clone(),
+    }
+}
+
+// `x.3` -> `x`
+fn base_name(name: &str) -> &str {
+    match name.rsplit_once('.') {
+        Some((base, version)) if !version.is_empty() && version.chars().all(|c| c.is_ascii_digit()) => base,
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{intermediate::{analyzer::new_analyzer, interpreter::new_interpreter, irgen::{CodeGen, Value, new_codegen}, verify::verify}, optimizer::cfg::create_cfgs, parser::{parser::new_parser, token::Type}};
+
+    use super::*;
+
+    fn codegen_of(input: &str) -> CodeGen {
+        let mut parser = new_parser(input).unwrap();
+        let mut program_node = parser.parse().unwrap();
+        let mut analyzer = new_analyzer();
+        analyzer.analyze(&mut program_node).unwrap();
+        let mut code_gen = new_codegen(analyzer.function_frames, analyzer.function_signatures);
+        code_gen.cgen(&program_node);
+        code_gen
+    }
+
+    const PROGRAMS: [&str; 5] = [
+        "int f(int n) { int a = 0; int b = 1; while (n > 0) { int t = a + b; a = b; b = t; n = n - 1; } return a; }",
+        "int f(int n) { int x = 1; if (n > 3) { x = 2; } else { if (n < 1) { x = 3; } } return x * 10 + n; }",
+        "int f(int n) { int s = 0; for (int i = 0; i < n; i = i + 1) { if (i % 3 == 0) { s = s + i; } else { s = s - 1; } } return s; }",
+        "int g(int a, int b) { if (a > b) { return a - b; } return b - a; } int f(int n) { int x = n; int y = 10; while (x > 0) { int t = x; x = y - 1; y = t; } return g(x, y); }",
+        "int f(int n) { int a = n; int b = 2; int i = 0; while (i < 5) { int t = a; a = b; b = t; i = i + 1; } return a * 100 + b; }",
+    ];
+
+    #[test]
+    fn ssa_single_definitions() {
+        for input in PROGRAMS {
+            let mut code_gen = codegen_of(input);
+            let cfgs = create_cfgs(&mut code_gen.frames, &code_gen.instructions);
+            for function in cfgs.iter().map(|cfg| new_ssa_function(&code_gen.instructions, cfg)) {
+                let mut defined = IndexSet::new();
+                for block in &function.blocks {
+                    for phi in &block.phis {
+                        assert_eq!(phi.args.len(), block.preds.len(), "failed at: {}", input);
+                        assert!(defined.insert(phi.dest.print()), "failed at: {}", input);
+                    }
+                    for inst in &block.instructions {
+                        if let Some(dest) = inst.def() {
+                            assert!(defined.insert(dest.print()), "{} defined twice in: {}", dest.print(), input);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ssa_round_trip() {
+        for input in PROGRAMS {
+            let mut code_gen = codegen_of(input);
+            let cfgs = create_cfgs(&mut code_gen.frames, &code_gen.instructions);
+            let instructions: Vec<Instruction> = cfgs.iter().flat_map(|cfg| new_ssa_function(&code_gen.instructions, cfg).destruct()).collect();
+            let got = verify(&instructions, &code_gen.signatures);
+            assert!(got.is_ok(), "failed at: {}\n{}", input, got.unwrap_err());
+
+            for n in [0, 1, 2, 4, 7] {
+                let args = [Value::Int(n)];
+                let expected = new_interpreter(&code_gen.instructions, &code_gen.frames, &code_gen.strings).run("f", &args);
+                let got = new_interpreter(&instructions, &code_gen.frames, &code_gen.strings).run("f", &args);
+                assert!(matches!((&got, &expected), (Ok(a), Ok(b)) if a == b), "failed at: {} with {}", input, n);
+            }
+        }
+    }
+
+    #[test]
+    fn ssa_parallel_copies() {
+        let var = |name: &str| Operand::Var(name.to_string(), Type::Int);
+        let cases = [
+            vec![(var("a"), var("b")), (var("b"), var("a"))],
+            vec![(var("a"), var("b")), (var("b"), var("c")), (var("c"), var("a")), (var("d"), var("a"))],
+            vec![(var("a"), var("a")), (var("b"), Operand::Const(crate::parser::node::ConstValue::Int(7))), (var("c"), var("b"))],
+        ];
+
+        for copies in cases {
+            let mut function = SsaFunction { name: String::from("f"), blocks: Vec::new(), versions: IndexMap::new() };
+            let moves = function.sequentialize(copies.clone());
+
+            let initial = |name: &str| match name { "a" => 1, "b" => 2, "c" => 3, _ => 4 };
+            let mut values: IndexMap<String, i64> = IndexMap::new();
+            let read = |values: &IndexMap<String, i64>, op: &Operand| match op {
+                Operand::Const(crate::parser::node::ConstValue::Int(n)) => *n as i64,
+                _ => *values.get(&op.print()).unwrap_or(&initial(&op.print())),
+            };
+            for m in &moves {
+                let Instruction::Assign { dest, arg1 } = m else { panic!("not a copy") };
+                let v = read(&values, arg1);
+                values.insert(dest.print(), v);
+            }
+            for (dest, src) in &copies {
+                let expected = match src {
+                    Operand::Const(_) => 7,
+                    _ => initial(&src.print()),
+                };
+                assert_eq!(values.get(&dest.print()).copied().unwrap_or(initial(&dest.print())), expected, "failed at: {:?}", copies);
+            }
+        }
+    }
+}
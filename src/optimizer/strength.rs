@@ -0,0 +1,306 @@
+use indexmap::IndexMap;
+
+use crate::{intermediate::{instruction::Instruction, irgen::{Operand, Value, const_value}}, optimizer::{constant::constant, licm::{append_to_preheader, insert_preheaders, preheader}, loops::{Loop, find_loops}, ssa::{Phi, SsaFunction, renamed, variable_name}}, parser::{node::ConstValue, token::Type}};
+
+// strength reduction on a function in SSA form:
+// - a basic induction variable of a loop is a phi of its header that every
+//   back edge brings back increased by the same constant. a derived one is
+//   scale * i + offset for a basic i, built from it by adding, subtracting,
+//   multiplying and shifting by constants. a derived one that needs a
+//   multiplication becomes a basic one of its own, set in the preheader
+//   and increased next to i, so `i * 4` costs an addition per iteration
+// - a multiplication by a power of two becomes a shift, and so do divisions
+//   and remainders by one, the signed ones are biased to round toward zero
+// divisions by other constants are left to the backend, a multiplication
+// by a magic number there needs the high half of the product
+
+pub fn reduce_strength(function: &mut SsaFunction) {
+    insert_preheaders(function);
+    let forest = find_loops(&function.successors(), &function.dominator_tree());
+    for lp in &forest.loops {
+        reduce_induction_variables(function, lp);
+    }
+
+    for b in 0..function.blocks.len() {
+        let instructions = std::mem::take(&mut function.blocks[b].instructions);
+        for inst in instructions {
+            match by_power_of_two(function, &inst) {
+                Some(replaced) => function.blocks[b].instructions.extend(replaced),
+                None => function.blocks[b].instructions.push(inst),
+            }
+        }
+    }
+}
+
+pub struct Basic {
+    pub phi: Operand,
+    // the value coming from the preheader
+    pub init: Operand,
+    pub step: i64,
+    // the name the back edges bring back
+    pub next: String,
+}
+
+// scale * basic + offset
+#[derive(Debug, Clone, Copy)]
+struct Induction {
+    basic: usize,
+    scale: i64,
+    offset: i64,
+}
+
+fn reduce_induction_variables(function: &mut SsaFunction, lp: &Loop) {
+    let Some(pre) = preheader(function, lp) else { return };
+    let entry = function.blocks[lp.header].preds.iter().position(|p| *p == pre).unwrap();
+    let defs = loop_definitions(function, lp);
+    let basics = basic_inductions(function, lp, entry, &defs);
+    if basics.is_empty() {
+        return;
+    }
+
+    let mut inductions: IndexMap<String, Induction> = basics.iter().enumerate()
+        .map(|(n, basic)| (variable_name(&basic.phi).unwrap(), Induction { basic: n, scale: 1, offset: 0 }))
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (name, inst) in &defs {
+            if !inductions.contains_key(name) && let Some(induction) = derive(inst, &inductions, &basics) {
+                inductions.insert(name.clone(), induction);
+                changed = true;
+            }
+        }
+    }
+
+    for (name, induction) in inductions {
+        let Some(inst) = defs.get(&name) else { continue };
+        if induction.scale == 1 || !matches!(inst, Instruction::Mul { .. } | Instruction::ShiftLeft { .. }) {
+            continue;
+        }
+        let basic = &basics[induction.basic];
+        let ty = basic.phi.ty();
+        let Some(step) = basic.step.checked_mul(induction.scale).and_then(immediate) else { continue };
+
+        // scale * init + offset, before the loop
+        let dest = inst.def().unwrap();
+        let mut start = Vec::new();
+        let first = match int_constant(&basic.init, ty) {
+            Some(n) => match constant(Value::Int(n.wrapping_mul(induction.scale).wrapping_add(induction.offset)), ty) {
+                Some(c) => c,
+                None => continue,
+            },
+            None => {
+                let scaled = Operand::Temp(function.fresh_name(&dest.print()), ty);
+                start.push(Instruction::Mul { dest: scaled.clone(), arg1: basic.init.clone(), arg2: immediate(induction.scale).unwrap() });
+                if induction.offset == 0 {
+                    scaled
+                } else {
+                    let moved = Operand::Temp(function.fresh_name(&dest.print()), ty);
+                    start.push(Instruction::Add { dest: moved.clone(), arg1: scaled, arg2: immediate(induction.offset).unwrap() });
+                    moved
+                }
+            },
+        };
+        append_to_preheader(&mut function.blocks[pre], start);
+
+        let value = renamed(&dest, function.fresh_name(&dest.print()));
+        let next = renamed(&dest, function.fresh_name(&dest.print()));
+        let args = (0..function.blocks[lp.header].preds.len()).map(|j| if j == entry { first.clone() } else { next.clone() }).collect();
+        function.blocks[lp.header].phis.push(Phi { dest: value.clone(), args });
+        let increment = Instruction::Add { dest: next, arg1: value.clone(), arg2: step };
+        let copy = Instruction::Assign { dest, arg1: value };
+        for &b in &lp.body {
+            let instructions = &mut function.blocks[b].instructions;
+            if let Some(k) = instructions.iter().position(|i| defines(i, &basic.next)) {
+                instructions.insert(k + 1, increment.clone());
+            }
+            if let Some(k) = instructions.iter().position(|i| defines(i, &name)) {
+                instructions[k] = copy.clone();
+            }
+        }
+    }
+}
+
+// the definitions in the blocks of a loop, by name
+pub fn loop_definitions(function: &SsaFunction, lp: &Loop) -> IndexMap<String, Instruction> {
+    let mut defs = IndexMap::new();
+    for &b in &lp.body {
+        for inst in &function.blocks[b].instructions {
+            if let Some(name) = inst.def().and_then(|dest| variable_name(&dest)) {
+                defs.insert(name, inst.clone());
+            }
+        }
+    }
+    defs
+}
+
+// `entry` is the position of the preheader among the preds of the header
+pub fn basic_inductions(function: &SsaFunction, lp: &Loop, entry: usize, defs: &IndexMap<String, Instruction>) -> Vec<Basic> {
+    let mut basics = Vec::new();
+    for phi in &function.blocks[lp.header].phis {
+        let ty = phi.dest.ty();
+        let Some(name) = variable_name(&phi.dest) else { continue };
+        let mut latch_args = phi.args.iter().enumerate().filter(|(j, _)| *j != entry).map(|(_, arg)| variable_name(arg));
+        let Some(Some(next)) = latch_args.next() else { continue };
+        if !ty.is_integer() || !latch_args.all(|arg| arg.as_ref() == Some(&next)) {
+            continue;
+        }
+        let is_phi = |op: &Operand| variable_name(op).as_ref() == Some(&name);
+        // `i = i + 1` is a sum copied to i
+        let mut sum = defs.get(&next);
+        while let Some(Instruction::Assign { dest, arg1 }) = sum && dest.ty() == arg1.ty() {
+            sum = variable_name(arg1).and_then(|source| defs.get(&source));
+        }
+        let step = match sum {
+            Some(Instruction::Add { dest, arg1, arg2 }) if dest.ty() == ty && is_phi(arg1) => int_constant(arg2, ty),
+            Some(Instruction::Add { dest, arg1, arg2 }) if dest.ty() == ty && is_phi(arg2) => int_constant(arg1, ty),
+            Some(Instruction::Sub { dest, arg1, arg2 }) if dest.ty() == ty && is_phi(arg1) => int_constant(arg2, ty).and_then(i64::checked_neg),
+            _ => None,
+        };
+        // `_ret` is no initial value, the next call changes it
+        let init = phi.args[entry].clone();
+        if let Some(step) = step && (matches!(init, Operand::Const(_)) || variable_name(&init).is_some()) {
+            basics.push(Basic { phi: phi.dest.clone(), init, step, next });
+        }
+    }
+    basics
+}
+
+// the induction an instruction computes from another one
+fn derive(inst: &Instruction, inductions: &IndexMap<String, Induction>, basics: &[Basic]) -> Option<Induction> {
+    let of = |op: &Operand| variable_name(op).and_then(|name| inductions.get(&name)).copied();
+    let (x, c) = match inst {
+        Instruction::Assign { arg1, .. } => (of(arg1)?, None),
+        Instruction::Add { arg1, arg2, .. } | Instruction::Mul { arg1, arg2, .. } => match of(arg1) {
+            Some(x) => (x, Some(arg2)),
+            None => (of(arg2)?, Some(arg1)),
+        },
+        Instruction::Sub { arg1, arg2, .. } | Instruction::ShiftLeft { arg1, arg2, .. } => (of(arg1)?, Some(arg2)),
+        _ => return None,
+    };
+    let ty = basics[x.basic].phi.ty();
+    if inst.def()?.ty() != ty {
+        return None;
+    }
+    let Some(c) = c else { return Some(x) };
+    let c = int_constant(c, ty)?;
+    let (scale, offset) = match inst {
+        Instruction::Add { .. } => (x.scale, x.offset.checked_add(c)?),
+        Instruction::Sub { .. } => (x.scale, x.offset.checked_sub(c)?),
+        Instruction::Mul { .. } => (x.scale.checked_mul(c)?, x.offset.checked_mul(c)?),
+        _ if (0..31).contains(&c) => (x.scale.checked_mul(1 << c)?, x.offset.checked_mul(1 << c)?),
+        _ => return None,
+    };
+    // both end up as immediates
+    immediate(scale)?;
+    immediate(offset)?;
+    Some(Induction { basic: x.basic, scale, offset })
+}
+
+// the instructions for a multiplication, division or remainder by a power
+// of two. a signed division adds 2^k - 1 to a negative dividend first, so
+// that the shift rounds it up, and the remainder takes that bias back
+fn by_power_of_two(function: &mut SsaFunction, inst: &Instruction) -> Option<Vec<Instruction>> {
+    let (dest, x, c) = match inst {
+        Instruction::Mul { dest, arg1, arg2 } if !matches!(arg1, Operand::Const(_)) => (dest, arg1, arg2),
+        Instruction::Mul { dest, arg1, arg2 } => (dest, arg2, arg1),
+        Instruction::Div { dest, arg1, arg2 } | Instruction::Mod { dest, arg1, arg2 } => (dest, arg1, arg2),
+        _ => return None,
+    };
+    let ty = dest.ty();
+    if !ty.is_integer() || ty.size() < 4 || x.ty() != ty || matches!(x, Operand::Const(_)) {
+        return None;
+    }
+    let n = int_constant(c, ty)?;
+    if n < 2 || !(n as u64).is_power_of_two() {
+        return None;
+    }
+    let (dest, x) = (dest.clone(), x.clone());
+    let shift = immediate(n.trailing_zeros() as i64)?;
+    let mask = immediate(n - 1)?;
+    if let Instruction::Mul { .. } = inst {
+        return Some(vec![Instruction::ShiftLeft { dest, arg1: x, arg2: shift }]);
+    }
+    if !ty.is_signed() {
+        return Some(match inst {
+            Instruction::Div { .. } => vec![Instruction::ShiftRight { dest, arg1: x, arg2: shift }],
+            _ => vec![Instruction::BitwiseAnd { dest, arg1: x, arg2: mask }],
+        });
+    }
+
+    let mut temp = || Operand::Temp(function.fresh_name(&dest.print()), ty);
+    let (sign, bias, biased) = (temp(), temp(), temp());
+    let mut replaced = vec![
+        // all ones for a negative dividend
+        Instruction::ShiftRight { dest: sign.clone(), arg1: x.clone(), arg2: immediate(ty.size() as i64 * 8 - 1)? },
+        Instruction::BitwiseAnd { dest: bias.clone(), arg1: sign, arg2: mask.clone() },
+        Instruction::Add { dest: biased.clone(), arg1: x, arg2: bias.clone() },
+    ];
+    match inst {
+        Instruction::Div { .. } => replaced.push(Instruction::ShiftRight { dest, arg1: biased, arg2: shift }),
+        _ => {
+            let low = temp();
+            replaced.push(Instruction::BitwiseAnd { dest: low.clone(), arg1: biased, arg2: mask });
+            replaced.push(Instruction::Sub { dest, arg1: low, arg2: bias });
+        },
+    }
+    Some(replaced)
+}
+
+pub fn int_constant(op: &Operand, ty: Type) -> Option<i64> {
+    match op {
+        Operand::Const(c) => match const_value(c, ty) {
+            Value::Int(n) => Some(n),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// integer constants in instructions are 32-bit immediates
+pub fn immediate(n: i64) -> Option<Operand> {
+    Some(Operand::Const(ConstValue::Int(i32::try_from(n).ok()?)))
+}
+
+fn defines(inst: &Instruction, name: &str) -> bool {
+    inst.def().and_then(|dest| variable_name(&dest)).is_some_and(|d| d == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::optimizer::{constant::propagate_constants, tests::{assert_equivalent, int_calls, optimize_ssa}};
+
+    use super::*;
+
+    #[test]
+    fn strength_reduction() {
+        // (program, operators left nowhere in f, operators left in no loop of f)
+        let cases: [(&str, &[&str], &[&str]); 6] = [
+            ("int f(int n) { return n / 8 + n % 8 * 3 + n * 16 - 2 * n; }", &["/", "%", "* 16", "2 *"], &[]),
+            ("int f(int n) { unsigned int u = n; unsigned int v = u / 4 + u % 16; return v; }", &["/", "%"], &[]),
+            ("long f(int n) { long x = n; return x / 16 - x % 4 + x / 3; }", &["/ 16", "%"], &[]),
+            ("int f(int n) { int s = 0; for (int i = 0; i < n; i = i + 1) { s = s + i * 12; } return s; }", &["*"], &[]),
+            // set up in the preheader from n
+            ("int f(int n) { int s = 0; int i = n; while (i > 0) { s = s + (i + 3) * 5; i = i - 2; } return s; }", &[], &["*"]),
+            ("int f(int n) { int s = 0; for (int i = 0; i < n; i = i + 1) { for (int j = 0; j < 3; j = j + 1) { s = s + (i << 3) + j * 7; } } return s; }",
+                &[], &["<<", "*"]),
+        ];
+
+        for (input, nowhere, outside_loops) in cases {
+            let (code_gen, instructions) = optimize_ssa(input, |function| {
+                // constants converted to unsigned reach the division folded
+                propagate_constants(function);
+                reduce_strength(function);
+                let depth = find_loops(&function.successors(), &function.dominator_tree()).depth;
+                for (b, block) in function.blocks.iter().enumerate() {
+                    for line in block.instructions.iter().map(|i| i.print()) {
+                        for op in nowhere.iter().chain(if depth[b] > 0 { outside_loops } else { &[] }) {
+                            assert!(!line.contains(op), "'{}' left in: {}", line, input);
+                        }
+                    }
+                }
+            });
+            assert_equivalent(&code_gen, &instructions, input, &int_calls(&[-9, -8, -7, -1, 0, 1, 5, 8, 100]));
+        }
+    }
+}
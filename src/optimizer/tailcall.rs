@@ -0,0 +1,120 @@
+use crate::{intermediate::{frame::Frame, instruction::Instruction, irgen::Operand}, optimizer::inline::temp_number, parser::token::Type};
+
+// tail recursion elimination on the flat TAC of a function. a call of the
+// function itself whose result is returned right away
+//   PushParam a ... LCall f, t = _ret, PopParams n, Return t
+// becomes a jump back to its start with the parameters assigned the pushed
+// arguments. they are copied to temporaries first, an argument can read a
+// parameter assigned before it. the start is a label after BeginFunc, so
+// the entry block keeps no predecessors. a variadic function keeps its
+// calls, the arguments after the named ones have nowhere to go
+
+pub fn eliminate_tail_recursion(instructions: Vec<Instruction>, frame: &Frame) -> Vec<Instruction> {
+    if frame.variadic {
+        return instructions;
+    }
+    let sites: Vec<(usize, usize)> = (0..instructions.len()).filter_map(|i| tail_call(&instructions, i, frame).map(|len| (i, len))).collect();
+    if sites.is_empty() {
+        return instructions;
+    }
+
+    let start = format!("{}.tail", frame.name);
+    let mut next_temp = 1 + instructions.iter()
+        .flat_map(|inst| inst.uses().into_iter().chain(inst.def()))
+        .filter_map(|op| temp_number(&op))
+        .max().unwrap_or(0);
+
+    let n = frame.params.len();
+    let mut eliminated = Vec::new();
+    let mut i = 0;
+    while i < instructions.len() {
+        match sites.iter().find(|(at, _)| *at == i + n) {
+            Some(&(at, len)) => {
+                let mut copies = Vec::new();
+                for (push, param) in instructions[i..at].iter().zip(&frame.params) {
+                    let Instruction::PushParam(arg, ty) = push else { unreachable!() };
+                    let param = Operand::Var(param.name.clone(), *ty);
+                    if *arg == param {
+                        continue;
+                    }
+                    let temp = Operand::Temp(format!("t{}", next_temp), *ty);
+                    next_temp += 1;
+                    eliminated.push(Instruction::Assign { dest: temp.clone(), arg1: arg.clone() });
+                    copies.push(Instruction::Assign { dest: param, arg1: temp });
+                }
+                eliminated.extend(copies);
+                eliminated.push(Instruction::Goto(start.clone()));
+                i = at + len;
+            },
+            None => {
+                eliminated.push(instructions[i].clone());
+                if matches!(instructions[i], Instruction::BeginFunc(_)) && instructions.get(i + 1) != Some(&Instruction::Label(start.clone())) {
+                    eliminated.push(Instruction::Label(start.clone()));
+                }
+                i += 1;
+            },
+        }
+    }
+    eliminated
+}
+
+// the number of instructions from the LCall at `i` to the Return, when it
+// is a tail call of `frame` with arguments of the parameter types
+fn tail_call(instructions: &[Instruction], i: usize, frame: &Frame) -> Option<usize> {
+    if instructions[i] != Instruction::LCall(frame.name.clone()) {
+        return None;
+    }
+    let pushes = instructions.get(i.checked_sub(frame.params.len())?..i)?;
+    let typed = pushes.iter().zip(&frame.params).all(|(push, param)| {
+        matches!(push, Instruction::PushParam(arg, ty) if *ty == param.stype && arg.ty() == param.stype)
+    });
+    if !typed {
+        return None;
+    }
+    match &instructions[i + 1..] {
+        [Instruction::Assign { dest, arg1: Operand::Temp(ret, _) }, Instruction::PopParams(_), Instruction::Return { dest: returned }, ..]
+            if ret == "_ret" && dest == returned => Some(4),
+        // a void function returns by reaching EndFunc
+        [Instruction::Assign { arg1: Operand::Temp(ret, _), .. }, Instruction::PopParams(_), Instruction::EndFunc, ..]
+            if ret == "_ret" && frame.ret_type == Type::Void => Some(3),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{intermediate::irgen::Value, optimizer::{cfg::create_cfgs, tests::{assert_equivalent, optimize_flat}}};
+
+    use super::*;
+
+    #[test]
+    fn tail_recursion() {
+        // (program, calls of f left)
+        let cases = [
+            ("int f(int n, int acc) { if (n < 2) { return acc; } return f(n - 1, acc * n); }", 0),
+            // the arguments swap the parameters
+            ("int f(int a, int b) { if (a <= 0) { return b; } return f(b - 1, a); }", 0),
+            ("int f(int n, int acc) { if (n == 0) { return acc; } if (n > 5) { return f(n - 2, acc + 1); } return f(n - 1, acc + n); }", 0),
+            // the result is used after the call
+            ("int f(int n, int acc) { if (n < 2) { return acc; } return f(n - 1, acc) + 1; }", 1),
+            ("int g(int n, int acc) { return n + acc; } int f(int n, int acc) { if (n < 2) { return g(n, acc); } return f(n - 1, acc + 2); }", 0),
+        ];
+
+        for (input, calls) in cases {
+            let (code_gen, instructions) = optimize_flat(input, |code_gen| {
+                let cfgs = create_cfgs(&mut code_gen.frames, &code_gen.instructions);
+                let mut instructions = Vec::new();
+                for (cfg, frame) in cfgs.iter().zip(code_gen.frames.values()) {
+                    let (start, end) = cfg.range;
+                    instructions.extend(eliminate_tail_recursion(code_gen.instructions[start..=end].to_vec(), frame));
+                }
+                instructions
+            });
+            let left = instructions.iter().filter(|i| **i == Instruction::LCall(String::from("f"))).count();
+            assert_eq!(left, calls, "failed at: {}", input);
+
+            let args: Vec<Vec<Value>> = [0, 1, 5, 8].iter().map(|n| vec![Value::Int(*n), Value::Int(3)]).collect();
+            assert_equivalent(&code_gen, &instructions, input, &args);
+        }
+    }
+}
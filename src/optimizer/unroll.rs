@@ -0,0 +1,410 @@
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{intermediate::{instruction::Instruction, irgen::{Operand, Value}}, optimizer::{constant::constant, licm::{insert_preheaders, preheader}, loops::{Loop, find_loops}, ssa::{Phi, SsaBlock, SsaFunction, renamed, variable_name}, strength::{Basic, basic_inductions, int_constant, immediate, loop_definitions}}, parser::token::Type};
+
+// loop unrolling on a function in SSA form. it takes innermost loops that
+// are only left by one test of a basic induction variable i, or of its
+// next value, against a bound that the loop does not change
+//   header: i = phi(init, next) ... c = next < n, IfZero c
+// an iteration is copied with every name the loop defines renamed and
+// without the test, the phis of its header become copies of what the
+// previous iteration ends with.
+// - when init and n are constants and the loop runs a few times, that many
+//   iterations run one after the other before it, the loop is left with
+//   nothing to do and sccp folds its test
+// - otherwise `factor` iterations form a new loop in front of the original,
+//   taken while i + (factor - 1) * step still passes the test. the original
+//   loop runs what remains and is still the only way out, so no name needs
+//   a new phi. a constant bound is moved by the steps instead, a 32 bit
+//   variable is compared in 64 bits where the sum cannot wrap
+// the copies of a loop must fit in the budget of instructions, a partial
+// unrolling takes a smaller factor when they do not
+
+pub struct Unrolling {
+    pub factor: usize,
+    pub budget: usize,
+}
+
+pub fn new_unrolling() -> Unrolling {
+    Unrolling { factor: 4, budget: 200 }
+}
+
+// the most iterations a loop is fully unrolled for
+const FULL_LIMIT: usize = 16;
+
+pub fn unroll_loops(function: &mut SsaFunction, unrolling: &Unrolling) {
+    insert_preheaders(function);
+    let forest = find_loops(&function.successors(), &function.dominator_tree());
+    for lp in &forest.loops {
+        let innermost = lp.body.iter().all(|b| forest.depth[*b] == lp.depth);
+        if innermost && let Some(shape) = loop_shape(function, lp) {
+            unroll(function, lp, &shape, unrolling);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Relation {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Relation {
+    fn swapped(self) -> Relation {
+        match self {
+            Relation::Less => Relation::Greater,
+            Relation::LessEqual => Relation::GreaterEqual,
+            Relation::Greater => Relation::Less,
+            Relation::GreaterEqual => Relation::LessEqual,
+        }
+    }
+
+    fn holds(self, a: i128, b: i128) -> bool {
+        match self {
+            Relation::Less => a < b,
+            Relation::LessEqual => a <= b,
+            Relation::Greater => a > b,
+            Relation::GreaterEqual => a >= b,
+        }
+    }
+
+    fn instruction(self, dest: Operand, arg1: Operand, arg2: Operand) -> Instruction {
+        match self {
+            Relation::Less => Instruction::Less { dest, arg1, arg2 },
+            Relation::LessEqual => Instruction::LessEqual { dest, arg1, arg2 },
+            Relation::Greater => Instruction::Greater { dest, arg1, arg2 },
+            Relation::GreaterEqual => Instruction::GreaterEqual { dest, arg1, arg2 },
+        }
+    }
+}
+
+// the loop is left from one block, when `basic + offset relation bound` is
+// false. the offset is the step when the test reads the next value of i
+struct Shape {
+    pre: usize,
+    latch: usize,
+    exiting: usize,
+    // the position of the preheader among the preds of the header
+    entry: usize,
+    basic: Basic,
+    offset: i64,
+    relation: Relation,
+    bound: Operand,
+    cond: Operand,
+    // the names the loop defines
+    defined: IndexSet<String>,
+    size: usize,
+}
+
+fn loop_shape(function: &SsaFunction, lp: &Loop) -> Option<Shape> {
+    let h = lp.header;
+    let pre = preheader(function, lp)?;
+    let [latch] = lp.latches[..] else { return None };
+    let [exiting] = lp.body.iter().copied().filter(|b| function.blocks[*b].succs.iter().any(|s| !lp.body.contains(s))).collect::<Vec<usize>>()[..] else { return None };
+    let block = &function.blocks[exiting];
+    let Some(Instruction::IfZero { cond, .. }) = block.instructions.last() else { return None };
+    if !lp.body.contains(&block.succs[0]) || lp.body.contains(&block.succs[1]) {
+        return None;
+    }
+    // without the test the latch only goes back
+    let back: &[usize] = if latch == exiting { &block.succs[..1] } else { &function.blocks[latch].succs };
+    if back != [h] {
+        return None;
+    }
+
+    let entry = function.blocks[h].preds.iter().position(|p| *p == pre)?;
+    let defs = loop_definitions(function, lp);
+    let (relation, a, b) = match defs.get(&variable_name(cond)?)? {
+        Instruction::Less { arg1, arg2, .. } => (Relation::Less, arg1, arg2),
+        Instruction::LessEqual { arg1, arg2, .. } => (Relation::LessEqual, arg1, arg2),
+        Instruction::Greater { arg1, arg2, .. } => (Relation::Greater, arg1, arg2),
+        Instruction::GreaterEqual { arg1, arg2, .. } => (Relation::GreaterEqual, arg1, arg2),
+        _ => return None,
+    };
+    let mut basics = basic_inductions(function, lp, entry, &defs);
+    // i or its next value, through the copies of them
+    let read = |op: &Operand| {
+        let name = original(variable_name(op)?, &defs);
+        basics.iter().enumerate().find_map(|(k, basic)| {
+            if variable_name(&basic.phi).as_ref() == Some(&name) {
+                Some((k, 0))
+            } else if original(basic.next.clone(), &defs) == name {
+                Some((k, basic.step))
+            } else {
+                None
+            }
+        })
+    };
+    let ((k, offset), relation, bound) = match (read(a), read(b)) {
+        (Some(read), None) => (read, relation, b.clone()),
+        (None, Some(read)) => (read, relation.swapped(), a.clone()),
+        _ => return None,
+    };
+    let basic = basics.swap_remove(k);
+    let ty = basic.phi.ty();
+    if a.ty() != ty || b.ty() != ty {
+        return None;
+    }
+    // i has to move toward the bound
+    let increasing = matches!(relation, Relation::Less | Relation::LessEqual);
+    if basic.step == 0 || (basic.step > 0) != increasing {
+        return None;
+    }
+
+    let mut defined: IndexSet<String> = defs.into_keys().collect();
+    for &b in &lp.body {
+        defined.extend(function.blocks[b].phis.iter().filter_map(|phi| variable_name(&phi.dest)));
+    }
+    // `_ret` is written by every call
+    let invariant = match &bound {
+        Operand::Const(_) => true,
+        _ => variable_name(&bound).is_some_and(|name| !defined.contains(&name)),
+    };
+    if !invariant {
+        return None;
+    }
+    let size = lp.body.iter().map(|b| function.blocks[*b].phis.len() + function.blocks[*b].instructions.len()).sum();
+    Some(Shape { pre, latch, exiting, entry, basic, offset, relation, bound, cond: cond.clone(), defined, size })
+}
+
+// the name a copy `x = y` of the same type comes from
+fn original(mut name: String, defs: &IndexMap<String, Instruction>) -> String {
+    while let Some(Instruction::Assign { dest, arg1 }) = defs.get(&name) && dest.ty() == arg1.ty() && let Some(source) = variable_name(arg1) {
+        name = source;
+    }
+    name
+}
+
+fn unroll(function: &mut SsaFunction, lp: &Loop, shape: &Shape, unrolling: &Unrolling) {
+    if let Some(trips) = trip_count(shape) && trips * shape.size <= unrolling.budget {
+        // a loop that never runs is left to sccp
+        if trips > 0 {
+            unroll_fully(function, lp, shape, trips);
+        }
+        return;
+    }
+    let factor = unrolling.factor.min(unrolling.budget / shape.size.max(1));
+    if factor >= 2 {
+        unroll_partially(function, lp, shape, factor);
+    }
+}
+
+// the number of iterations when it is at most the limit
+fn trip_count(shape: &Shape) -> Option<usize> {
+    let ty = shape.basic.phi.ty();
+    let mut i = wrap(int_constant(&shape.basic.init, ty)? as i128, ty);
+    let n = wrap(int_constant(&shape.bound, ty)? as i128, ty);
+    for trips in 0..=FULL_LIMIT {
+        if !shape.relation.holds(wrap(i + shape.offset as i128, ty), n) {
+            return Some(trips);
+        }
+        i = wrap(i + shape.basic.step as i128, ty);
+    }
+    None
+}
+
+// `n` as a value of `ty`
+fn wrap(n: i128, ty: Type) -> i128 {
+    let bits = ty.size() as u32 * 8;
+    let low = n & ((1 << bits) - 1);
+    if ty.is_signed() && low >> (bits - 1) == 1 { low - (1 << bits) } else { low }
+}
+
+fn unroll_fully(function: &mut SsaFunction, lp: &Loop, shape: &Shape, trips: usize) {
+    let h = lp.header;
+    let init: Vec<Operand> = function.blocks[h].phis.iter().map(|phi| phi.args[shape.entry].clone()).collect();
+    let (first, last, values) = copy_iterations(function, lp, shape, trips, shape.pre, init);
+    redirect(function, shape.pre, h, first);
+
+    // the loop starts over from where the copies end
+    function.blocks[last].succs = vec![h];
+    function.blocks[h].preds[shape.entry] = last;
+    for (phi, value) in function.blocks[h].phis.iter_mut().zip(values) {
+        phi.args[shape.entry] = value;
+    }
+}
+
+fn unroll_partially(function: &mut SsaFunction, lp: &Loop, shape: &Shape, factor: usize) {
+    let h = lp.header;
+    let ty = shape.basic.phi.ty();
+    // the value the last of the tests reads
+    let Some(ahead) = (factor as i64 - 1).checked_mul(shape.basic.step).and_then(|n| n.checked_add(shape.offset)) else { return };
+
+    // the header of the unrolled loop
+    let u = function.blocks.len();
+    let mut phis = Vec::new();
+    for phi in function.blocks[h].phis.clone() {
+        let dest = renamed(&phi.dest, function.fresh_name(&phi.dest.print()));
+        phis.push(Phi { dest, args: vec![phi.args[shape.entry].clone()] });
+    }
+    let basic = phis.iter().zip(&function.blocks[h].phis).find(|(_, phi)| phi.dest == shape.basic.phi).unwrap().0.dest.clone();
+    let cond = renamed(&shape.cond, function.fresh_name(&shape.cond.print()));
+    let mut test = Vec::new();
+    match int_constant(&shape.bound, ty) {
+        Some(n) => {
+            let moved = wrap(n as i128, ty) - ahead as i128;
+            if wrap(moved, ty) != moved {
+                return;
+            }
+            let Some(moved) = constant(Value::Int(moved as i64), ty) else { return };
+            test.push(shape.relation.instruction(cond.clone(), basic, moved));
+        },
+        None if ty.size() == 4 => {
+            let mut wide = |op: &Operand| {
+                let dest = Operand::Temp(function.fresh_name(&op.print()), Type::Long);
+                test.push(match ty.is_signed() {
+                    true => Instruction::SignExtend { dest: dest.clone(), arg1: op.clone(), from: ty, to: Type::Long },
+                    false => Instruction::ZeroExtend { dest: dest.clone(), arg1: op.clone(), from: ty, to: Type::Long },
+                });
+                dest
+            };
+            let (i, n) = (wide(&basic), wide(&shape.bound));
+            let last = Operand::Temp(function.fresh_name(&basic.print()), Type::Long);
+            let Some(ahead) = immediate(ahead) else { return };
+            test.push(Instruction::Add { dest: last.clone(), arg1: i, arg2: ahead });
+            test.push(shape.relation.instruction(cond.clone(), last, n));
+        },
+        None => return,
+    }
+    let Some(Instruction::IfZero { label, .. }) = function.blocks[shape.exiting].instructions.last().cloned() else { return };
+    test.push(Instruction::IfZero { cond, label });
+    let values = phis.iter().map(|phi| phi.dest.clone()).collect();
+    function.blocks.push(SsaBlock { label: None, phis, instructions: test, preds: vec![shape.pre], succs: Vec::new() });
+
+    let (first, last, values) = copy_iterations(function, lp, shape, factor, u, values);
+    function.blocks[last].succs = vec![u];
+    function.blocks[u].preds.push(last);
+    for (phi, value) in function.blocks[u].phis.iter_mut().zip(values) {
+        phi.args.push(value);
+    }
+    function.blocks[u].succs = vec![first, h];
+
+    // what the unrolled loop leaves goes to the original one
+    redirect(function, shape.pre, h, u);
+    function.blocks[h].preds[shape.entry] = u;
+    let entering: Vec<Operand> = function.blocks[u].phis.iter().map(|phi| phi.dest.clone()).collect();
+    for (phi, value) in function.blocks[h].phis.iter_mut().zip(entering) {
+        phi.args[shape.entry] = value;
+    }
+}
+
+fn redirect(function: &mut SsaFunction, from: usize, old: usize, new: usize) {
+    for s in function.blocks[from].succs.iter_mut() {
+        if *s == old {
+            *s = new;
+        }
+    }
+}
+
+// `count` iterations of the loop one after the other, entered from `from`
+// with `values` for the phis of the header. the header of the first copy,
+// the latch of the last one and the values it brings back. the latch of
+// the last copy is left without successors
+fn copy_iterations(function: &mut SsaFunction, lp: &Loop, shape: &Shape, count: usize, from: usize, mut values: Vec<Operand>) -> (usize, usize, Vec<Operand>) {
+    let h = lp.header;
+    let back = 1 - shape.entry;
+    let mut first = None;
+    let mut previous = from;
+    for _ in 0..count {
+        let base = function.blocks.len();
+        let ids: IndexMap<usize, usize> = lp.body.iter().enumerate().map(|(k, b)| (*b, base + k)).collect();
+        let names: IndexMap<String, String> = shape.defined.iter().map(|name| (name.clone(), function.fresh_name(name))).collect();
+        let rename = |op: &mut Operand| {
+            if let Some(name) = variable_name(op).and_then(|name| names.get(&name)) {
+                *op = renamed(op, name.clone());
+            }
+        };
+
+        for &b in &lp.body {
+            let block = &function.blocks[b];
+            let mut copy = SsaBlock { label: None, phis: Vec::new(), instructions: Vec::new(), preds: Vec::new(), succs: Vec::new() };
+            let mut instructions = &block.instructions[..];
+            let mut succs = block.succs.clone();
+            if b == shape.exiting {
+                instructions = &instructions[..instructions.len() - 1];
+                succs.truncate(1);
+            }
+            // the latch goes on to the next copy
+            copy.succs = succs.iter().filter(|s| **s != h).map(|s| ids[s]).collect();
+            if b == h {
+                for (phi, value) in block.phis.iter().zip(&values) {
+                    let mut dest = phi.dest.clone();
+                    rename(&mut dest);
+                    copy.instructions.push(Instruction::Assign { dest, arg1: value.clone() });
+                }
+                copy.preds = vec![previous];
+            } else {
+                for phi in &block.phis {
+                    let mut phi = phi.clone();
+                    rename(&mut phi.dest);
+                    phi.args.iter_mut().for_each(rename);
+                    copy.phis.push(phi);
+                }
+                copy.preds = block.preds.iter().map(|p| ids[p]).collect();
+            }
+            for inst in instructions {
+                let mut inst = inst.clone();
+                inst.uses_mut().into_iter().for_each(rename);
+                inst.def_mut().into_iter().for_each(rename);
+                copy.instructions.push(inst);
+            }
+            function.blocks.push(copy);
+        }
+
+        values = function.blocks[h].phis.iter().map(|phi| {
+            let mut value = phi.args[back].clone();
+            rename(&mut value);
+            value
+        }).collect();
+        if previous != from {
+            function.blocks[previous].succs = vec![ids[&h]];
+        }
+        first.get_or_insert(ids[&h]);
+        previous = ids[&shape.latch];
+    }
+    (first.unwrap(), previous, values)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::optimizer::{constant::propagate_constants, copies::propagate_copies, simplify::simplify_cfg, tests::{assert_equivalent, int_calls, optimize_ssa}};
+
+    use super::*;
+
+    #[test]
+    fn loop_unrolling() {
+        // (program, loops left in f)
+        let cases = [
+            // ten iterations, none left
+            ("int f(int n) { int s = 0; for (int i = 0; i < 10; i = i + 1) { s = s + i * n; } return s; }", 0),
+            ("int f(int n) { int s = 1; for (int i = 12; i >= 0; i = i - 3) { if (i > n) { s = s * 2; } } return s; }", 0),
+            // an unrolled loop in front of the original
+            ("int f(int n) { int s = 0; for (int i = 0; i < n; i = i + 1) { s = s + i; } return s; }", 2),
+            ("int f(int n) { int s = 1; int i = n; while (i > 0) { s = s * 3 + i; i = i - 2; } return s; }", 2),
+            ("int f(int n) { int s = 0; for (int i = 0; i < 100; i = i + 3) { if (i > n) { s = s + i; } } return s; }", 2),
+            ("unsigned int f(unsigned int n) { unsigned int s = 0; for (unsigned int i = 2; i <= n; i = i + 1) { s = s ^ i; } return s; }", 2),
+            // i + 3 goes past the largest int
+            ("int f(int n) { int m = 2147483647 - n; int c = 0; for (int i = m - 6; i < m; i = i + 1) { c = c + i % 3; } return c; }", 2),
+            // the bound changes in the loop
+            ("int f(int n) { int s = 0; int i = 0; while (i < n) { n = n - 1; i = i + 1; s = s + 1; } return s; }", 1),
+            ("long f(long n) { long s = 0; for (long i = 0; i < n; i = i + 1) { s = s + i; } return s; }", 1),
+            // the inner loop is unrolled, the outer one stays
+            ("int f(int n) { int s = 0; for (int i = 0; i < n; i = i + 1) { for (int j = 0; j < 3; j = j + 1) { s = s + i * j; } } return s; }", 1),
+        ];
+
+        for (input, loops) in cases {
+            let (code_gen, instructions) = optimize_ssa(input, |function| {
+                propagate_constants(function);
+                propagate_copies(function);
+                unroll_loops(function, &new_unrolling());
+                propagate_constants(function);
+                simplify_cfg(function);
+                let forest = find_loops(&function.successors(), &function.dominator_tree());
+                assert_eq!(forest.loops.len(), loops, "failed at: {}", input);
+            });
+            assert_equivalent(&code_gen, &instructions, input, &int_calls(&[0, 1, 2, 3, 4, 5, 9, 14]));
+        }
+    }
+}
@@ -15,13 +15,16 @@ pub struct Lexer {
 pub enum LexerError {
     InvalidChar(char, usize),
     InvalidConst(String, usize),
+    UnterminatedString(usize),
 }
 
 impl fmt::Display for LexerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             LexerError::InvalidChar(c, pos) => write!(f, "LexerError: invalid char '{}' found in position {}.", c, pos),
-            LexerError::InvalidConst(c, pos) => write!(f, "LexerError: invalid const '{}' found in position {}.", c, pos),        }
+            LexerError::InvalidConst(c, pos) => write!(f, "LexerError: invalid const '{}' found in position {}.", c, pos),
+            LexerError::UnterminatedString(pos) => write!(f, "LexerError: unterminated string starting in position {}.", pos),
+        }
     }
 }
 
@@ -102,6 +105,31 @@ impl Lexer {
 
     }
 
+    fn read_string(&mut self) -> Result<String, LexerError> {
+        let start = self.curr;
+        let mut bytes = Vec::new();
+        self.read_char();
+        while self.ch != b'"' {
+            match self.ch {
+                0 | b'\n' => return Err(LexerError::UnterminatedString(start)),
+                b'\\' => {
+                    self.read_char();
+                    bytes.push(match self.ch {
+                        b'n' => b'\n',
+                        b't' => b'\t',
+                        b'r' => b'\r',
+                        b'0' => 0,
+                        c => c,
+                    });
+                },
+                c => bytes.push(c),
+            }
+            self.read_char();
+        }
+        self.read_char();
+        Ok(String::from_utf8_lossy(&bytes).to_string())
+    }
+
     fn read_ident(&mut self) -> String {
         let start = self.curr;
         while self.ch.is_ascii_alphanumeric() || self.ch == b'_' {
@@ -130,6 +158,18 @@ impl Lexer {
             b')' => Token::CloseParenthesis,
             b';' => Token::Semicolon,
             b',' => Token::Comma,
+            b'.' => {
+                if self.peek() != b'.' {
+                    return Err(LexerError::InvalidChar(self.ch as char, self.curr))
+                }
+                self.read_char();
+                if self.peek() != b'.' {
+                    return Err(LexerError::InvalidChar(self.ch as char, self.curr))
+                }
+                self.read_char();
+                Token::Ellipsis
+            },
+            b'"' => return Ok(Token::StringLiteral(self.read_string()?)),
             b'~' => Token::Tilde,
             b'+' => Token::Plus,
             b'-' => Token::Minus,
@@ -208,7 +248,14 @@ impl Lexer {
                     "float" => Token::Type(Type::Float),
                     "double" => Token::Type(Type::Double),
                     "char" => Token::Type(Type::Char),
+                    "short" => Token::Type(Type::Short),
+                    "long" => Token::Type(Type::Long),
                     "void" => Token::Type(Type::Void),
+                    "va_list" => Token::Type(Type::Pointer),
+                    "unsigned" => Token::Unsigned,
+                    "sizeof" => Token::Sizeof,
+                    "extern" => Token::Extern,
+                    "inline" => Token::Inline,
                     "return" => Token::Return,
                     "if" => Token::If,
                     "else" => Token::Else,
@@ -274,7 +321,10 @@ mod tests {
     #[test]
     fn lexer_expression() {
         let cases = [
-            ("(a + b) * c", vec![Token::OpenParenthesis, Token::Ident("a".into()), Token::Plus, Token::Ident("b".into()), Token::CloseParenthesis, Token::Asterisk, Token::Ident("c".into()), Token::EoF])
+            ("(a + b) * c", vec![Token::OpenParenthesis, Token::Ident("a".into()), Token::Plus, Token::Ident("b".into()), Token::CloseParenthesis, Token::Asterisk, Token::Ident("c".into()), Token::EoF]),
+            ("(unsigned long)x", vec![Token::OpenParenthesis, Token::Unsigned, Token::Type(Type::Long), Token::CloseParenthesis, Token::Ident("x".into()), Token::EoF]),
+            ("sizeof(short)", vec![Token::Sizeof, Token::OpenParenthesis, Token::Type(Type::Short), Token::CloseParenthesis, Token::EoF]),
+            ("printf(\"%d\\n\", ...)", vec![Token::Ident("printf".into()), Token::OpenParenthesis, Token::StringLiteral("%d\n".into()), Token::Comma, Token::Ellipsis, Token::CloseParenthesis, Token::EoF]),
         ];
         for (input, expected) in cases {
             let got = collect_tokens(input);
@@ -18,6 +18,16 @@ impl ConstValue {
             ConstValue::Void => format!(""), 
         }
     }
+
+    pub fn ty(&self) -> Type {
+        match self {
+            ConstValue::Int(_) => Type::Int,
+            ConstValue::Float(_) => Type::Float,
+            ConstValue::Double(_) => Type::Double,
+            ConstValue::Char(_) => Type::Char,
+            ConstValue::Void => Type::Void,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -26,7 +36,8 @@ pub enum ParserNode {
     Block(Vec<ParserNode>),
     
     // statement (declaration)
-    FuncDecl {ident: Box<ParserNode>, args: Vec<ParserNode>, block: Box<ParserNode>, ntype: Type},
+    FuncDecl {ident: Box<ParserNode>, args: Vec<ParserNode>, block: Box<ParserNode>, ntype: Type, variadic: bool, inline: bool},
+    FuncProto {ident: Box<ParserNode>, args: Vec<ParserNode>, ntype: Type, variadic: bool},
     Declare {ident: Box<ParserNode>, exp: Option<Box<ParserNode>>, ntype: Type},
 
     // statement
@@ -82,11 +93,19 @@ pub enum ParserNode {
     Neg {val: Box<ParserNode>},
     Complement {val: Box<ParserNode>},
     Not {val: Box<ParserNode>},
+    Cast {ntype: Type, from: Type, val: Box<ParserNode>},
+    SizeOf {ntype: Type, val: Option<Box<ParserNode>>},
+
+    // variadic builtins
+    VaStart {list: Box<ParserNode>, last: Box<ParserNode>},
+    VaArg {list: Box<ParserNode>, ntype: Type},
+    VaEnd {list: Box<ParserNode>},
 
     // factor
     FuncCall{ident: String, args: Vec<ParserNode>},
     Var{ ident: String, ntype: Type},
     Const(ConstValue),
+    StringLiteral(String),
     SubExp {val: Box<ParserNode>},
 }
 
@@ -108,25 +127,46 @@ impl ParserNode {
             }
 
             // statement
-            ParserNode::FuncDecl { ident, args, block, ntype } => {
-                let mut s = format!("{} {}(", ntype.to_string(), ident.to_string());
+            ParserNode::FuncDecl { ident, args, block, ntype, variadic, inline } => {
+                let mut s = format!("{}{} {}(", if *inline { "inline " } else { "" }, ntype.to_string(), ident.to_string());
                 for arg in args {
-                    s.push_str("int ");
+                    if let ParserNode::Var { ntype, .. } = arg {
+                        s.push_str(&ntype.to_string());
+                        s.push(' ');
+                    }
                     s.push_str(&arg.to_string());
                     s.push_str(", ");
                 }
-                if args.len() != 0 { s.pop(); s.pop();}
+                if *variadic { s.push_str("..., "); }
+                if args.len() != 0 || *variadic { s.pop(); s.pop();}
                 s.push_str(format!(") {{{}}}", block.to_string()).as_str());
                 s
                 
             }
+            ParserNode::FuncProto { ident, args, ntype, variadic } => {
+                let mut s = format!("{} {}(", ntype.to_string(), ident.to_string());
+                for arg in args {
+                    if let ParserNode::Var { ident, ntype } = arg {
+                        s.push_str(&ntype.to_string());
+                        if !ident.is_empty() {
+                            s.push(' ');
+                            s.push_str(ident);
+                        }
+                    }
+                    s.push_str(", ");
+                }
+                if *variadic { s.push_str("..., "); }
+                if !args.is_empty() || *variadic { s.pop(); s.pop(); }
+                s.push_str(");");
+                s
+            }
             ParserNode::Declare{ ident, exp, ntype } => {
                 match exp {
                     None => {
                         format!("{} {};\n", ntype.to_string(), ident.to_string())
                     },
                     Some(exp) => {
-                        format!("int {} = {};\n",ident.to_string(), exp.to_string())
+                        format!("{} {} = {};\n", ntype.to_string(), ident.to_string(), exp.to_string())
                     }
                 }
                 
@@ -238,6 +278,24 @@ impl ParserNode {
             ParserNode::Not { val } => {
                 format!("!{}", val.to_string())
             }
+            ParserNode::Cast { ntype, val, .. } => {
+                format!("({}){}", ntype.to_string(), val.to_string())
+            }
+            ParserNode::VaStart { list, last } => {
+                format!("va_start({}, {})", list.to_string(), last.to_string())
+            }
+            ParserNode::VaArg { list, ntype } => {
+                format!("va_arg({}, {})", list.to_string(), ntype.to_string())
+            }
+            ParserNode::VaEnd { list } => {
+                format!("va_end({})", list.to_string())
+            }
+            ParserNode::SizeOf { ntype, val } => {
+                match val {
+                    Some(val) => format!("sizeof {}", val.to_string()),
+                    None => format!("sizeof({})", ntype.to_string()),
+                }
+            }
 
             // factor
             ParserNode::FuncCall { ident, args } => {
@@ -257,6 +315,9 @@ impl ParserNode {
             ParserNode::Const(num) => {
                     format!("{}", num.to_string())
             }
+            ParserNode::StringLiteral(s) => {
+                format!("{:?}", s)
+            }
             ParserNode::SubExp { val } => {
                 format!("({})", val.to_string())
             }
@@ -77,8 +77,10 @@ impl Parser {
             Token::For => self.parse_for(),
             Token::While => self.parse_while(),
             Token::Return => self.parse_return(),
-            Token::Type(t) => {
-                self.read_token();
+            Token::Extern => self.parse_extern(),
+            Token::Inline => self.parse_inline(),
+            Token::Type(_) | Token::Unsigned => {
+                let t = self.parse_type()?;
                 if !matches!(self.next_token,Token::Ident(_)) {
                     return Err(ParserError::InvalidToken{ t:self.next_token.clone(), msg:String::from("parse_stmt > not a Ident")})
                 }
@@ -190,21 +192,55 @@ impl Parser {
     fn parse_func_decl(&mut self, ident: String, t: Type) -> Result<ParserNode, ParserError> {
         self.print_debug("func_decl");
         self.read_token();
-        let args = self.parse_func_args()?;
+        let (args, variadic) = self.parse_func_args()?;
         self.expect(Token::CloseParenthesis)?;
+        let ident_node = Box::from(ParserNode::Var{ident, ntype: t});
+        if self.next_token == Token::Semicolon {
+            self.read_token();
+            return Ok(ParserNode::FuncProto { ident: ident_node, args, ntype: t, variadic })
+        }
         self.expect(Token::OpenBracket)?;
         let block = self.parse_block()?;
-        let ident_node = Box::from(ParserNode::Var{ident, ntype: t});
-        Ok(ParserNode::FuncDecl { ident: ident_node, args, block: Box::from(block), ntype: t })
+        Ok(ParserNode::FuncDecl { ident: ident_node, args, block: Box::from(block), ntype: t, variadic, inline: false })
+    }
+    fn parse_extern(&mut self) -> Result<ParserNode, ParserError> {
+        self.print_debug("extern");
+        self.read_token();
+        if !self.is_type_start() {
+            return Err(ParserError::UnexpectedToken
+                { expected: Token::Type(Type::Void), found: self.next_token.clone(), line: self.lexer.line, pos: self.lexer.column })
+        }
+        match self.parse_stmt()? {
+            n @ ParserNode::FuncProto { .. } => Ok(n),
+            _ => Err(ParserError::InvalidToken { t: Token::Extern, msg: String::from("parse_extern > only function prototypes can be extern") }),
+        }
     }
-    fn parse_func_args(&mut self) -> Result<Vec<ParserNode>, ParserError> {
+    // a hint for the inliner, it is taken from the definition
+    fn parse_inline(&mut self) -> Result<ParserNode, ParserError> {
+        self.print_debug("inline");
+        self.read_token();
+        if !self.is_type_start() {
+            return Err(ParserError::UnexpectedToken
+                { expected: Token::Type(Type::Void), found: self.next_token.clone(), line: self.lexer.line, pos: self.lexer.column })
+        }
+        match self.parse_stmt()? {
+            ParserNode::FuncDecl { ident, args, block, ntype, variadic, .. } => Ok(ParserNode::FuncDecl { ident, args, block, ntype, variadic, inline: true }),
+            n @ ParserNode::FuncProto { .. } => Ok(n),
+            _ => Err(ParserError::InvalidToken { t: Token::Inline, msg: String::from("parse_inline > only functions can be inline") }),
+        }
+    }
+    fn parse_func_args(&mut self) -> Result<(Vec<ParserNode>, bool), ParserError> {
         self.print_debug("func_args");
         let mut args = Vec::new();
         while self.next_token != Token::CloseParenthesis {
-            let mut _ntype = Type::Void;
-            if let Token::Type(t) = &self.next_token {
-                _ntype = t.clone();
+            // '...' closes the parameter list after at least one named parameter
+            if self.next_token == Token::Ellipsis && !args.is_empty() {
                 self.read_token();
+                return Ok((args, true))
+            }
+            let mut _ntype = Type::Void;
+            if self.is_type_start() {
+                _ntype = self.parse_type()?;
             } else {
                 return Err(ParserError::UnexpectedToken
                     { expected: Token::Type(Type::Void), found: self.next_token.clone(), line: self.lexer.line, pos: self.lexer.column })
@@ -214,13 +250,57 @@ impl Parser {
                     args.push(ParserNode::Var{ident: name.clone(), ntype: _ntype}); 
                     self.read_token();
                 },
+                // '(void)' is an empty parameter list
+                Token::CloseParenthesis if _ntype == Type::Void && args.is_empty() => break,
+                // prototypes may leave parameters unnamed
+                Token::Comma | Token::CloseParenthesis => {
+                    args.push(ParserNode::Var{ident: String::new(), ntype: _ntype});
+                },
                 _ => return Err(ParserError::InvalidToken { t:self.next_token.clone(), msg: String::from("parse_func_args") }),
             }
             if self.next_token == Token::Comma {
                 self.read_token();
             }
         }
-        Ok(args)
+        Ok((args, false))
+    }
+
+    fn is_type_start(&self) -> bool {
+        matches!(self.next_token, Token::Type(_) | Token::Unsigned)
+    }
+    fn parse_type(&mut self) -> Result<Type, ParserError> {
+        self.print_debug("type");
+        let unsigned = self.next_token == Token::Unsigned;
+        if unsigned {
+            self.read_token();
+        }
+        let t = match self.next_token {
+            Token::Type(t) => {
+                self.read_token();
+                t
+            },
+            _ if unsigned => Type::Int,
+            _ => return Err(ParserError::UnexpectedToken
+                { expected: Token::Type(Type::Int), found: self.next_token.clone(), line: self.lexer.line, pos: self.lexer.column }),
+        };
+        // 'long int' and 'short int'
+        if matches!(t, Type::Long | Type::Short) && self.next_token == Token::Type(Type::Int) {
+            self.read_token();
+        }
+        let mut t = match t {
+            _ if !unsigned => t,
+            Type::Char => Type::UnsignedChar,
+            Type::Short => Type::UnsignedShort,
+            Type::Int => Type::UnsignedInt,
+            Type::Long => Type::UnsignedLong,
+            _ => return Err(ParserError::InvalidToken { t: Token::Type(t), msg: String::from("parse_type > not an integer type") }),
+        };
+        // pointers are opaque addresses, the pointed type is not kept
+        while self.next_token == Token::Asterisk {
+            self.read_token();
+            t = Type::Pointer;
+        }
+        Ok(t)
     }
 
     fn parse_expression(&mut self) -> Result<ParserNode, ParserError> {
@@ -497,6 +577,23 @@ impl Parser {
                 let node = self.parse_unary()?;
                 Ok(ParserNode::Neg { val: Box::from(node) })
             },
+            Token::Sizeof => {
+                self.read_token();
+                if self.next_token != Token::OpenParenthesis {
+                    let node = self.parse_unary()?;
+                    return Ok(ParserNode::SizeOf { ntype: Type::Void, val: Some(Box::from(node)) })
+                }
+                self.read_token();
+                if self.is_type_start() {
+                    let ntype = self.parse_type()?;
+                    self.expect(Token::CloseParenthesis)?;
+                    return Ok(ParserNode::SizeOf { ntype, val: None })
+                }
+                let exp = self.parse_logical_or()?;
+                self.expect(Token::CloseParenthesis)?;
+                let node = ParserNode::SubExp { val: Box::from(exp) };
+                Ok(ParserNode::SizeOf { ntype: Type::Void, val: Some(Box::from(node)) })
+            },
             _ => self.parse_factor(),
         }
     }
@@ -507,6 +604,9 @@ impl Parser {
             Token::Ident(ref id) => {
                 self.read_token();
                 if self.next_token == Token::OpenParenthesis {
+                    if id.starts_with("va_") {
+                        return self.parse_va_builtin(id.clone())
+                    }
                     return self.parse_func_call(id.clone())
                 }
                 Ok(ParserNode::Var{ident: id.clone(), ntype: Type::Void})
@@ -516,8 +616,25 @@ impl Parser {
                 self.read_token();
                 Ok(node)
             },
+            Token::StringLiteral(ref s) => {
+                let mut s = s.clone();
+                self.read_token();
+                // adjacent literals are concatenated
+                while let Token::StringLiteral(next) = &self.next_token {
+                    s.push_str(next);
+                    self.read_token();
+                }
+                Ok(ParserNode::StringLiteral(s))
+            },
             Token::OpenParenthesis => {
                 self.read_token();
+                // a type name after '(' makes it a cast, otherwise a sub expression
+                if self.is_type_start() {
+                    let ntype = self.parse_type()?;
+                    self.expect(Token::CloseParenthesis)?;
+                    let val = self.parse_unary()?;
+                    return Ok(ParserNode::Cast { ntype, from: Type::Void, val: Box::from(val) })
+                }
                 let exp = self.parse_logical_or()?;
                 self.expect(Token::CloseParenthesis)?;
                 Ok(ParserNode::SubExp { val: Box::from(exp) })
@@ -540,6 +657,33 @@ impl Parser {
 
         Ok(ParserNode::FuncCall { ident: id.clone(), args: args })
     }
+    fn parse_va_builtin(&mut self, id: String) -> Result<ParserNode, ParserError> {
+        self.print_debug("va_builtin");
+        let node = match id.as_str() {
+            "va_start" => {
+                self.read_token();
+                let list = self.parse_logical_or()?;
+                self.expect(Token::Comma)?;
+                let last = self.parse_logical_or()?;
+                ParserNode::VaStart { list: Box::from(list), last: Box::from(last) }
+            },
+            "va_arg" => {
+                self.read_token();
+                let list = self.parse_logical_or()?;
+                self.expect(Token::Comma)?;
+                let ntype = self.parse_type()?;
+                ParserNode::VaArg { list: Box::from(list), ntype }
+            },
+            "va_end" => {
+                self.read_token();
+                let list = self.parse_logical_or()?;
+                ParserNode::VaEnd { list: Box::from(list) }
+            },
+            _ => return self.parse_func_call(id),
+        };
+        self.expect(Token::CloseParenthesis)?;
+        Ok(node)
+    }
     pub fn read_token(&mut self) {
         if self.next_token != Token::EoF {
             self.next_token = self.lexer.next_token().expect("LexerError (read_token)");
@@ -587,6 +731,14 @@ mod tests {
             ("int x() {}", "int x() {}"),
             ("y(a, b)", "y(a,b)"),
             ("int z(int a) {}","int z(int a) {}"),
+            ("int p(int, double d);", "int p(int, double d);"),
+            ("extern void *malloc(unsigned long);", "void * malloc(unsigned long);"),
+            ("inline int sq(int a) {}", "inline int sq(int a) {}"),
+            ("int q(void);", "int q();"),
+            ("int printf(char *fmt, ...);", "int printf(void * fmt, ...);"),
+            ("int s(int n, ...) { va_list ap; va_start(ap, n); int x = va_arg(ap, int); va_end(ap); }",
+                "int s(int n, ...) {void * ap;\nva_start(ap, n)int x = va_arg(ap, int);\nva_end(ap)}"),
+            ("printf(\"a\" \"b\\n\", 1)", "printf(\"ab\\n\",1)"),
         ];
         
         for (input, expected) in cases {
@@ -606,6 +758,11 @@ mod tests {
             ("(a + b) * c", "(((a + b)) * c)"),
             ("!(a + b * ~c)", "!((a + (b * ~c)))"),
             ("!a + b * ~c", "(!a + (b * ~c))"),
+            ("(double)a + b", "((double)a + b)"),
+            ("(unsigned long)(a + b) * c", "((unsigned long)((a + b)) * c)"),
+            ("(a) + (char)-b", "((a) + (char)-b)"),
+            ("sizeof(long int) + sizeof a * 2", "(sizeof(long) + (sizeof a * 2))"),
+            ("sizeof (a + b)", "sizeof ((a + b))"),
         ];
         
         for (input, expected) in cases {
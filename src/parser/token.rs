@@ -7,6 +7,13 @@ pub enum Type {
     Float,
     Double,
     Char,
+    Short,
+    Long,
+    UnsignedChar,
+    UnsignedShort,
+    UnsignedInt,
+    UnsignedLong,
+    Pointer,
     Void,
 }
 impl Type {
@@ -16,6 +23,13 @@ impl Type {
             Type::Float => format!("float"),
             Type::Double => format!("double"),
             Type::Char => format!("char"),
+            Type::Short => format!("short"),
+            Type::Long => format!("long"),
+            Type::UnsignedChar => format!("unsigned char"),
+            Type::UnsignedShort => format!("unsigned short"),
+            Type::UnsignedInt => format!("unsigned int"),
+            Type::UnsignedLong => format!("unsigned long"),
+            Type::Pointer => format!("void *"),
             Type::Void => format!("void"),
         }
     }
@@ -25,12 +39,55 @@ impl Type {
             Type::Int => 4,
             Type::Float => 4,
             Type::Double => 8,
-            Type::Char => 4,
+            Type::Char => 1,
+            Type::Short => 2,
+            Type::Long => 8,
+            Type::UnsignedChar => 1,
+            Type::UnsignedShort => 2,
+            Type::UnsignedInt => 4,
+            Type::UnsignedLong => 8,
+            Type::Pointer => 8,
             Type::Void => 4,
         }
     }
     pub fn is_number(&self) -> bool {
-        *self != Type::Char
+        !matches!(self, Type::Void | Type::Pointer)
+    }
+    pub fn is_integer(&self) -> bool {
+        self.is_number() && !self.is_floating()
+    }
+    pub fn is_floating(&self) -> bool {
+        matches!(self, Type::Float | Type::Double)
+    }
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Type::Char | Type::Short | Type::Int | Type::Long | Type::Float | Type::Double)
+    }
+
+    // integer promotion: anything smaller than int becomes int
+    pub fn promote(&self) -> Type {
+        if self.is_integer() && self.size() < Type::Int.size() {
+            Type::Int
+        } else {
+            *self
+        }
+    }
+
+    // usual arithmetic conversions between two number types
+    pub fn common(&self, other: &Type) -> Type {
+        if *self == Type::Double || *other == Type::Double {
+            return Type::Double;
+        }
+        if *self == Type::Float || *other == Type::Float {
+            return Type::Float;
+        }
+        let (a, b) = (self.promote(), other.promote());
+        if a == b {
+            return a;
+        }
+        if a.size() != b.size() {
+            return if a.size() > b.size() { a } else { b };
+        }
+        if a.is_signed() { b } else { a }
     }
 }
 
@@ -40,6 +97,7 @@ pub enum Token {
     Type(Type),
     Ident(String),
     Const(ConstValue),
+    StringLiteral(String),
     // keywords
     If,
     Else,
@@ -47,6 +105,10 @@ pub enum Token {
     Assign,
     For,
     While,
+    Unsigned,
+    Sizeof,
+    Extern,
+    Inline,
     
     // condition 
     LogicalAnd,
@@ -87,6 +149,7 @@ pub enum Token {
     OpenParenthesis,
     CloseParenthesis,
     Comma,
+    Ellipsis,
     EoF,
     Invalid
     